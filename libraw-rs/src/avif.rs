@@ -0,0 +1,166 @@
+//! AVIF previews of the processed image or the embedded thumbnail
+//!
+//! Browsers ignore the EXIF orientation of AVIF images so the pixels are always stored upright and
+//! the EXIF is written with orientation 1. ravif doesn't embed ICC profiles and always tags the
+//! images as sRGB, so processed images need `output_color` 1 (sRGB).
+use image::ColorType;
+use img_parts::jpeg::Jpeg;
+use img_parts::{Bytes, ImageEXIF};
+use ravif::{BitDepth, Encoder, Img, MatrixCoefficients, PixelRange, RGB8};
+
+use crate::colorspace::OutputColorSpace;
+use crate::metadata::CameraMetadata;
+use crate::resize::ResizeSpec;
+use crate::{jpeg, LibrawError, Orientation, Processor};
+
+impl Processor {
+    /// Process the raw data into an avif, 16 bit images (`output_bps` 16) are encoded with 10 bits
+    ///
+    /// Fails with [LibrawError::UnsupportedOutputColor] unless `output_color` is sRGB and no
+    /// `output_profile` is set
+    ///
+    /// `quality` goes from 1 to 100 and `speed` from 1 (slowest, smallest files) to 10
+    pub fn to_avif(&mut self, quality: u8, speed: u8) -> Result<Vec<u8>, LibrawError> {
+        self.processed_avif(quality, speed, None)
+    }
+
+    /// Same as to_avif but resized according to `resize`
+    pub fn to_avif_resized(
+        &mut self,
+        quality: u8,
+        speed: u8,
+        resize: &ResizeSpec,
+    ) -> Result<Vec<u8>, LibrawError> {
+        self.processed_avif(quality, speed, Some(resize))
+    }
+
+    /// Transcode the embedded thumbnail into an avif keeping it's EXIF
+    pub fn get_avif(&mut self, quality: u8, speed: u8) -> Result<Vec<u8>, LibrawError> {
+        self.thumbnail_avif(quality, speed, None)
+    }
+
+    /// Same as get_avif but resized according to `resize`
+    pub fn get_avif_resized(
+        &mut self,
+        quality: u8,
+        speed: u8,
+        resize: &ResizeSpec,
+    ) -> Result<Vec<u8>, LibrawError> {
+        self.thumbnail_avif(quality, speed, Some(resize))
+    }
+
+    fn processed_avif(
+        &mut self,
+        quality: u8,
+        speed: u8,
+        resize: Option<&ResizeSpec>,
+    ) -> Result<Vec<u8>, LibrawError> {
+        let params = &self.inner().params;
+        let output_color = params.output_color;
+        // An LCMS output_profile replaces output_color
+        if output_color != OutputColorSpace::Srgb as i32 || !params.output_profile.is_null() {
+            return Err(LibrawError::UnsupportedOutputColor(output_color));
+        }
+        let (pixels, width, height, colortype) = self.processed_pixels(resize)?;
+        let exif = jpeg::exif_with_orientation(
+            None,
+            Orientation::NONE,
+            Some(&CameraMetadata::from(&*self)),
+        );
+        encode(&pixels, width, height, colortype, quality, speed, exif)
+    }
+
+    fn thumbnail_avif(
+        &mut self,
+        quality: u8,
+        speed: u8,
+        resize: Option<&ResizeSpec>,
+    ) -> Result<Vec<u8>, LibrawError> {
+        let (pixels, width, height, orientation) = self.thumbnail_pixels(resize)?;
+        let exif = match self.thumbnail_jpeg() {
+            Some(thumbnail) => Jpeg::from_bytes(Bytes::copy_from_slice(thumbnail))?.exif(),
+            None => None,
+        };
+        let exif = jpeg::exif_with_orientation(
+            exif.as_deref(),
+            Orientation::NONE,
+            Some(&CameraMetadata::from(&*self)),
+        );
+        let (pixels, width, height) =
+            orientation.apply_to_pixels(&pixels, width as usize, height as usize, 3);
+        encode(
+            &pixels,
+            width as u32,
+            height as u32,
+            ColorType::Rgb8,
+            quality,
+            speed,
+            exif,
+        )
+    }
+}
+
+fn encode(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    colortype: ColorType,
+    quality: u8,
+    speed: u8,
+    exif: Vec<u8>,
+) -> Result<Vec<u8>, LibrawError> {
+    let encoder = Encoder::new()
+        .with_quality(quality.into())
+        .with_speed(speed)
+        .with_exif(exif);
+    let (width, height) = (width as usize, height as usize);
+    let encoded = match colortype {
+        ColorType::L8 | ColorType::Rgb8 => {
+            let rgb = match colortype {
+                ColorType::L8 => pixels.iter().map(|&v| RGB8::new(v, v, v)).collect(),
+                _ => pixels
+                    .chunks_exact(3)
+                    .map(|p| RGB8::new(p[0], p[1], p[2]))
+                    .collect::<Vec<_>>(),
+            };
+            encoder.encode_rgb(Img::new(&rgb[..], width, height))
+        }
+        ColorType::L16 | ColorType::Rgb16 => {
+            let channels = match colortype {
+                ColorType::L16 => 1,
+                _ => 3,
+            };
+            let samples = pixels
+                .chunks_exact(2)
+                .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+                .collect::<Vec<_>>();
+            let planes = samples.chunks_exact(channels).map(|p| match p {
+                [v] => ycbcr_10bit(*v, *v, *v),
+                p => ycbcr_10bit(p[0], p[1], p[2]),
+            });
+            encoder
+                .with_bit_depth(BitDepth::Ten)
+                .encode_raw_planes_10_bit(
+                    width,
+                    height,
+                    planes,
+                    None::<[u16; 0]>,
+                    PixelRange::Full,
+                    MatrixCoefficients::BT601,
+                )
+        }
+        color => return Err(LibrawError::InvalidColor(color.bits_per_pixel())),
+    }
+    .map_err(|e| LibrawError::CustomError(e.into()))?;
+    Ok(encoded.avif_file)
+}
+
+/// Full range BT.601 Y'CbCr with 10 bits from 16 bit rgb
+fn ycbcr_10bit(r: u16, g: u16, b: u16) -> [u16; 3] {
+    let scale = 1023.0 / 65535.0;
+    let (r, g, b) = (r as f32 * scale, g as f32 * scale, b as f32 * scale);
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = (b - y) * 0.5 / (1.0 - 0.114) + 512.0;
+    let cr = (r - y) * 0.5 / (1.0 - 0.299) + 512.0;
+    [y, cb, cr].map(|v| v.round().clamp(0.0, 1023.0) as u16)
+}
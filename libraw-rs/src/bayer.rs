@@ -0,0 +1,210 @@
+//! Open headerless bayer buffers (machine vision sensors, Raspberry Pi cameras) with
+//! `libraw_open_bayer`
+//!
+//! libraw picks the unpacker from the bits per pixel of the buffer length, so the buffer is passed
+//! with exactly the length [BayerPacking] implies. Rows are as long as libraw's unpackers stride
+//! them, widths for which that length would select another unpacker are rejected. The processor
+//! keeps the buffer until the next `open*` / `recycle`, after `open_bayer` the usual `unpack` and
+//! `dcraw_process` work.
+use crate::{LibrawError, Processor};
+
+/// Arrangement of the top left 2x2 pixels of the visible area
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(all(windows, target_env = "msvc"), repr(i32))]
+#[cfg_attr(all(windows, target_env = "gnu"), repr(u32))]
+#[cfg_attr(unix, repr(u32))]
+pub enum BayerPattern {
+    Rggb = sys::LibRaw_openbayer_patterns_LIBRAW_OPENBAYER_RGGB,
+    Bggr = sys::LibRaw_openbayer_patterns_LIBRAW_OPENBAYER_BGGR,
+    Grbg = sys::LibRaw_openbayer_patterns_LIBRAW_OPENBAYER_GRBG,
+    Gbrg = sys::LibRaw_openbayer_patterns_LIBRAW_OPENBAYER_GBRG,
+}
+
+/// How the pixels are stored in the buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BayerPacking {
+    /// One byte per pixel
+    Unpacked8,
+    /// Two bytes per pixel, the value in the low [BayerLayout::bits] bits
+    Unpacked16 { big_endian: bool },
+    /// MIPI RAW10, 4 pixels in 5 bytes with the low bits in the last byte, rows padded to 8 bytes
+    Mipi10,
+    /// Android RAW10 loose, 6 pixels in 8 bytes
+    Loose10,
+    /// A big endian bit stream of 10 bit values, rows padded to an even number of bytes
+    Packed10,
+    /// A big endian bit stream of 12 bit values, rows padded to an even number of bytes. MIPI
+    /// RAW12 has to be unpacked first
+    Packed12,
+}
+
+impl BayerPacking {
+    /// Bits each pixel takes in the buffer
+    pub fn container_bits(&self) -> u8 {
+        match self {
+            Self::Unpacked8 => 8,
+            Self::Unpacked16 { .. } => 16,
+            Self::Mipi10 | Self::Loose10 | Self::Packed10 => 10,
+            Self::Packed12 => 12,
+        }
+    }
+
+    /// Bytes libraw reads for each row of `width` pixels
+    pub fn row_stride(&self, width: usize) -> usize {
+        match self {
+            Self::Unpacked8 => width,
+            Self::Unpacked16 { .. } => width * 2,
+            // android_tight_load_raw
+            Self::Mipi10 => (width * 5 + 31) / 32 * 8,
+            // android_loose_load_raw
+            Self::Loose10 => (width + 5) / 6 * 8,
+            // packed_load_raw with load_flags 128
+            Self::Packed10 | Self::Packed12 => {
+                let bytes = width * usize::from(self.container_bits()) / 8;
+                bytes + (bytes & 1)
+            }
+        }
+    }
+
+    /// Length of a buffer of `width` x `height` pixels
+    pub fn buffer_len(&self, width: usize, height: usize) -> usize {
+        self.row_stride(width) * height
+    }
+
+    /// Whether libraw picks the unpacker for this packing from a buffer of [Self::buffer_len]
+    ///
+    /// `open_bayer` selects it by the bits per pixel (rounded down) and tells loose from tight
+    /// RAW10 by the bytes per row, so the row padding of some widths selects the wrong one
+    pub fn supports(&self, width: usize, height: usize) -> bool {
+        if width == 0 || height == 0 {
+            return false;
+        }
+        let len = self.buffer_len(width, height);
+        let bpp = len * 8 / (width * height);
+        let loose = len / height * 3 >= width * 4;
+        // packed_load_raw can't skip a fraction of a byte at the end of a row
+        let whole_bytes = width * usize::from(self.container_bits()) % 8 == 0;
+        match self {
+            Self::Unpacked8 => bpp == 8,
+            Self::Unpacked16 { .. } => bpp == 16,
+            Self::Loose10 => bpp == 10 && loose,
+            Self::Mipi10 => bpp == 10 && !loose,
+            Self::Packed10 => bpp == 10 && !loose && whole_bytes,
+            Self::Packed12 => bpp == 12 && whole_bytes,
+        }
+    }
+
+    /// `load_flags` selecting the unpacker for the bits per pixel
+    fn load_flags(&self, bits: u8) -> u32 {
+        match *self {
+            Self::Unpacked16 { big_endian } => (u32::from(16 - bits) << 4) | big_endian as u32,
+            Self::Mipi10 => 1,
+            _ => 0,
+        }
+    }
+}
+
+/// Pixels excluded from the visible area on each side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BayerMargins {
+    pub left: u16,
+    pub top: u16,
+    pub right: u16,
+    pub bottom: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BayerLayout {
+    pub raw_width: u16,
+    pub raw_height: u16,
+    pub margins: BayerMargins,
+    pub pattern: BayerPattern,
+    /// Significant bits of each pixel, less than 16 only for [BayerPacking::Unpacked16]
+    pub bits: u8,
+    pub unpacking: BayerPacking,
+    /// Or'ed into the `load_flags` derived from `unpacking`
+    pub otherflags: u32,
+    pub black_level: u32,
+}
+
+impl BayerLayout {
+    pub fn new(
+        raw_width: u16,
+        raw_height: u16,
+        pattern: BayerPattern,
+        unpacking: BayerPacking,
+    ) -> Self {
+        Self {
+            raw_width,
+            raw_height,
+            margins: BayerMargins::default(),
+            pattern,
+            bits: unpacking.container_bits(),
+            unpacking,
+            otherflags: 0,
+            black_level: 0,
+        }
+    }
+
+    /// Length of the buffer libraw reads
+    pub fn buffer_len(&self) -> usize {
+        self.unpacking
+            .buffer_len(self.raw_width.into(), self.raw_height.into())
+    }
+}
+
+impl Processor {
+    /// Open a headerless bayer buffer, which is kept alive until the next open or recycle
+    pub fn open_bayer(
+        &mut self,
+        data: impl Into<Vec<u8>>,
+        layout: &BayerLayout,
+    ) -> Result<(), LibrawError> {
+        let container = layout.unpacking.container_bits();
+        let bits_valid = match layout.unpacking {
+            BayerPacking::Unpacked16 { .. } => (1..=16).contains(&layout.bits),
+            _ => layout.bits == container,
+        };
+        if !bits_valid {
+            return Err(LibrawError::InvalidColor(layout.bits.into()));
+        }
+        let margins = &layout.margins;
+        let covered = |a: u16, b: u16, size: u16| u32::from(a) + u32::from(b) >= u32::from(size);
+        if covered(margins.left, margins.right, layout.raw_width)
+            || covered(margins.top, margins.bottom, layout.raw_height)
+            || !layout
+                .unpacking
+                .supports(layout.raw_width.into(), layout.raw_height.into())
+        {
+            return Err(LibrawError::UnsupportedImageFormat);
+        }
+        let len = layout.buffer_len();
+        let mut data = data.into();
+        if data.len() < len {
+            return Err(LibrawError::UnsupportedImageFormat);
+        }
+
+        self.recycle()?;
+        let ptr = data.as_mut_ptr();
+        // Moving the vec doesn't move the heap allocation libraw reads from
+        self.bayer_data = Some(data);
+        LibrawError::check(unsafe {
+            sys::libraw_open_bayer(
+                self.inner.as_ptr(),
+                ptr,
+                len as _,
+                layout.raw_width,
+                layout.raw_height,
+                margins.left,
+                margins.top,
+                margins.right,
+                margins.bottom,
+                0,
+                layout.pattern as u8,
+                0,
+                layout.unpacking.load_flags(layout.bits) | layout.otherflags,
+                layout.black_level,
+            )
+        })
+    }
+}
@@ -0,0 +1,307 @@
+//! Typed access to the camera color matrices and the DNG color math
+//!
+//! Follows "Mapping Camera Color Space to CIE XYZ Space" from the DNG specification: the
+//! ColorMatrix, CameraCalibration and ForwardMatrix of the two calibration illuminants are
+//! interpolated linearly in inverse color temperature (mired) and combined with the
+//! AnalogBalance and AsShotNeutral to map camera values to D50 adapted XYZ.
+//!
+//! Only three color cameras are handled, the fourth row / column of libraw's matrices is ignored.
+use crate::colorspace::{OutputColorSpace, D50, D65};
+use crate::matrix::Matrix3;
+use crate::white_balance::xy_to_temperature_tint;
+use crate::Processor;
+
+/// The EXIF LightSource values used for CalibrationIlluminant
+pub(crate) const STANDARD_LIGHT_A: u16 = 17;
+pub(crate) const D65_ILLUMINANT: u16 = 21;
+const D50_ILLUMINANT: u16 = 23;
+
+/// One of the (up to two) DNG calibrations
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DngCalibration {
+    /// EXIF LightSource of the CalibrationIlluminant tag
+    pub illuminant: u16,
+    /// Correlated color temperature of the illuminant in kelvin
+    pub temperature: f64,
+    /// ColorMatrix, XYZ to camera
+    pub color_matrix: Matrix3,
+    /// CameraCalibration, identity when the file doesn't have one
+    pub camera_calibration: Matrix3,
+    /// ForwardMatrix, white balanced camera to D50 XYZ
+    pub forward_matrix: Option<Matrix3>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorCalibration {
+    /// The DNG calibrations sorted by temperature, empty for files without a ColorMatrix
+    pub dng: Vec<DngCalibration>,
+    /// libraw's XYZ (D65) to camera matrix from its camera tables (`cam_xyz`)
+    pub cam_xyz: Matrix3,
+    /// libraw's white balanced camera to linear sRGB matrix (`rgb_cam`)
+    pub rgb_cam: Matrix3,
+    /// AnalogBalance, 1 for every channel when the file doesn't have one
+    pub analog_balance: [f64; 3],
+    /// AsShotNeutral, or derived from the camera white balance multipliers
+    pub as_shot_neutral: Option<[f64; 3]>,
+}
+
+impl From<&sys::libraw_colordata_t> for ColorCalibration {
+    fn from(color: &sys::libraw_colordata_t) -> Self {
+        let parsed = |fields: u32, flag: u32| fields & flag != 0;
+        let matrix = |m: &[[f32; 3]]| Matrix3::from_f32([m[0], m[1], m[2]]);
+        let square =
+            |m: &[[f32; 4]]| Matrix3::from_f32([0, 1, 2].map(|r| [m[r][0], m[r][1], m[r][2]]));
+
+        let mut dng: Vec<_> = color
+            .dng_color
+            .iter()
+            .zip([STANDARD_LIGHT_A, D65_ILLUMINANT])
+            .filter(|(dng, _)| {
+                parsed(
+                    dng.parsedfields,
+                    sys::LibRaw_dngfields_marks_LIBRAW_DNGFM_COLORMATRIX,
+                )
+            })
+            .map(|(dng, default)| {
+                let illuminant = match dng.illuminant {
+                    0 => default,
+                    v => v,
+                };
+                DngCalibration {
+                    illuminant,
+                    temperature: light_source_temperature(illuminant)
+                        .or_else(|| light_source_temperature(default))
+                        .unwrap_or(6500.0),
+                    color_matrix: matrix(&dng.colormatrix),
+                    camera_calibration: match parsed(
+                        dng.parsedfields,
+                        sys::LibRaw_dngfields_marks_LIBRAW_DNGFM_CALIBRATION,
+                    ) {
+                        true => square(&dng.calibration),
+                        false => Matrix3::IDENTITY,
+                    },
+                    forward_matrix: parsed(
+                        dng.parsedfields,
+                        sys::LibRaw_dngfields_marks_LIBRAW_DNGFM_FORWARDMATRIX,
+                    )
+                    .then(|| square(&dng.forwardmatrix))
+                    .filter(|m| m.determinant().abs() > f64::EPSILON),
+                }
+            })
+            .collect();
+        dng.sort_by(|a, b| a.temperature.total_cmp(&b.temperature));
+
+        let levels = &color.dng_levels;
+        let analog_balance = match parsed(
+            levels.parsedfields,
+            sys::LibRaw_dngfields_marks_LIBRAW_DNGFM_ANALOGBALANCE,
+        ) {
+            true => [0, 1, 2].map(|c| match levels.analogbalance[c] {
+                v if v > 0.0 => v as f64,
+                _ => 1.0,
+            }),
+            false => [1.0; 3],
+        };
+        let neutral = levels.asshotneutral;
+        let as_shot_neutral = match parsed(
+            levels.parsedfields,
+            sys::LibRaw_dngfields_marks_LIBRAW_DNGFM_ASSHOTNEUTRAL,
+        ) && neutral[..3].iter().all(|v| *v > 0.0)
+        {
+            true => Some([0, 1, 2].map(|c| neutral[c] as f64)),
+            false => {
+                let mul = color.cam_mul;
+                mul[..3]
+                    .iter()
+                    .all(|v| *v > 0.0)
+                    .then(|| [0, 1, 2].map(|c| (mul[1] / mul[c]) as f64))
+            }
+        };
+
+        Self {
+            dng,
+            cam_xyz: matrix(&color.cam_xyz),
+            rgb_cam: square(&color.rgb_cam),
+            analog_balance,
+            as_shot_neutral,
+        }
+    }
+}
+
+impl ColorCalibration {
+    /// Weight of the lower temperature calibration for a white balance of `temperature` kelvin
+    ///
+    /// Interpolated in inverse temperature and clamped to the two calibrations, 1.0 with only
+    /// one calibration
+    pub fn interpolation_weight(&self, temperature: f64) -> f64 {
+        match self.dng.as_slice() {
+            [low, high] if high.temperature > low.temperature => {
+                let t = temperature.clamp(low.temperature, high.temperature);
+                (1.0 / t - 1.0 / high.temperature)
+                    / (1.0 / low.temperature - 1.0 / high.temperature)
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// The XYZ to camera matrix for `temperature`, libraw's `cam_xyz` for non DNG files
+    pub fn color_matrix(&self, temperature: f64) -> Matrix3 {
+        self.interpolate(temperature, |dng| Some(dng.color_matrix))
+            .unwrap_or(self.cam_xyz)
+    }
+
+    /// The CameraCalibration matrix for `temperature`
+    pub fn camera_calibration(&self, temperature: f64) -> Matrix3 {
+        self.interpolate(temperature, |dng| Some(dng.camera_calibration))
+            .unwrap_or(Matrix3::IDENTITY)
+    }
+
+    /// The ForwardMatrix for `temperature`, only when every calibration has one
+    pub fn forward_matrix(&self, temperature: f64) -> Option<Matrix3> {
+        self.interpolate(temperature, |dng| dng.forward_matrix)
+    }
+
+    /// AB * CC * CM, XYZ to (not white balanced) camera values
+    pub fn xyz_to_camera(&self, temperature: f64) -> Matrix3 {
+        Matrix3::diagonal(self.analog_balance)
+            * self.camera_calibration(temperature)
+            * self.color_matrix(temperature)
+    }
+
+    /// The camera values of a neutral, AsShotNeutral when present
+    pub fn camera_neutral(&self) -> [f64; 3] {
+        self.as_shot_neutral.unwrap_or([1.0; 3])
+    }
+
+    /// Inverse(AB * CC) * CameraNeutral, the neutral in the calibrated camera space
+    pub fn reference_neutral(&self, temperature: f64) -> Option<[f64; 3]> {
+        let balance = Matrix3::diagonal(self.analog_balance) * self.camera_calibration(temperature);
+        Some(balance.inverse()?.mul_vec(self.camera_neutral()))
+    }
+
+    /// Camera values to D50 adapted XYZ for a white balance of `temperature` kelvin
+    ///
+    /// Uses the ForwardMatrix when available, otherwise the inverse of [Self::xyz_to_camera]
+    /// followed by a Bradford adaptation from the camera neutral to D50. The camera neutral is
+    /// mapped to D50 with Y = 1
+    pub fn camera_to_xyz_d50(&self, temperature: f64) -> Option<Matrix3> {
+        let balance = Matrix3::diagonal(self.analog_balance) * self.camera_calibration(temperature);
+        if let Some(forward) = self.forward_matrix(temperature) {
+            let reference = self.reference_neutral(temperature)?;
+            if reference.iter().any(|v| *v <= 0.0) {
+                return None;
+            }
+            let d = Matrix3::diagonal(reference.map(|v| 1.0 / v));
+            return Some(forward * d * balance.inverse()?);
+        }
+        let camera_to_xyz = self.xyz_to_camera(temperature).inverse()?;
+        let white = camera_to_xyz.mul_vec(self.camera_neutral());
+        if white[1] <= 0.0 {
+            return None;
+        }
+        let scale = Matrix3::diagonal([1.0 / white[1]; 3]);
+        Some(Matrix3::bradford(white.map(|v| v / white[1]), D50) * scale * camera_to_xyz)
+    }
+
+    /// Like [Self::camera_to_xyz_d50] but for white balanced values (camera values divided by
+    /// the camera neutral) such as libraw's output with `output_color` 0
+    pub fn white_balanced_to_xyz_d50(&self, temperature: f64) -> Option<Matrix3> {
+        Some(self.camera_to_xyz_d50(temperature)? * Matrix3::diagonal(self.camera_neutral()))
+    }
+
+    /// White balanced camera values to D50 XYZ using libraw's `rgb_cam`
+    pub fn rgb_cam_to_xyz_d50(&self) -> Option<Matrix3> {
+        if self.rgb_cam.determinant().abs() < f64::EPSILON {
+            return None;
+        }
+        Some(OutputColorSpace::Srgb.to_xyz_d50()? * self.rgb_cam)
+    }
+
+    /// The white balance multipliers (green = 1) matching the camera neutral
+    pub fn white_balance_multipliers(&self) -> [f64; 3] {
+        let neutral = self.camera_neutral();
+        neutral.map(|v| match v > 0.0 {
+            true => neutral[1] / v,
+            false => 1.0,
+        })
+    }
+
+    /// Temperature and tint (see [crate::white_balance]) of a camera neutral
+    ///
+    /// Iterates the DNG neutral to xy search since the color matrix depends on the temperature
+    pub fn temperature_tint(&self, neutral: [f64; 3]) -> Option<(f64, f64)> {
+        let mut result = xy_to_temperature_tint(xyz_to_xy(D50));
+        for _ in 0..30 {
+            let xyz = self.xyz_to_camera(result.0).inverse()?.mul_vec(neutral);
+            let next = xy_to_temperature_tint(xyz_to_xy(xyz));
+            if (next.0 - result.0).abs() < 0.1 {
+                return Some(next);
+            }
+            result = next;
+        }
+        Some(result)
+    }
+
+    /// Correlated color temperature of the AsShotNeutral
+    pub fn as_shot_temperature(&self) -> Option<f64> {
+        Some(self.temperature_tint(self.as_shot_neutral?)?.0)
+    }
+
+    fn interpolate(
+        &self,
+        temperature: f64,
+        matrix: impl Fn(&DngCalibration) -> Option<Matrix3>,
+    ) -> Option<Matrix3> {
+        match self.dng.as_slice() {
+            [] => None,
+            [only] => matrix(only),
+            [low, high, ..] => {
+                let (a, b) = (matrix(low)?, matrix(high)?);
+                let w = self.interpolation_weight(temperature);
+                let mut out = [[0.0; 3]; 3];
+                for (r, row) in out.iter_mut().enumerate() {
+                    for (c, v) in row.iter_mut().enumerate() {
+                        *v = w * a[r][c] + (1.0 - w) * b[r][c];
+                    }
+                }
+                Some(Matrix3(out))
+            }
+        }
+    }
+}
+
+impl Processor {
+    /// The camera color matrices as typed matrices together with the DNG color math
+    pub fn color_calibration(&self) -> ColorCalibration {
+        ColorCalibration::from(self.color())
+    }
+}
+
+/// Correlated color temperature of an EXIF LightSource, the same values as the DNG SDK
+pub fn light_source_temperature(light_source: u16) -> Option<f64> {
+    Some(match light_source {
+        STANDARD_LIGHT_A | 3 => 2850.0,
+        24 => 3200.0,
+        D50_ILLUMINANT => 5000.0,
+        1 | 4 | 9 | 18 | 20 => 5500.0,
+        D65_ILLUMINANT | 10 | 19 => 6500.0,
+        11 | 22 => 7500.0,
+        12 => (5700.0 + 7100.0) * 0.5,
+        13 => (4600.0 + 5500.0) * 0.5,
+        2 | 14 => (3800.0 + 4500.0) * 0.5,
+        15 => (3250.0 + 3800.0) * 0.5,
+        16 => (2600.0 + 3250.0) * 0.5,
+        _ => return None,
+    })
+}
+
+fn xyz_to_xy(xyz: [f64; 3]) -> (f64, f64) {
+    let sum: f64 = xyz.iter().sum();
+    match sum.abs() < f64::EPSILON {
+        true => (
+            D65[0] / D65.iter().sum::<f64>(),
+            1.0 / D65.iter().sum::<f64>(),
+        ),
+        false => (xyz[0] / sum, xyz[1] / sum),
+    }
+}
@@ -0,0 +1,131 @@
+//! The cameras supported by the linked libraw (`libraw_cameraList`)
+//!
+//! libraw stores each camera as a single "Make Model" string, sometimes followed by a note in
+//! parentheses. Matching ignores case, punctuation, spacing, the note and the company names
+//! around the exif make ("NIKON CORPORATION", "LEICA CAMERA AG", "EASTMAN KODAK COMPANY").
+use core::fmt;
+use std::ffi::CStr;
+
+/// Makes with more than one word, every other make is the first word of the entry
+const MULTI_WORD_MAKES: &[&str] = &[
+    "Digital Bolex",
+    "Konica Minolta",
+    "OM Digital Solutions",
+    "Phase One",
+    "Photo Control",
+    "ST Micro",
+];
+
+/// Words at the end of the exif make that aren't part of the name libraw uses
+const COMPANY_SUFFIXES: &[&str] = &[
+    "ag",
+    "camera",
+    "co",
+    "company",
+    "corp",
+    "corporation",
+    "imaging",
+    "inc",
+    "ltd",
+    "optical",
+];
+
+/// Words at the start of the exif make that aren't part of the name libraw uses
+const COMPANY_PREFIXES: &[&str] = &["eastman"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CameraModel {
+    pub make: &'static str,
+    /// The rest of the entry, including libraw's note if there is one
+    pub model: &'static str,
+}
+
+impl fmt::Display for CameraModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.make, self.model)
+    }
+}
+
+impl CameraModel {
+    /// Split an entry of libraw's camera list into make and model
+    pub fn parse(entry: &'static str) -> Self {
+        let make_len = MULTI_WORD_MAKES
+            .iter()
+            .find(|make| {
+                entry.len() > make.len()
+                    && entry.is_char_boundary(make.len())
+                    && entry[..make.len()].eq_ignore_ascii_case(make)
+                    && entry[make.len()..].starts_with(' ')
+            })
+            .map_or_else(|| entry.find(' ').unwrap_or(entry.len()), |make| make.len());
+        Self {
+            make: &entry[..make_len],
+            model: entry[make_len..].trim_start(),
+        }
+    }
+
+    /// Whether this is the camera with the given exif make and model
+    pub fn matches(&self, make: &str, model: &str) -> bool {
+        let make = normalize_make(make);
+        if make != normalize_make(self.make) {
+            return false;
+        }
+        // Some bodies repeat the make in the model ("Canon EOS R5")
+        let model = normalize(model);
+        let model = match model.strip_prefix(&make) {
+            Some(rest) if rest.starts_with(' ') => rest.trim_start().to_owned(),
+            _ => model,
+        };
+        let listed = match self.model.find(" (") {
+            Some(note) => &self.model[..note],
+            None => self.model,
+        };
+        // Exif models often drop the spaces libraw has ("E-M1MarkII" for "E-M1 Mark II")
+        model.replace(' ', "") == normalize(listed).replace(' ', "")
+    }
+}
+
+/// Every camera in the linked libraw's list, in libraw's order
+pub fn supported_cameras() -> impl Iterator<Item = CameraModel> {
+    let list = unsafe { sys::libraw_cameraList() };
+    let count = match list.is_null() {
+        true => 0,
+        false => unsafe { sys::libraw_cameraCount() }.max(0) as usize,
+    };
+    (0..count)
+        .map(move |index| unsafe { *list.add(index) })
+        .take_while(|entry| !entry.is_null())
+        .filter_map(|entry| {
+            let entry: &'static CStr = unsafe { CStr::from_ptr(entry) };
+            entry.to_str().ok()
+        })
+        .map(CameraModel::parse)
+}
+
+/// Whether the linked libraw lists the camera with the given exif make and model
+pub fn is_supported(make: &str, model: &str) -> bool {
+    supported_cameras().any(|camera| camera.matches(make, model))
+}
+
+/// Lowercase alphanumeric words separated by a single space
+fn normalize(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_ascii_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn normalize_make(make: &str) -> String {
+    let make = normalize(make);
+    let words: Vec<_> = make.split(' ').collect();
+    let start = words
+        .iter()
+        .position(|word| !COMPANY_PREFIXES.contains(word))
+        .unwrap_or(0);
+    let end = words
+        .iter()
+        .rposition(|word| !COMPANY_SUFFIXES.contains(word))
+        .map_or(words.len(), |last| last + 1);
+    words[start.min(end)..end].join(" ")
+}
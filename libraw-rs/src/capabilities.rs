@@ -0,0 +1,135 @@
+//! What the linked libraw was built with and which decoder handles a file
+use core::fmt;
+use std::ffi::CStr;
+#[cfg(feature = "rawspeed")]
+use std::path::Path;
+
+use crate::{LibrawError, Processor};
+
+bitflags::bitflags! {
+    /// Optional parts compiled into libraw (`libraw_capabilities`)
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Capabilities: u32 {
+        // The sys enums are a c_int with msvc and a c_uint everywhere else
+        const RAWSPEED = sys::LibRaw_runtime_capabilities_LIBRAW_CAPS_RAWSPEED as u32;
+        const DNGSDK = sys::LibRaw_runtime_capabilities_LIBRAW_CAPS_DNGSDK as u32;
+        const GPRSDK = sys::LibRaw_runtime_capabilities_LIBRAW_CAPS_GPRSDK as u32;
+        const UNICODEPATHS = sys::LibRaw_runtime_capabilities_LIBRAW_CAPS_UNICODEPATHS as u32;
+        const X3FTOOLS = sys::LibRaw_runtime_capabilities_LIBRAW_CAPS_X3FTOOLS as u32;
+        const RPI6BY9 = sys::LibRaw_runtime_capabilities_LIBRAW_CAPS_RPI6BY9 as u32;
+        const ZLIB = sys::LibRaw_runtime_capabilities_LIBRAW_CAPS_ZLIB as u32;
+        const JPEG = sys::LibRaw_runtime_capabilities_LIBRAW_CAPS_JPEG as u32;
+        const RAWSPEED3 = sys::LibRaw_runtime_capabilities_LIBRAW_CAPS_RAWSPEED3 as u32;
+        const RAWSPEED_BITS = sys::LibRaw_runtime_capabilities_LIBRAW_CAPS_RAWSPEED_BITS as u32;
+        /// Not reported by libraw, set when libraw_r is built with the `lcms` feature (which
+        /// can't be combined with a system LibRaw)
+        const LCMS = 1 << 30;
+        /// Not reported by libraw, set when libraw_r is built with the `jasper` feature (which
+        /// can't be combined with a system LibRaw)
+        const JASPER = 1 << 31;
+    }
+}
+
+impl Capabilities {
+    /// The capabilities of the linked libraw
+    pub fn current() -> Self {
+        let mut capabilities = Self::from_bits_retain(unsafe { sys::libraw_capabilities() });
+        capabilities.set(Self::LCMS, cfg!(feature = "lcms"));
+        capabilities.set(Self::JASPER, cfg!(feature = "jasper"));
+        capabilities
+    }
+
+    /// Fail with the missing capabilities unless the linked libraw has all of `needed`
+    ///
+    /// Meant to be called on startup, e.g. with `ZLIB | JPEG` when deflate compressed DNGs and
+    /// lossy DNGs have to be decoded
+    pub fn require(needed: Self) -> Result<Self, LibrawError> {
+        let current = Self::current();
+        match needed - current {
+            missing if missing.is_empty() => Ok(current),
+            missing => Err(LibrawError::MissingCapabilities(missing)),
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// Properties of the raw decoder (`LibRaw_decoder_flags`)
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct DecoderFlags: u32 {
+        const HASCURVE = sys::LibRaw_decoder_flags_LIBRAW_DECODER_HASCURVE as u32;
+        const SONYARW2 = sys::LibRaw_decoder_flags_LIBRAW_DECODER_SONYARW2 as u32;
+        const TRYRAWSPEED = sys::LibRaw_decoder_flags_LIBRAW_DECODER_TRYRAWSPEED as u32;
+        const OWNALLOC = sys::LibRaw_decoder_flags_LIBRAW_DECODER_OWNALLOC as u32;
+        const FIXEDMAXC = sys::LibRaw_decoder_flags_LIBRAW_DECODER_FIXEDMAXC as u32;
+        const ADOBECOPYPIXEL = sys::LibRaw_decoder_flags_LIBRAW_DECODER_ADOBECOPYPIXEL as u32;
+        const LEGACY_WITH_MARGINS = sys::LibRaw_decoder_flags_LIBRAW_DECODER_LEGACY_WITH_MARGINS as u32;
+        /// Also `SINAR4SHOT`, which has the same value
+        const THREE_CHANNEL = sys::LibRaw_decoder_flags_LIBRAW_DECODER_3CHANNEL as u32;
+        const FLATDATA = sys::LibRaw_decoder_flags_LIBRAW_DECODER_FLATDATA as u32;
+        const FLAT_BG2_SWAPPED = sys::LibRaw_decoder_flags_LIBRAW_DECODER_FLAT_BG2_SWAPPED as u32;
+        const UNSUPPORTED_FORMAT = sys::LibRaw_decoder_flags_LIBRAW_DECODER_UNSUPPORTED_FORMAT as u32;
+        const NOTSET = sys::LibRaw_decoder_flags_LIBRAW_DECODER_NOTSET as u32;
+        const TRYRAWSPEED3 = sys::LibRaw_decoder_flags_LIBRAW_DECODER_TRYRAWSPEED3 as u32;
+    }
+}
+
+/// The decoder libraw picked for the opened file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecoderInfo {
+    /// Name of the unpack function, e.g. `crxLoadRaw()`
+    pub name: &'static str,
+    pub flags: DecoderFlags,
+}
+
+impl fmt::Display for DecoderInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:?})", self.name, self.flags)
+    }
+}
+
+impl Processor {
+    /// The decoder of the opened file (`libraw_get_decoder_info`)
+    pub fn decoder_info(&self) -> Result<DecoderInfo, LibrawError> {
+        let mut info = sys::libraw_decoder_info_t {
+            decoder_name: core::ptr::null(),
+            decoder_flags: 0,
+        };
+        LibrawError::check(unsafe {
+            sys::libraw_get_decoder_info(self.inner.as_ptr(), &mut info)
+        })?;
+        Ok(DecoderInfo {
+            name: static_str(info.decoder_name).unwrap_or_default(),
+            flags: DecoderFlags::from_bits_retain(info.decoder_flags),
+        })
+    }
+
+    /// Try RawSpeed before libraw's own decoders (`rawparams.use_rawspeed`)
+    ///
+    /// RawSpeed only decodes anything after [Self::set_rawspeed_camera_file]
+    #[cfg(feature = "rawspeed")]
+    pub fn set_use_rawspeed(&mut self, enabled: bool) {
+        self.rawparams().use_rawspeed = enabled as i32;
+    }
+
+    /// Load RawSpeed's cameras.xml
+    #[cfg(feature = "rawspeed")]
+    pub fn set_rawspeed_camera_file(&mut self, path: impl AsRef<Path>) -> Result<(), LibrawError> {
+        let path = crate::path_to_cstr(path)?;
+        LibrawError::check(unsafe {
+            sys::libraw_sys_set_rawspeed_camerafile(self.inner.as_ptr(), path.as_ptr())
+        })
+    }
+
+    /// Name of the unpack function (`libraw_unpack_function_name`)
+    pub fn unpack_function_name(&self) -> Option<&'static str> {
+        static_str(unsafe { sys::libraw_unpack_function_name(self.inner.as_ptr()) })
+    }
+}
+
+/// Strings libraw returns point to static tables
+fn static_str(ptr: *const core::ffi::c_char) -> Option<&'static str> {
+    match ptr.is_null() {
+        true => None,
+        false => unsafe { CStr::from_ptr(ptr) }.to_str().ok(),
+    }
+}
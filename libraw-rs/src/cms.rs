@@ -0,0 +1,180 @@
+//! Color managed output with ICC profiles
+//!
+//! Without LCMS (the `lcms` feature) libraw can only convert to the fixed `output_color` spaces,
+//! so instead the image is processed in linear camera rgb and converted with moxcms. The camera rgb
+//! is described by [Processor::camera_icc_profile] so any ICC v2 / v4 output profile works,
+//! including LUT based printer profiles. With a proof profile the colors are first converted to
+//! the proofed device and from there to the output profile to preview (soft proof) a print.
+pub use moxcms::{ColorProfile, RenderingIntent};
+use moxcms::{DataColorSpace, Layout, TransformOptions};
+
+use crate::colorspace::OutputColorSpace;
+use crate::{ImageFormat, LibrawError, Processor};
+
+/// The profile to convert to and how
+#[derive(Debug, Clone)]
+pub struct ColorTarget {
+    pub profile: ColorProfile,
+    pub intent: RenderingIntent,
+    /// Simulate this device (and intent) before converting to `profile`
+    pub proof: Option<(ColorProfile, RenderingIntent)>,
+}
+
+impl ColorTarget {
+    pub fn new(profile: ColorProfile) -> Self {
+        Self {
+            profile,
+            intent: RenderingIntent::Perceptual,
+            proof: None,
+        }
+    }
+
+    /// Parse an ICC v2 / v4 profile
+    pub fn from_icc(icc: &[u8]) -> Result<Self, LibrawError> {
+        Ok(Self::new(ColorProfile::new_from_slice(icc)?))
+    }
+
+    pub fn srgb() -> Self {
+        Self::new(ColorProfile::new_srgb())
+    }
+
+    pub fn display_p3() -> Self {
+        Self::new(ColorProfile::new_display_p3())
+    }
+
+    pub fn rec2020_pq() -> Self {
+        Self::new(ColorProfile::new_bt2020_pq())
+    }
+
+    pub fn with_intent(mut self, intent: RenderingIntent) -> Self {
+        self.intent = intent;
+        self
+    }
+
+    /// Soft proof, the colors are converted to `proof` with `intent` and from there to the output
+    /// profile with relative colorimetric intent
+    pub fn with_proof(mut self, proof: ColorProfile, intent: RenderingIntent) -> Self {
+        self.proof = Some((proof, intent));
+        self
+    }
+
+    /// Convert interleaved rgb in the `source` color space (0..1, linear for camera profiles) to
+    /// the target, through the proof profile if there is one
+    pub fn convert(
+        &self,
+        source: &ColorProfile,
+        pixels: Vec<f32>,
+    ) -> Result<Vec<f32>, LibrawError> {
+        let (pixels, source) = match &self.proof {
+            Some((proof, intent)) => (transform(source, proof, *intent, &pixels)?, proof),
+            None => (pixels, source),
+        };
+        let intent = match self.proof {
+            Some(_) => RenderingIntent::RelativeColorimetric,
+            None => self.intent,
+        };
+        transform(source, &self.profile, intent, &pixels)
+    }
+}
+
+/// 16 bit pixels in the color space of a [ColorTarget]
+#[derive(Debug, Clone)]
+pub struct ManagedImage {
+    pub pixels: Vec<u16>,
+    pub width: u32,
+    pub height: u32,
+    /// 1 for gray, 3 for rgb and 4 for cmyk profiles
+    pub channels: usize,
+    /// The encoded output profile to embed with the pixels
+    pub icc: Vec<u8>,
+}
+
+impl Processor {
+    /// Process the image in linear camera rgb and convert it to `target`
+    ///
+    /// The white balance, brightness and orientation params are used as usual, the camera profile
+    /// is built for the multipliers that were applied ([Processor::applied_white_balance]).
+    /// `output_color`, `output_bps` and `gamm` are overridden while processing and restored
+    /// afterwards
+    pub fn to_color_managed(&mut self, target: &ColorTarget) -> Result<ManagedImage, LibrawError> {
+        let (pixels, width, height) = self.camera_rgb()?;
+        let camera = ColorProfile::new_from_slice(
+            &self
+                .camera_icc_profile()
+                .ok_or(LibrawError::MissingColorMatrix)?,
+        )?;
+
+        let pixels = target.convert(&camera, pixels)?;
+
+        Ok(ManagedImage {
+            pixels: pixels
+                .into_iter()
+                .map(|v| (v.clamp(0.0, 1.0) * 65535.0).round() as u16)
+                .collect(),
+            width,
+            height,
+            channels: layout(target.profile.color_space)?.channels(),
+            icc: target.profile.encode()?,
+        })
+    }
+
+    /// Linear, white balanced camera rgb scaled to 0..1
+    fn camera_rgb(&mut self) -> Result<(Vec<f32>, u32, u32), LibrawError> {
+        self.unpack_once()?;
+        let saved = *self.params();
+        let params = self.params();
+        params.output_color = OutputColorSpace::Raw as i32;
+        params.output_bps = 16;
+        params.gamm[0] = 1.0;
+        params.gamm[1] = 1.0;
+        let processed = self
+            .dcraw_process()
+            .and_then(|_| self.dcraw_process_make_mem_image());
+        *self.params() = saved;
+        let processed = processed?;
+
+        if processed.type_() != ImageFormat::Bitmap {
+            return Err(LibrawError::UnsupportedImageFormat);
+        }
+        if processed.colors() != 3 || processed.bits() != 16 {
+            return Err(LibrawError::InvalidColor(processed.bits()));
+        }
+        let pixels = processed
+            .as_slice_u16()
+            .iter()
+            .map(|v| f32::from(*v) / 65535.0)
+            .collect();
+        Ok((pixels, processed.width(), processed.height()))
+    }
+}
+
+fn transform(
+    source: &ColorProfile,
+    destination: &ColorProfile,
+    intent: RenderingIntent,
+    pixels: &[f32],
+) -> Result<Vec<f32>, LibrawError> {
+    let (src_layout, dst_layout) = (
+        layout(source.color_space)?,
+        layout(destination.color_space)?,
+    );
+    let options = TransformOptions {
+        rendering_intent: intent,
+        allow_extended_range_rgb_xyz: true,
+        ..Default::default()
+    };
+    let transform = source.create_transform_f32(src_layout, destination, dst_layout, options)?;
+    let mut out = vec![0.0; pixels.len() / src_layout.channels() * dst_layout.channels()];
+    transform.transform(pixels, &mut out)?;
+    Ok(out)
+}
+
+/// The pixel layout moxcms uses for a profile's color space
+fn layout(space: DataColorSpace) -> Result<Layout, LibrawError> {
+    match space {
+        DataColorSpace::Gray => Ok(Layout::Gray),
+        DataColorSpace::Rgb | DataColorSpace::Cmy | DataColorSpace::Color3 => Ok(Layout::Rgb),
+        DataColorSpace::Cmyk | DataColorSpace::Color4 => Ok(Layout::Rgba),
+        _ => Err(moxcms::CmsError::InvalidLayout.into()),
+    }
+}
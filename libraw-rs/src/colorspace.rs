@@ -0,0 +1,189 @@
+//! The output color spaces supported by libraw (`params.output_color`) and their primaries
+use crate::matrix::{xy_to_xyz, Matrix3};
+
+/// The ICC profile connection space white point
+pub const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+/// The D65 white point
+pub const D65: [f64; 3] = [0.95047, 1.0, 1.08883];
+
+/// libraw_output_params_t.output_color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputColorSpace {
+    Raw = 0,
+    Srgb = 1,
+    AdobeRgb = 2,
+    WideGamutRgb = 3,
+    ProPhotoRgb = 4,
+    Xyz = 5,
+    Aces = 6,
+    DciP3 = 7,
+    Rec2020 = 8,
+}
+
+impl TryFrom<i32> for OutputColorSpace {
+    type Error = i32;
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        use OutputColorSpace::*;
+        Ok(match value {
+            0 => Raw,
+            1 => Srgb,
+            2 => AdobeRgb,
+            3 => WideGamutRgb,
+            4 => ProPhotoRgb,
+            5 => Xyz,
+            6 => Aces,
+            7 => DciP3,
+            8 => Rec2020,
+            v => return Err(v),
+        })
+    }
+}
+
+impl OutputColorSpace {
+    pub fn name(&self) -> &'static str {
+        use OutputColorSpace::*;
+        match self {
+            Raw => "Camera RGB",
+            Srgb => "sRGB",
+            AdobeRgb => "Adobe RGB (1998)",
+            WideGamutRgb => "Wide Gamut RGB",
+            ProPhotoRgb => "ProPhoto RGB",
+            Xyz => "XYZ",
+            Aces => "ACES",
+            DciP3 => "DCI-P3 D65",
+            Rec2020 => "Rec. 2020",
+        }
+    }
+
+    /// The xy chromaticities of the red, green and blue primaries and the white point
+    ///
+    /// Returns None for camera RGB and XYZ since they don't have primaries
+    pub fn chromaticities(&self) -> Option<[(f64, f64); 4]> {
+        use OutputColorSpace::*;
+        const D65_XY: (f64, f64) = (0.3127, 0.3290);
+        const D50_XY: (f64, f64) = (0.3457, 0.3585);
+        Some(match self {
+            Raw | Xyz => return None,
+            Srgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), D65_XY],
+            AdobeRgb => [(0.64, 0.33), (0.21, 0.71), (0.15, 0.06), D65_XY],
+            WideGamutRgb => [(0.7347, 0.2653), (0.1152, 0.8264), (0.1566, 0.0177), D50_XY],
+            ProPhotoRgb => [(0.7347, 0.2653), (0.1596, 0.8404), (0.0366, 0.0001), D50_XY],
+            Aces => [
+                (0.7347, 0.2653),
+                (0.0, 1.0),
+                (0.0001, -0.0770),
+                (0.32168, 0.33767),
+            ],
+            DciP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060), D65_XY],
+            Rec2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046), D65_XY],
+        })
+    }
+
+    /// The XYZ of the white point of the color space
+    pub fn white(&self) -> [f64; 3] {
+        match self.chromaticities() {
+            Some([.., (x, y)]) => xy_to_xyz(x, y),
+            None => D65,
+        }
+    }
+
+    /// Matrix converting linear rgb in this color space to XYZ with the native white point
+    pub fn to_xyz(&self) -> Option<Matrix3> {
+        if *self == OutputColorSpace::Xyz {
+            return Some(Matrix3::IDENTITY);
+        }
+        let [r, g, b, w] = self.chromaticities()?;
+        let primaries = Matrix3([
+            xy_to_xyz(r.0, r.1),
+            xy_to_xyz(g.0, g.1),
+            xy_to_xyz(b.0, b.1),
+        ])
+        .transpose();
+        let scale = primaries.inverse()?.mul_vec(xy_to_xyz(w.0, w.1));
+        Some(primaries * Matrix3::diagonal(scale))
+    }
+
+    /// Matrix converting linear rgb in this color space to the D50 adapted XYZ used by ICC
+    pub fn to_xyz_d50(&self) -> Option<Matrix3> {
+        Some(Matrix3::bradford(self.white(), D50) * self.to_xyz()?)
+    }
+}
+
+/// The gamma curve libraw applies to the output (`params.gamm`)
+///
+/// `power` is the exponent of the curve (0.45 for BT.709) and `slope` the slope of the linear
+/// toe (4.5 for BT.709), (1.0, 1.0) gives linear output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GammaCurve {
+    pub power: f64,
+    pub slope: f64,
+    // Derived values from dcraw's gamma_curve
+    g: [f64; 6],
+}
+
+impl GammaCurve {
+    pub const LINEAR: (f64, f64) = (1.0, 1.0);
+    pub const BT709: (f64, f64) = (0.45, 4.5);
+    pub const SRGB: (f64, f64) = (1.0 / 2.4, 12.92);
+
+    /// Same as dcraw's gamma_curve with mode 0
+    pub fn new(power: f64, slope: f64) -> Self {
+        let mut g = [power, slope, 0.0, 0.0, 0.0, 0.0];
+        let mut bnd = [0.0, 0.0];
+        bnd[usize::from(g[1] >= 1.0)] = 1.0;
+        if g[1] != 0.0 && (g[1] - 1.0) * (g[0] - 1.0) <= 0.0 {
+            for _ in 0..48 {
+                g[2] = (bnd[0] + bnd[1]) / 2.0;
+                if g[0] != 0.0 {
+                    let i = ((g[2] / g[1]).powf(-g[0]) - 1.0) / g[0] - 1.0 / g[2] > -1.0;
+                    bnd[usize::from(i)] = g[2];
+                } else {
+                    let i = g[2] / (1.0 - 1.0 / g[2]).exp() < g[1];
+                    bnd[usize::from(i)] = g[2];
+                }
+            }
+            g[3] = g[2] / g[1];
+            if g[0] != 0.0 {
+                g[4] = g[2] * (1.0 / g[0] - 1.0);
+            }
+        }
+        Self { power, slope, g }
+    }
+
+    /// From libraw_output_params_t.gamm
+    pub fn from_params(gamm: &[f64; 6]) -> Self {
+        Self::new(gamm[0], gamm[1])
+    }
+
+    pub fn is_linear(&self) -> bool {
+        self.power == 1.0 && self.slope == 1.0
+    }
+
+    /// Linear [0, 1] to encoded [0, 1]
+    pub fn encode(&self, v: f64) -> f64 {
+        let g = &self.g;
+        if v >= 1.0 {
+            1.0
+        } else if v < g[3] {
+            v * g[1]
+        } else if g[0] != 0.0 {
+            v.powf(g[0]) * (1.0 + g[4]) - g[4]
+        } else {
+            v.ln() * g[2] + 1.0
+        }
+    }
+
+    /// Encoded [0, 1] to linear [0, 1]
+    pub fn decode(&self, v: f64) -> f64 {
+        let g = &self.g;
+        if v >= 1.0 {
+            1.0
+        } else if v < g[2] {
+            v / g[1]
+        } else if g[0] != 0.0 {
+            ((v + g[4]) / (1.0 + g[4])).powf(1.0 / g[0])
+        } else {
+            ((v - 1.0) / g[2]).exp()
+        }
+    }
+}
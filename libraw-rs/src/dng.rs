@@ -0,0 +1,323 @@
+//! Write DNG files from the unpacked raw data
+//!
+//! Either the undemosaiced sensor data from `rawdata.raw_image` is written as a CFA DNG or the
+//! image is demosaiced by libraw (in the camera color space without white balance) and written
+//! as a linear DNG. The color matrices, levels and crop are filled from libraw's color data so
+//! any raw converter supporting DNG can open the result.
+use std::io::Write;
+
+use crate::calibration::{D65_ILLUMINANT, STANDARD_LIGHT_A};
+use crate::colorspace::OutputColorSpace;
+use crate::ifd::{Ifd, Value};
+use crate::matrix::Matrix3;
+use crate::metadata::CameraMetadata;
+use crate::traits::LRString;
+use crate::{cfa_color, Flip, LibrawError, Orientation, Processor};
+
+/// Rows are split into strips of roughly this many bytes
+const STRIP_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DngKind {
+    /// The undemosaiced bayer / x-trans (or monochrome) sensor data
+    #[default]
+    Cfa,
+    /// The demosaiced image in the camera color space
+    Linear,
+}
+
+impl Processor {
+    /// Write the opened file as a DNG, the file is unpacked if that hasn't been done yet
+    ///
+    /// For [DngKind::Linear] the image is processed with `dcraw_process`, the output params are
+    /// restored afterwards
+    pub fn write_dng(&mut self, kind: DngKind, mut writer: impl Write) -> Result<(), LibrawError> {
+        self.unpack_once()?;
+        let mut ifd = CameraMetadata::from(&*self).tiff_ifd();
+        let colors = match kind {
+            DngKind::Cfa => self.dng_cfa(&mut ifd)?,
+            DngKind::Linear => self.dng_linear(&mut ifd)?,
+        };
+        self.dng_color(&mut ifd, colors);
+
+        let idata = self.idata();
+        let make = idata.make.as_ascii();
+        let model = idata.model.as_ascii();
+        let orientation = Orientation::from(Flip::from(self.sizes().flip));
+        ifd.set(0x00fe, Value::Long(vec![0]))
+            .set(0x0103, Value::Short(vec![1]))
+            .set(0x0112, Value::Short(vec![orientation.0.into()]))
+            .set(0x011c, Value::Short(vec![1]))
+            .set(0xc612, Value::Byte(vec![1, 4, 0, 0]))
+            .set(0xc613, Value::Byte(vec![1, 1, 0, 0]))
+            .set(0xc614, Value::Ascii(format!("{make} {model}")));
+
+        writer.write_all(&ifd.to_tiff())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Same as [Processor::write_dng] but returns the file contents
+    pub fn to_dng(&mut self, kind: DngKind) -> Result<Vec<u8>, LibrawError> {
+        let mut dng = Vec::new();
+        self.write_dng(kind, &mut dng)?;
+        Ok(dng)
+    }
+
+    /// Add the raw data, CFA pattern and levels, returns the number of color planes
+    fn dng_cfa(&self, ifd: &mut Ifd) -> Result<usize, LibrawError> {
+        let sizes = self.sizes();
+        let idata = self.idata();
+        let color = self.color();
+        let raw = self.raw_image().ok_or(LibrawError::UnsupportedCfa)?;
+        if idata.filters == 0 && idata.colors != 1 {
+            return Err(LibrawError::UnsupportedCfa);
+        }
+
+        let (width, height) = (sizes.raw_width as usize, sizes.raw_height as usize);
+        let pitch = sizes.raw_pitch as usize / 2;
+        let strips = strips(width, height, 1, |row| {
+            raw[row * pitch..row * pitch + width]
+                .iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect()
+        });
+        let (top, left) = (sizes.top_margin as u32, sizes.left_margin as u32);
+        set_strips(ifd, width, height, 1, strips);
+        ifd.set(
+            0xc68d,
+            Value::Long(vec![
+                top,
+                left,
+                top + sizes.height as u32,
+                left + sizes.width as u32,
+            ]),
+        )
+        .set(0xc61f, Value::Long(vec![0, 0]))
+        .set(
+            0xc620,
+            Value::Long(vec![sizes.width.into(), sizes.height.into()]),
+        )
+        .set(0xc61d, Value::Long(vec![color.maximum]));
+
+        // The CFA and black level patterns are relative to the top left of the active area
+        let black = self.black_levels();
+        let (cfa_rows, cfa_cols) = black.cfa_period();
+        // 4 color RGBG (four_color_rgb) is written as RGB, CFAPlaneColor can't repeat a color
+        let rgbg = idata.colors == 4 && idata.cdesc[..4].iter().map(|c| *c as u8).eq(*b"RGBG");
+        let plane = |row: usize, col: usize| match cfa_color(idata, row, col) {
+            // The second green of 3 color bayer sensors
+            3 if idata.colors == 3 || rgbg => 1,
+            c => c,
+        };
+        if idata.filters == 0 {
+            ifd.set(0x0106, Value::Short(vec![34892]));
+        } else {
+            let pattern = (0..cfa_rows)
+                .flat_map(|row| (0..cfa_cols).map(move |col| (row, col)))
+                .map(|(row, col)| plane(row, col) as u8)
+                .collect();
+            ifd.set(0x0106, Value::Short(vec![32803]))
+                .set(0x828d, Value::Short(vec![cfa_rows as u16, cfa_cols as u16]))
+                .set(0x828e, Value::Byte(pattern))
+                .set(0xc617, Value::Short(vec![1]));
+            if idata.colors == 4 && !rgbg {
+                let planes = idata.cdesc[..4]
+                    .iter()
+                    .map(|c| match *c as u8 {
+                        b'R' => 0,
+                        b'G' => 1,
+                        b'B' => 2,
+                        b'C' => 3,
+                        b'M' => 4,
+                        _ => 5,
+                    })
+                    .collect();
+                ifd.set(0xc616, Value::Byte(planes));
+            }
+        }
+
+        // Per channel and per position black levels from cblack
+        let black = black.per_cfa_position();
+        ifd.set(
+            0xc619,
+            Value::Short(vec![black.len() as u16, black[0].len() as u16]),
+        )
+        .set(0xc61a, Value::Long(black.concat()));
+
+        match rgbg {
+            true => Ok(3),
+            false => Ok(idata.colors.max(1) as usize),
+        }
+    }
+
+    /// Demosaic without white balance or color conversion and add the image, returns the number
+    /// of color planes
+    fn dng_linear(&mut self, ifd: &mut Ifd) -> Result<usize, LibrawError> {
+        let saved = *self.params();
+        // dcraw_process overwrites pre_mul with user_mul, which dng_color still needs
+        let color = *self.color();
+        let params = self.params();
+        params.output_color = OutputColorSpace::Raw as i32;
+        params.output_bps = 16;
+        params.gamm[0] = 1.0;
+        params.gamm[1] = 1.0;
+        params.no_auto_bright = 1;
+        params.use_camera_wb = 0;
+        params.use_auto_wb = 0;
+        params.user_mul = [1.0; 4];
+        let processed = self.dcraw_process();
+        *self.params() = saved;
+        unsafe { self.inner.as_mut().color = color };
+        processed?;
+
+        let colors = self.idata().colors as usize;
+        if !matches!(colors, 1 | 3) || self.inner().image.is_null() {
+            return Err(LibrawError::UnsupportedCfa);
+        }
+        let sizes = self.sizes();
+        let (width, height) = (sizes.iwidth as usize, sizes.iheight as usize);
+        let image = unsafe { std::slice::from_raw_parts(self.inner().image, width * height) };
+        let strips = strips(width, height, colors, |row| {
+            image[row * width..(row + 1) * width]
+                .iter()
+                .flat_map(|pixel| &pixel[..colors])
+                .flat_map(|v| v.to_le_bytes())
+                .collect()
+        });
+        set_strips(ifd, width, height, colors, strips);
+        ifd.set(0x0106, Value::Short(vec![34892]))
+            .set(0xc61a, Value::Long(vec![0; colors]))
+            .set(0xc61d, Value::Long(vec![65535; colors]))
+            .set(0xc620, Value::Long(vec![width as u32, height as u32]));
+        Ok(colors)
+    }
+
+    /// ColorMatrix, ForwardMatrix, CalibrationIlluminant and AsShotNeutral
+    fn dng_color(&self, ifd: &mut Ifd, colors: usize) {
+        if colors == 1 {
+            return;
+        }
+        let color = self.color();
+        let srational = |v: &mut dyn Iterator<Item = f32>| {
+            Value::SRational(v.map(|v| Value::srational(v as f64)).collect())
+        };
+
+        let mut matrices = 0;
+        for (dng, i) in color.dng_color.iter().zip(0..) {
+            let parsed = |flag: u32| dng.parsedfields & flag != 0;
+            if !parsed(sys::LibRaw_dngfields_marks_LIBRAW_DNGFM_COLORMATRIX) {
+                continue;
+            }
+            let illuminant = match dng.illuminant {
+                0 => [STANDARD_LIGHT_A, D65_ILLUMINANT][i as usize],
+                v => v,
+            };
+            ifd.set(
+                0xc621 + i,
+                srational(&mut dng.colormatrix[..colors].iter().flatten().copied()),
+            )
+            .set(0xc65a + i, Value::Short(vec![illuminant]));
+            if parsed(sys::LibRaw_dngfields_marks_LIBRAW_DNGFM_FORWARDMATRIX) {
+                ifd.set(
+                    0xc714 + i,
+                    srational(
+                        &mut dng
+                            .forwardmatrix
+                            .iter()
+                            .flat_map(|row| &row[..colors])
+                            .copied(),
+                    ),
+                );
+            }
+            matrices += 1;
+        }
+
+        if matrices == 0 {
+            let cam_xyz = if color.cam_xyz.iter().flatten().any(|v| *v != 0.0) {
+                color.cam_xyz[..colors].iter().flatten().copied().collect()
+            } else {
+                self.cam_xyz_from_rgb_cam()
+            };
+            ifd.set(0xc621, srational(&mut cam_xyz.into_iter()))
+                .set(0xc65a, Value::Short(vec![D65_ILLUMINANT]));
+        }
+
+        let mul = match color.cam_mul[0] > 0.0 && color.cam_mul[1] > 0.0 {
+            true => color.cam_mul,
+            false => color.pre_mul,
+        };
+        let neutral = (0..colors)
+            .map(|c| match mul[c] > 0.0 && mul[1] > 0.0 {
+                true => Value::rational((mul[1] / mul[c]) as f64),
+                false => (1, 1),
+            })
+            .collect();
+        ifd.set(0xc628, Value::Rational(neutral));
+    }
+
+    /// Reconstruct an XYZ to camera matrix from rgb_cam and pre_mul when libraw doesn't have one
+    /// for the camera
+    fn cam_xyz_from_rgb_cam(&self) -> Vec<f32> {
+        let color = self.color();
+        let rgb_cam = Matrix3::from_f32(color.rgb_cam.map(|row| [row[0], row[1], row[2]]));
+        let xyz_rgb = OutputColorSpace::Srgb.to_xyz().and_then(|m| m.inverse());
+        let cam_xyz = match (rgb_cam.inverse(), xyz_rgb) {
+            (Some(cam_rgb), Some(rgb_xyz)) => {
+                let pre_mul = color
+                    .pre_mul
+                    .map(|v| if v > 0.0 { 1.0 / v as f64 } else { 1.0 });
+                Matrix3::diagonal([pre_mul[0], pre_mul[1], pre_mul[2]]) * cam_rgb * rgb_xyz
+            }
+            _ => Matrix3::IDENTITY,
+        };
+        // Normalize so the D65 white maps to a maximum camera value of 1
+        let white = cam_xyz.mul_vec(crate::colorspace::D65);
+        let max = white.into_iter().fold(f64::MIN, f64::max);
+        let scale = if max > 0.0 { 1.0 / max } else { 1.0 };
+        cam_xyz
+            .0
+            .iter()
+            .flatten()
+            .map(|v| (v * scale) as f32)
+            .collect()
+    }
+}
+
+/// Split the rows into uncompressed strips of about [STRIP_SIZE] bytes
+fn strips(
+    width: usize,
+    height: usize,
+    samples: usize,
+    row: impl Fn(usize) -> Vec<u8>,
+) -> (usize, Vec<Vec<u8>>) {
+    let rows_per_strip = (STRIP_SIZE / (width * samples * 2).max(1)).clamp(1, height.max(1));
+    let strips = (0..height)
+        .step_by(rows_per_strip)
+        .map(|start| {
+            (start..(start + rows_per_strip).min(height))
+                .flat_map(&row)
+                .collect()
+        })
+        .collect();
+    (rows_per_strip, strips)
+}
+
+fn set_strips(
+    ifd: &mut Ifd,
+    width: usize,
+    height: usize,
+    samples: usize,
+    (rows_per_strip, strips): (usize, Vec<Vec<u8>>),
+) {
+    ifd.set(0x0100, Value::Long(vec![width as u32]))
+        .set(0x0101, Value::Long(vec![height as u32]))
+        .set(0x0102, Value::Short(vec![16; samples]))
+        .set(0x0115, Value::Short(vec![samples as u16]))
+        .set(0x0116, Value::Long(vec![rows_per_strip as u32]))
+        .set(
+            0x0117,
+            Value::Long(strips.iter().map(|s| s.len() as u32).collect()),
+        )
+        .set(0x0111, Value::Offsets(strips));
+}
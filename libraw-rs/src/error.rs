@@ -1,6 +1,7 @@
 use crate::sys;
 
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum LibrawError {
     #[error("{0}")]
     InternalError(InternalLibrawError),
@@ -23,14 +24,36 @@ pub enum LibrawError {
     UnsupportedThumbnail,
     #[error("Invalid Number of bits ({0}) for colortype")]
     InvalidColor(u16),
+    #[error("Unsupported image format, expected a bitmap")]
+    UnsupportedImageFormat,
+    #[error("Unsupported number of colors ({0}) for the image format")]
+    UnsupportedColors(u16),
     #[cfg(feature = "jpeg")]
     #[error("{0}")]
     ImgPartsError(#[from] img_parts::Error),
     #[cfg(feature = "jpeg")]
     #[error("Failed to encode the processed image into and rgb image")]
     EncodingError,
+    #[cfg(feature = "export")]
+    #[error("{0}")]
+    PngError(#[from] png::EncodingError),
     #[error("Missing XMP header in raw file")]
     XMPMissing,
+    #[error("Unsupported number of shots ({0}) for pixel shift")]
+    InvalidShotCount(u32),
+    #[error("Unsupported color filter array, only bayer sensors are supported")]
+    UnsupportedCfa,
+    #[error("Pixel shift merging needs single channel raw frames")]
+    UnsupportedPixelShift,
+    #[error("Unsupported output color space ({0}) for the image format")]
+    UnsupportedOutputColor(i32),
+    #[error("No color matrix available for the camera")]
+    MissingColorMatrix,
+    #[error("libraw was built without {0:?}")]
+    MissingCapabilities(crate::capabilities::Capabilities),
+    #[cfg(feature = "cms")]
+    #[error("{0}")]
+    CmsError(#[from] moxcms::CmsError),
     #[error("{0}")]
     CustomError(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
@@ -5,7 +5,12 @@ use std::sync::Mutex;
 use alloc::sync::Arc;
 use libraw_sys::*;
 
+use crate::exif_tree::ExifIfd;
 use crate::{LibrawError, Processor};
+
+/// Upper bound for the size of a single value read in the callback
+const MAX_VALUE_LEN: usize = 16 << 20;
+
 pub type Callback<T> =
     Box<dyn Fn(ExifCallbackArgs<T>) -> Result<(), Box<dyn std::error::Error + Send + Sync>>>;
 
@@ -13,9 +18,13 @@ pub type Callback<T> =
 pub struct ExifCallbackArgs<'a, T> {
     pub callback_data: &'a mut T,
     pub tag: i32,
+    /// The directory the tag was read from
+    pub ifd: ExifIfd,
     pub data_type: DataType,
+    /// Number of values, not bytes
     pub len: i32,
     pub ord: u32,
+    /// `len * data_type.size()` bytes
     pub data: &'a mut [u8],
     pub base: i64,
 }
@@ -105,6 +114,18 @@ impl From<DataType> for libc::c_int {
     }
 }
 
+impl DataType {
+    /// Size in bytes of a single value of this type
+    pub fn size(&self) -> usize {
+        match self {
+            DataType::Byte | DataType::Ascii | DataType::SByte | DataType::Undefined => 1,
+            DataType::Short | DataType::SShort => 2,
+            DataType::Long | DataType::SLong | DataType::Float => 4,
+            DataType::Rational | DataType::SRational | DataType::Double => 8,
+        }
+    }
+}
+
 impl DataStreamType {
     fn read(
         &self,
@@ -134,14 +155,17 @@ impl Processor {
     ///      |---|---|---|
     ///      | data | &mut T | The data we pass to the function to act as a temp storage |
     ///      | tag  |  i32   | The tag of the exif data |
+    ///      | ifd  | ExifIfd | The IFD the tag was read from |
     ///      | type | i32    | The type of the exif data |
-    ///      | len  |  i32   | The length of the exif data |
+    ///      | len  |  i32   | The number of values in the exif data |
     ///      | ord  |  u32   | The order of the exif data |
-    ///      | data | &[u8]  | The exif data as a byte slice |
+    ///      | data | &[u8]  | The exif data as a byte slice (`len * type size` bytes) |
     ///      | base | i64    | Not sure  |
     ///
     /// NOTE:-
     ///
+    /// `data` used to be only `len` bytes long and `ifd` is new, see CHANGELOG.md
+    ///
     /// Currently this uses `Rc<RefCell<T>>` for the data  
     /// and Rc<Box<T: Fn>> for the callback function  
     /// So if libraw internally uses multithreading for a single image then this might cause UB  
@@ -238,7 +262,9 @@ impl<T> ExifReader<T> {
         base: INT64,
     ) {
         let context: Arc<ExifRead<T>> = unsafe { Arc::from_raw(context as *const ExifRead<T>) };
-        let mut buffer = vec![0_u8; len as usize];
+        let data_type = DataType::from(_type);
+        let size = (len.max(0) as usize * data_type.size()).min(MAX_VALUE_LEN);
+        let mut buffer = vec![0_u8; size];
 
         let res = unsafe {
             context.data_stream_type.read()(
@@ -261,7 +287,8 @@ impl<T> ExifReader<T> {
             if let Err(e) = (context.callback)(ExifCallbackArgs::<T> {
                 callback_data: &mut data,
                 tag: tag & 0x0fffff, // Undo (ifdN + 1 ) << 20
-                data_type: _type.into(),
+                ifd: ExifIfd::from_callback_tag(tag),
+                data_type,
                 len,
                 ord,
                 data: buffer.as_mut_slice(),
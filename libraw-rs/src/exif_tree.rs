@@ -0,0 +1,469 @@
+//! An owned tree of all the EXIF / TIFF tags libraw reports through the exif parser callback
+//!
+//! ```no_run
+//! use libraw_r::exif::DataStreamType;
+//! use libraw_r::exif_tree::Tag;
+//! # fn main() -> Result<(), libraw_r::error::LibrawError> {
+//! let mut p = libraw_r::Processor::default();
+//! let collector = p.exif_collector(DataStreamType::File)?;
+//! p.open("image.nef")?;
+//! let exif = collector.data()?;
+//! println!("{:?}", exif.get(Tag::LensModel).and_then(|v| v.as_str()));
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::BTreeMap;
+
+use crate::exif::{DataStreamType, DataType, ExifReader};
+use crate::{LibrawError, Processor};
+
+/// The ExifReader returned by [Processor::exif_collector], call `data()` after open to get the
+/// collected tags
+pub type ExifCollector = ExifReader<ExifTree>;
+
+/// The directory a tag was read from
+///
+/// libraw encodes this in the high bits of the tag it passes to the callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ExifIfd {
+    /// IFD0, IFD1, .. of the TIFF structure
+    Tiff(u16),
+    Exif,
+    MakerNote,
+    PanasonicRaw,
+    Interop,
+    Gps,
+    Unknown(u16),
+}
+
+impl ExifIfd {
+    /// Decode the IFD from the unmasked tag libraw passes to the callback
+    pub fn from_callback_tag(tag: i32) -> Self {
+        let tiff = (tag >> 20) as u16;
+        if tiff > 0 {
+            return ExifIfd::Tiff(tiff - 1);
+        }
+        match (tag >> 16) & 0xf {
+            0 => ExifIfd::Exif,
+            2 => ExifIfd::MakerNote,
+            3 => ExifIfd::PanasonicRaw,
+            4 => ExifIfd::Interop,
+            5 => ExifIfd::Gps,
+            group => ExifIfd::Unknown(group as u16),
+        }
+    }
+}
+
+/// An unsigned EXIF rational
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rational {
+    pub num: u32,
+    pub den: u32,
+}
+
+/// A signed EXIF rational
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SRational {
+    pub num: i32,
+    pub den: i32,
+}
+
+impl Rational {
+    pub fn to_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+}
+
+impl SRational {
+    pub fn to_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+}
+
+/// A decoded tag value
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExifValue {
+    Byte(Vec<u8>),
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<Rational>),
+    SByte(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShort(Vec<i16>),
+    SLong(Vec<i32>),
+    SRational(Vec<SRational>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+}
+
+impl ExifValue {
+    /// Decode the raw bytes of a value, `ord` is the byte order libraw reports (0x4949 for
+    /// little endian and 0x4d4d for big endian)
+    pub fn decode(data_type: DataType, data: &[u8], ord: u32) -> Self {
+        let big_endian = ord == 0x4d4d;
+        macro_rules! numbers {
+            ($t:ty) => {
+                data.chunks_exact(std::mem::size_of::<$t>())
+                    .map(|b| {
+                        let b = b.try_into().expect("chunks have the size of the type");
+                        match big_endian {
+                            true => <$t>::from_be_bytes(b),
+                            false => <$t>::from_le_bytes(b),
+                        }
+                    })
+                    .collect::<Vec<$t>>()
+            };
+        }
+        match data_type {
+            DataType::Byte => ExifValue::Byte(data.to_vec()),
+            DataType::Ascii => {
+                let end = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+                ExifValue::Ascii(String::from_utf8_lossy(&data[..end]).into_owned())
+            }
+            DataType::Short => ExifValue::Short(numbers!(u16)),
+            DataType::Long => ExifValue::Long(numbers!(u32)),
+            DataType::Rational => ExifValue::Rational(
+                numbers!(u32)
+                    .chunks_exact(2)
+                    .map(|v| Rational {
+                        num: v[0],
+                        den: v[1],
+                    })
+                    .collect(),
+            ),
+            DataType::SByte => ExifValue::SByte(data.iter().map(|v| *v as i8).collect()),
+            DataType::Undefined => ExifValue::Undefined(data.to_vec()),
+            DataType::SShort => ExifValue::SShort(numbers!(i16)),
+            DataType::SLong => ExifValue::SLong(numbers!(i32)),
+            DataType::SRational => ExifValue::SRational(
+                numbers!(i32)
+                    .chunks_exact(2)
+                    .map(|v| SRational {
+                        num: v[0],
+                        den: v[1],
+                    })
+                    .collect(),
+            ),
+            DataType::Float => ExifValue::Float(numbers!(f32)),
+            DataType::Double => ExifValue::Double(numbers!(f64)),
+        }
+    }
+
+    /// Number of values (or bytes of the string)
+    pub fn len(&self) -> usize {
+        use ExifValue::*;
+        match self {
+            Byte(v) | Undefined(v) => v.len(),
+            Ascii(v) => v.len(),
+            Short(v) => v.len(),
+            Long(v) => v.len(),
+            Rational(v) => v.len(),
+            SByte(v) => v.len(),
+            SShort(v) => v.len(),
+            SLong(v) => v.len(),
+            SRational(v) => v.len(),
+            Float(v) => v.len(),
+            Double(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ExifValue::Ascii(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    /// The first value of an unsigned integer tag
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            ExifValue::Byte(v) => v.first().map(|v| *v as u32),
+            ExifValue::Short(v) => v.first().map(|v| *v as u32),
+            ExifValue::Long(v) => v.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// The first value of any numeric tag
+    pub fn as_f64(&self) -> Option<f64> {
+        self.to_f64_vec().into_iter().next()
+    }
+
+    /// All the values of a numeric tag, empty for strings and undefined data
+    pub fn to_f64_vec(&self) -> Vec<f64> {
+        use ExifValue::*;
+        match self {
+            Byte(v) => v.iter().map(|v| *v as f64).collect(),
+            Short(v) => v.iter().map(|v| *v as f64).collect(),
+            Long(v) => v.iter().map(|v| *v as f64).collect(),
+            Rational(v) => v.iter().map(|v| v.to_f64()).collect(),
+            SByte(v) => v.iter().map(|v| *v as f64).collect(),
+            SShort(v) => v.iter().map(|v| *v as f64).collect(),
+            SLong(v) => v.iter().map(|v| *v as f64).collect(),
+            SRational(v) => v.iter().map(|v| v.to_f64()).collect(),
+            Float(v) => v.iter().map(|v| *v as f64).collect(),
+            Double(v) => v.clone(),
+            Ascii(_) | Undefined(_) => Vec::new(),
+        }
+    }
+}
+
+/// All the tags read while opening a file, grouped by IFD
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExifTree {
+    ifds: BTreeMap<ExifIfd, BTreeMap<u16, ExifValue>>,
+}
+
+impl ExifTree {
+    /// Insert a tag, the first value read for a tag in an IFD is kept
+    pub fn insert(&mut self, ifd: ExifIfd, tag: u16, value: ExifValue) {
+        self.ifds
+            .entry(ifd)
+            .or_default()
+            .entry(tag)
+            .or_insert(value);
+    }
+
+    /// Look up a standard tag in the IFD it belongs to
+    ///
+    /// TIFF tags are searched in IFD0 first and then in the following IFDs
+    pub fn get(&self, tag: Tag) -> Option<&ExifValue> {
+        match tag.group() {
+            TagGroup::Tiff => self
+                .ifds
+                .range(ExifIfd::Tiff(0)..=ExifIfd::Tiff(u16::MAX))
+                .find_map(|(_, tags)| tags.get(&tag.id())),
+            TagGroup::Exif => self.get_in(ExifIfd::Exif, tag.id()),
+            TagGroup::Gps => self.get_in(ExifIfd::Gps, tag.id()),
+            TagGroup::Interop => self.get_in(ExifIfd::Interop, tag.id()),
+        }
+    }
+
+    pub fn get_in(&self, ifd: ExifIfd, tag: u16) -> Option<&ExifValue> {
+        self.ifds.get(&ifd)?.get(&tag)
+    }
+
+    pub fn ifd(&self, ifd: ExifIfd) -> Option<&BTreeMap<u16, ExifValue>> {
+        self.ifds.get(&ifd)
+    }
+
+    pub fn ifds(&self) -> impl Iterator<Item = (ExifIfd, &BTreeMap<u16, ExifValue>)> {
+        self.ifds.iter().map(|(ifd, tags)| (*ifd, tags))
+    }
+
+    /// Every (ifd, tag, value) in the tree
+    pub fn iter(&self) -> impl Iterator<Item = (ExifIfd, u16, &ExifValue)> {
+        self.ifds
+            .iter()
+            .flat_map(|(ifd, tags)| tags.iter().map(move |(tag, value)| (*ifd, *tag, value)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ifds.is_empty()
+    }
+}
+
+impl Processor {
+    /// Collect every tag libraw reads while opening the file into an [ExifTree]
+    ///
+    /// This has to be called before open, see [Processor::set_exif_callback]
+    pub fn exif_collector(
+        &mut self,
+        data_stream_type: DataStreamType,
+    ) -> Result<ExifCollector, LibrawError> {
+        self.set_exif_callback(ExifTree::default(), data_stream_type, |args| {
+            let value = ExifValue::decode(args.data_type, args.data, args.ord);
+            args.callback_data
+                .insert(args.ifd, (args.tag & 0xffff) as u16, value);
+            Ok(())
+        })
+    }
+}
+
+/// The directory a standard tag is defined in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TagGroup {
+    Tiff,
+    Exif,
+    Gps,
+    Interop,
+}
+
+macro_rules! tags {
+    ($($group:ident $name:ident = $id:literal,)*) => {
+        /// Standard TIFF / EXIF / GPS tags
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[non_exhaustive]
+        pub enum Tag {
+            $($name,)*
+        }
+
+        impl Tag {
+            pub const ALL: &'static [Tag] = &[$(Tag::$name,)*];
+
+            pub fn id(&self) -> u16 {
+                match self {
+                    $(Tag::$name => $id,)*
+                }
+            }
+
+            pub fn group(&self) -> TagGroup {
+                match self {
+                    $(Tag::$name => TagGroup::$group,)*
+                }
+            }
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Tag::$name => stringify!($name),)*
+                }
+            }
+        }
+    };
+}
+
+impl Tag {
+    /// The standard tag with this id in the given IFD
+    pub fn from_id(ifd: ExifIfd, id: u16) -> Option<Tag> {
+        let group = match ifd {
+            ExifIfd::Tiff(_) => TagGroup::Tiff,
+            ExifIfd::Exif => TagGroup::Exif,
+            ExifIfd::Gps => TagGroup::Gps,
+            ExifIfd::Interop => TagGroup::Interop,
+            _ => return None,
+        };
+        Tag::ALL
+            .iter()
+            .find(|tag| tag.group() == group && tag.id() == id)
+            .copied()
+    }
+}
+
+tags! {
+    Tiff NewSubfileType = 0x00fe,
+    Tiff ImageWidth = 0x0100,
+    Tiff ImageLength = 0x0101,
+    Tiff BitsPerSample = 0x0102,
+    Tiff Compression = 0x0103,
+    Tiff PhotometricInterpretation = 0x0106,
+    Tiff ImageDescription = 0x010e,
+    Tiff Make = 0x010f,
+    Tiff Model = 0x0110,
+    Tiff StripOffsets = 0x0111,
+    Tiff Orientation = 0x0112,
+    Tiff SamplesPerPixel = 0x0115,
+    Tiff RowsPerStrip = 0x0116,
+    Tiff StripByteCounts = 0x0117,
+    Tiff XResolution = 0x011a,
+    Tiff YResolution = 0x011b,
+    Tiff PlanarConfiguration = 0x011c,
+    Tiff ResolutionUnit = 0x0128,
+    Tiff Software = 0x0131,
+    Tiff DateTime = 0x0132,
+    Tiff Artist = 0x013b,
+    Tiff TileWidth = 0x0142,
+    Tiff TileLength = 0x0143,
+    Tiff TileOffsets = 0x0144,
+    Tiff TileByteCounts = 0x0145,
+    Tiff SubIfds = 0x014a,
+    Tiff JpegInterchangeFormat = 0x0201,
+    Tiff JpegInterchangeFormatLength = 0x0202,
+    Tiff YCbCrPositioning = 0x0213,
+    Tiff Xmp = 0x02bc,
+    Tiff CfaRepeatPatternDim = 0x828d,
+    Tiff CfaPattern = 0x828e,
+    Tiff Copyright = 0x8298,
+    Tiff ExifIfdPointer = 0x8769,
+    Tiff IccProfile = 0x8773,
+    Tiff GpsIfdPointer = 0x8825,
+    Tiff DngVersion = 0xc612,
+    Tiff DngBackwardVersion = 0xc613,
+    Tiff UniqueCameraModel = 0xc614,
+    Tiff ColorMatrix1 = 0xc621,
+    Tiff ColorMatrix2 = 0xc622,
+    Tiff AsShotNeutral = 0xc628,
+    Tiff CalibrationIlluminant1 = 0xc65a,
+    Tiff CalibrationIlluminant2 = 0xc65b,
+    Tiff ForwardMatrix1 = 0xc714,
+    Tiff ForwardMatrix2 = 0xc715,
+    Exif ExposureTime = 0x829a,
+    Exif FNumber = 0x829d,
+    Exif ExposureProgram = 0x8822,
+    Exif PhotographicSensitivity = 0x8827,
+    Exif SensitivityType = 0x8830,
+    Exif ExifVersion = 0x9000,
+    Exif DateTimeOriginal = 0x9003,
+    Exif DateTimeDigitized = 0x9004,
+    Exif OffsetTime = 0x9010,
+    Exif OffsetTimeOriginal = 0x9011,
+    Exif OffsetTimeDigitized = 0x9012,
+    Exif ShutterSpeedValue = 0x9201,
+    Exif ApertureValue = 0x9202,
+    Exif BrightnessValue = 0x9203,
+    Exif ExposureBiasValue = 0x9204,
+    Exif MaxApertureValue = 0x9205,
+    Exif SubjectDistance = 0x9206,
+    Exif MeteringMode = 0x9207,
+    Exif LightSource = 0x9208,
+    Exif Flash = 0x9209,
+    Exif FocalLength = 0x920a,
+    Exif MakerNote = 0x927c,
+    Exif UserComment = 0x9286,
+    Exif SubSecTime = 0x9290,
+    Exif SubSecTimeOriginal = 0x9291,
+    Exif SubSecTimeDigitized = 0x9292,
+    Exif ColorSpace = 0xa001,
+    Exif PixelXDimension = 0xa002,
+    Exif PixelYDimension = 0xa003,
+    Exif InteropIfdPointer = 0xa005,
+    Exif FocalPlaneXResolution = 0xa20e,
+    Exif FocalPlaneYResolution = 0xa20f,
+    Exif FocalPlaneResolutionUnit = 0xa210,
+    Exif SensingMethod = 0xa217,
+    Exif FileSource = 0xa300,
+    Exif SceneType = 0xa301,
+    Exif CustomRendered = 0xa401,
+    Exif ExposureMode = 0xa402,
+    Exif WhiteBalance = 0xa403,
+    Exif DigitalZoomRatio = 0xa404,
+    Exif FocalLengthIn35mmFilm = 0xa405,
+    Exif SceneCaptureType = 0xa406,
+    Exif Contrast = 0xa408,
+    Exif Saturation = 0xa409,
+    Exif Sharpness = 0xa40a,
+    Exif SubjectDistanceRange = 0xa40c,
+    Exif ImageUniqueId = 0xa420,
+    Exif CameraOwnerName = 0xa430,
+    Exif BodySerialNumber = 0xa431,
+    Exif LensSpecification = 0xa432,
+    Exif LensMake = 0xa433,
+    Exif LensModel = 0xa434,
+    Exif LensSerialNumber = 0xa435,
+    Gps GpsVersionId = 0x0000,
+    Gps GpsLatitudeRef = 0x0001,
+    Gps GpsLatitude = 0x0002,
+    Gps GpsLongitudeRef = 0x0003,
+    Gps GpsLongitude = 0x0004,
+    Gps GpsAltitudeRef = 0x0005,
+    Gps GpsAltitude = 0x0006,
+    Gps GpsTimeStamp = 0x0007,
+    Gps GpsSatellites = 0x0008,
+    Gps GpsStatus = 0x0009,
+    Gps GpsMeasureMode = 0x000a,
+    Gps GpsDop = 0x000b,
+    Gps GpsSpeedRef = 0x000c,
+    Gps GpsSpeed = 0x000d,
+    Gps GpsTrackRef = 0x000e,
+    Gps GpsTrack = 0x000f,
+    Gps GpsImgDirectionRef = 0x0010,
+    Gps GpsImgDirection = 0x0011,
+    Gps GpsMapDatum = 0x0012,
+    Gps GpsDateStamp = 0x001d,
+    Interop InteropIndex = 0x0001,
+    Interop InteropVersion = 0x0002,
+}
@@ -0,0 +1,294 @@
+//! Lossless 8 / 16 bit tiff and png export of processed images with the ICC profile of the output
+//! color space and the camera EXIF embedded
+use std::io::Write;
+use std::path::Path;
+
+use crate::ifd::{Ifd, Value};
+use crate::metadata::CameraMetadata;
+use crate::{ImageFormat, LibrawError, ProcessedImage, Processor};
+
+/// Rows are split into strips of roughly this many bytes
+const STRIP_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Lzw,
+    Deflate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Tiff(Compression),
+    Png,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// 8 or 16, None keeps the bit depth of the processed image
+    pub bits: Option<u16>,
+    /// Embed the ICC profile of the output color space
+    pub icc_profile: bool,
+    /// Embed the camera EXIF
+    pub exif: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Tiff(Compression::None),
+            bits: Some(16),
+            icc_profile: true,
+            exif: true,
+        }
+    }
+}
+
+/// The metadata written along with the pixels
+#[derive(Debug, Clone, Default)]
+pub struct ExportMetadata {
+    pub icc_profile: Option<Vec<u8>>,
+    pub exif: Option<CameraMetadata>,
+}
+
+impl ExportOptions {
+    /// Fail with `InvalidColor` for a bit depth other than 8 or 16
+    fn check_bits(&self) -> Result<(), LibrawError> {
+        match self.bits {
+            None | Some(8) | Some(16) => Ok(()),
+            Some(bits) => Err(LibrawError::InvalidColor(bits)),
+        }
+    }
+}
+
+impl Processor {
+    /// Collect the ICC profile and EXIF to embed for the current file and params
+    pub fn export_metadata(&self, options: &ExportOptions) -> ExportMetadata {
+        ExportMetadata {
+            icc_profile: options
+                .icc_profile
+                .then(|| self.output_icc_profile())
+                .flatten(),
+            exif: options.exif.then(|| CameraMetadata::from(&*self)),
+        }
+    }
+
+    /// Process the opened file and write it to path
+    ///
+    /// output_bps is set to the requested bit depth for the processing and restored afterwards
+    pub fn export(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: &ExportOptions,
+    ) -> Result<(), LibrawError> {
+        // Before processing and creating (truncating) the file
+        options.check_bits()?;
+        self.unpack_once()?;
+        let output_bps = self.params().output_bps;
+        if let Some(bits) = options.bits {
+            self.params().output_bps = bits.into();
+        }
+        let processed = self
+            .dcraw_process()
+            .and_then(|_| self.dcraw_process_make_mem_image());
+        self.params().output_bps = output_bps;
+        let processed = processed?;
+        let metadata = self.export_metadata(options);
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        write(&processed, &metadata, options, file)
+    }
+}
+
+/// Write a processed bitmap as tiff or png
+pub fn write(
+    image: &ProcessedImage,
+    metadata: &ExportMetadata,
+    options: &ExportOptions,
+    writer: impl Write,
+) -> Result<(), LibrawError> {
+    options.check_bits()?;
+    if image.type_() != ImageFormat::Bitmap {
+        return Err(LibrawError::UnsupportedImageFormat);
+    }
+    if !matches!(image.colors(), 1 | 3) {
+        return Err(LibrawError::UnsupportedColors(image.colors()));
+    }
+    let bits = options.bits.unwrap_or(image.bits());
+    let samples = convert_samples(image, bits)?;
+    match options.format {
+        ExportFormat::Tiff(compression) => {
+            write_tiff(image, &samples, bits, metadata, compression, writer)
+        }
+        ExportFormat::Png => write_png(image, &samples, bits, metadata, writer),
+    }
+}
+
+/// The pixel samples at the requested bit depth
+enum Samples<'a> {
+    U8(std::borrow::Cow<'a, [u8]>),
+    U16(std::borrow::Cow<'a, [u16]>),
+}
+
+fn convert_samples(image: &ProcessedImage, bits: u16) -> Result<Samples<'_>, LibrawError> {
+    use std::borrow::Cow;
+    Ok(match (image.bits(), bits) {
+        (8, 8) => Samples::U8(Cow::Borrowed(image.as_slice_u8())),
+        (16, 16) => Samples::U16(Cow::Borrowed(image.as_slice_u16())),
+        (16, 8) => Samples::U8(Cow::Owned(
+            image
+                .as_slice_u16()
+                .iter()
+                .map(|v| (v >> 8) as u8)
+                .collect(),
+        )),
+        (8, 16) => Samples::U16(Cow::Owned(
+            image
+                .as_slice_u8()
+                .iter()
+                .map(|v| *v as u16 * 257)
+                .collect(),
+        )),
+        (_, bits) => return Err(LibrawError::InvalidColor(bits)),
+    })
+}
+
+fn write_tiff(
+    image: &ProcessedImage,
+    samples: &Samples,
+    bits: u16,
+    metadata: &ExportMetadata,
+    compression: Compression,
+    mut writer: impl Write,
+) -> Result<(), LibrawError> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let colors = image.colors() as usize;
+    let row_len = width * colors;
+    let row_bytes = row_len * bits as usize / 8;
+    let rows_per_strip = (STRIP_SIZE / row_bytes.max(1)).clamp(1, height.max(1));
+
+    let mut strips = Vec::new();
+    for rows in (0..height).step_by(rows_per_strip) {
+        let rows = rows..(rows + rows_per_strip).min(height);
+        let range = rows.start * row_len..rows.end * row_len;
+        let mut strip = match samples {
+            Samples::U8(s) => s[range].to_vec(),
+            Samples::U16(s) => s[range].iter().flat_map(|v| v.to_le_bytes()).collect(),
+        };
+        if compression != Compression::None {
+            horizontal_predictor(&mut strip, row_len, colors, bits);
+        }
+        strips.push(compress(strip, compression)?);
+    }
+
+    let mut ifd = match &metadata.exif {
+        Some(exif) => exif.tiff_ifd(),
+        None => Ifd::new(),
+    };
+    ifd.set(0x00fe, Value::Long(vec![0]))
+        .set(0x0100, Value::Long(vec![width as u32]))
+        .set(0x0101, Value::Long(vec![height as u32]))
+        .set(0x0102, Value::Short(vec![bits; colors]))
+        .set(
+            0x0103,
+            Value::Short(vec![match compression {
+                Compression::None => 1,
+                Compression::Lzw => 5,
+                Compression::Deflate => 8,
+            }]),
+        )
+        .set(0x0106, Value::Short(vec![if colors == 3 { 2 } else { 1 }]))
+        .set(0x0115, Value::Short(vec![colors as u16]))
+        .set(0x0116, Value::Long(vec![rows_per_strip as u32]))
+        .set(
+            0x0117,
+            Value::Long(strips.iter().map(|s| s.len() as u32).collect()),
+        )
+        .set(0x011c, Value::Short(vec![1]));
+    if compression != Compression::None {
+        ifd.set(0x013d, Value::Short(vec![2]));
+    }
+    if let Some(icc) = &metadata.icc_profile {
+        ifd.set(0x8773, Value::Undefined(icc.clone()));
+    }
+    ifd.set(0x0111, Value::Offsets(strips));
+
+    writer.write_all(&ifd.to_tiff())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// TIFF predictor 2, every sample is stored as the difference from the same sample in the
+/// previous pixel
+fn horizontal_predictor(strip: &mut [u8], row_len: usize, colors: usize, bits: u16) {
+    match bits {
+        8 => strip.chunks_mut(row_len).for_each(|row| {
+            for i in (colors..row.len()).rev() {
+                row[i] = row[i].wrapping_sub(row[i - colors]);
+            }
+        }),
+        _ => strip.chunks_mut(row_len * 2).for_each(|row| {
+            for i in (colors..row.len() / 2).rev() {
+                let cur = u16::from_le_bytes([row[i * 2], row[i * 2 + 1]]);
+                let prev = u16::from_le_bytes([row[(i - colors) * 2], row[(i - colors) * 2 + 1]]);
+                row[i * 2..i * 2 + 2].copy_from_slice(&cur.wrapping_sub(prev).to_le_bytes());
+            }
+        }),
+    }
+}
+
+fn compress(data: Vec<u8>, compression: Compression) -> Result<Vec<u8>, LibrawError> {
+    Ok(match compression {
+        Compression::None => data,
+        Compression::Lzw => weezl::encode::Encoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8)
+            .encode(&data)
+            .map_err(|e| LibrawError::CustomError(e.into()))?,
+        Compression::Deflate => zlib(&data)?,
+    })
+}
+
+fn zlib(data: &[u8]) -> Result<Vec<u8>, LibrawError> {
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn write_png(
+    image: &ProcessedImage,
+    samples: &Samples,
+    bits: u16,
+    metadata: &ExportMetadata,
+    writer: impl Write,
+) -> Result<(), LibrawError> {
+    let mut encoder = png::Encoder::new(writer, image.width(), image.height());
+    encoder.set_color(match image.colors() {
+        1 => png::ColorType::Grayscale,
+        _ => png::ColorType::Rgb,
+    });
+    encoder.set_depth(match bits {
+        8 => png::BitDepth::Eight,
+        _ => png::BitDepth::Sixteen,
+    });
+    let mut writer = encoder.write_header()?;
+
+    if let Some(icc) = &metadata.icc_profile {
+        let mut iccp = b"ICC Profile\0\0".to_vec();
+        iccp.extend_from_slice(&zlib(icc)?);
+        writer.write_chunk(png::chunk::iCCP, &iccp)?;
+    }
+    if let Some(exif) = &metadata.exif {
+        writer.write_chunk(png::chunk::ChunkType(*b"eXIf"), &exif.to_exif())?;
+    }
+
+    match samples {
+        Samples::U8(s) => writer.write_image_data(s)?,
+        Samples::U16(s) => {
+            // png stores 16 bit samples as big endian
+            let data = s.iter().flat_map(|v| v.to_be_bytes()).collect::<Vec<u8>>();
+            writer.write_image_data(&data)?
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
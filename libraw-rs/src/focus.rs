@@ -0,0 +1,312 @@
+//! Sharpness scores for culling
+//!
+//! The image is reduced to a gray plane, either the green of the raw mosaic (one value per CFA
+//! period, no demosaicing) or the luma of the largest embedded preview, and scored with the
+//! variance of the Laplacian or the Tenengrad (mean squared Sobel gradient). Values are
+//! normalized to 0..1 first so scores of different bit depths are comparable. Next to the score
+//! of the whole image (or the AF region) a per tile map is returned.
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::{LibrawError, Processor};
+
+/// Side of the square placed around AF points that are reported as a single position, as a
+/// fraction of the image width
+const AF_POINT_SIZE: f64 = 0.08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FocusSource {
+    /// Green pixels of `rawdata.raw_image`, only `unpack` is needed
+    #[default]
+    RawGreen,
+    /// Luma of the largest embedded jpeg, only `unpack_thumb` is needed
+    #[cfg(feature = "jpeg")]
+    EmbeddedPreview,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FocusMetric {
+    /// Variance of the 4 neighbour Laplacian
+    #[default]
+    LaplacianVariance,
+    /// Mean of the squared Sobel gradient magnitude
+    Tenengrad,
+}
+
+/// A rectangle relative to the (unrotated) sensor, all values in 0..=1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NormalizedRect {
+    pub left: f64,
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+}
+
+impl NormalizedRect {
+    pub const FULL: Self = Self {
+        left: 0.0,
+        top: 0.0,
+        right: 1.0,
+        bottom: 1.0,
+    };
+
+    /// A square of `size` (fraction of the width) around a point
+    pub fn around(x: f64, y: f64, size: f64, aspect: f64) -> Self {
+        let (half_w, half_h) = (size / 2.0, size * aspect / 2.0);
+        Self {
+            left: (x - half_w).max(0.0),
+            top: (y - half_h).max(0.0),
+            right: (x + half_w).min(1.0),
+            bottom: (y + half_h).min(1.0),
+        }
+    }
+
+    /// Pixel bounds in an image of `width` x `height`, at least one pixel wide and high
+    fn pixels(&self, width: usize, height: usize) -> (usize, usize, usize, usize) {
+        let scale = |v: f64, size: usize| ((v.clamp(0.0, 1.0) * size as f64) as usize).min(size);
+        let (left, top) = (scale(self.left, width), scale(self.top, height));
+        let right = scale(self.right, width).max(left + 1).min(width);
+        let bottom = scale(self.bottom, height).max(top + 1).min(height);
+        (left.min(right - 1), top.min(bottom - 1), right, bottom)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FocusRegion {
+    #[default]
+    Full,
+    /// The AF point from the makernotes, or the whole image when it isn't known
+    AfPoint,
+    Rect(NormalizedRect),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FocusOptions {
+    pub source: FocusSource,
+    pub metric: FocusMetric,
+    pub region: FocusRegion,
+    /// Columns and rows of the tile map
+    pub tiles: (usize, usize),
+}
+
+impl Default for FocusOptions {
+    fn default() -> Self {
+        Self {
+            source: FocusSource::default(),
+            metric: FocusMetric::default(),
+            region: FocusRegion::default(),
+            tiles: (8, 8),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FocusScore {
+    /// Score of the region, higher is sharper
+    pub score: f64,
+    /// The region that was scored
+    pub region: NormalizedRect,
+    /// Scores of the tiles, row major
+    pub tiles: Vec<f64>,
+    /// Columns and rows of `tiles`
+    pub tile_grid: (usize, usize),
+    /// Size of the analysed gray plane
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Processor {
+    /// Score the sharpness of the opened file
+    pub fn focus_score(&mut self, options: &FocusOptions) -> Result<FocusScore, LibrawError> {
+        let (gray, width, height) = match options.source {
+            FocusSource::RawGreen => self.raw_green()?,
+            #[cfg(feature = "jpeg")]
+            FocusSource::EmbeddedPreview => self.preview_luma()?,
+        };
+        if width < 3 || height < 3 {
+            return Err(LibrawError::UnsupportedImageFormat);
+        }
+        let response = response(&gray, width, height, options.metric);
+        let stats = |rect: &NormalizedRect| {
+            let (left, top, right, bottom) = rect.pixels(width, height);
+            let (mut sum, mut squares, mut count) = (0.0, 0.0, 0.0);
+            for row in response[top * width..bottom * width].chunks(width) {
+                for v in &row[left..right] {
+                    let v = f64::from(*v);
+                    sum += v;
+                    squares += v * v;
+                    count += 1.0;
+                }
+            }
+            let mean = sum / count;
+            match options.metric {
+                FocusMetric::LaplacianVariance => (squares / count - mean * mean).max(0.0),
+                FocusMetric::Tenengrad => mean,
+            }
+        };
+
+        let region = match options.region {
+            FocusRegion::Full => NormalizedRect::FULL,
+            FocusRegion::AfPoint => self.af_region().unwrap_or(NormalizedRect::FULL),
+            FocusRegion::Rect(rect) => rect,
+        };
+        let (columns, rows) = (options.tiles.0.max(1), options.tiles.1.max(1));
+        let tiles = (0..rows)
+            .flat_map(|row| (0..columns).map(move |col| (row, col)))
+            .map(|(row, col)| {
+                stats(&NormalizedRect {
+                    left: col as f64 / columns as f64,
+                    top: row as f64 / rows as f64,
+                    right: (col + 1) as f64 / columns as f64,
+                    bottom: (row + 1) as f64 / rows as f64,
+                })
+            })
+            .collect();
+        Ok(FocusScore {
+            score: stats(&region),
+            region,
+            tiles,
+            tile_grid: (columns, rows),
+            width,
+            height,
+        })
+    }
+
+    /// The AF area from the makernotes relative to the sensor
+    ///
+    /// Known for Fujifilm (`FocusPixel`) and Olympus (`AFPointSelected`), the other makers
+    /// (and `shootinginfo.AFPoint`) only store vendor specific point numbers
+    pub fn af_region(&self) -> Option<NormalizedRect> {
+        let sizes = self.sizes();
+        let (width, height) = (f64::from(sizes.width), f64::from(sizes.height));
+        if width == 0.0 || height == 0.0 {
+            return None;
+        }
+        let aspect = width / height;
+
+        let fuji = self.makernotes().fuji.FocusPixel;
+        if fuji[0] > 0 && fuji[1] > 0 {
+            let (x, y) = (f64::from(fuji[0]) / width, f64::from(fuji[1]) / height);
+            if x <= 1.0 && y <= 1.0 {
+                return Some(NormalizedRect::around(x, y, AF_POINT_SIZE, aspect));
+            }
+        }
+
+        // Top left and bottom right as fractions of the image
+        let [left, top, right, bottom, _] = self.makernotes().olympus.AFPointSelected;
+        let valid = |v: f64| (0.0..=1.0).contains(&v);
+        if [left, top, right, bottom].into_iter().all(valid) && (left > 0.0 || top > 0.0) {
+            return Some(match right > left && bottom > top {
+                true => NormalizedRect {
+                    left,
+                    top,
+                    right,
+                    bottom,
+                },
+                false => NormalizedRect::around(left, top, AF_POINT_SIZE, aspect),
+            });
+        }
+        None
+    }
+
+    /// The mean of the green pixels of every CFA period of the visible area
+    fn raw_green(&self) -> Result<(Vec<f32>, usize, usize), LibrawError> {
+        let raw = self.raw_image().ok_or(LibrawError::UnsupportedCfa)?;
+        let rawdata = self.rawdata();
+        let (sizes, idata) = (&rawdata.sizes, &rawdata.iparams);
+        let pitch = sizes.raw_pitch as usize / 2;
+        let (top, left) = (sizes.top_margin as usize, sizes.left_margin as usize);
+        let period = match idata.filters {
+            0 => 1,
+            // 3x3 blocks of the 6x6 xtrans pattern all have 5 green pixels
+            9 => 3,
+            _ => 2,
+        };
+        let green = [0, 1, 2, 3].map(|c| idata.filters == 0 || idata.cdesc[c] as u8 == b'G');
+        let black = self.black_levels();
+        let white = f64::from(self.saturation_levels().maximum.max(1));
+
+        let (width, height) = (
+            sizes.width as usize / period,
+            sizes.height as usize / period,
+        );
+        let block = |index: usize| {
+            let (bx, by) = (index % width * period, index / width * period);
+            let (mut sum, mut count) = (0.0, 0.0);
+            for row in by..by + period {
+                for col in bx..bx + period {
+                    if green[black.color(row, col)] {
+                        let v = f64::from(raw[(row + top) * pitch + col + left]);
+                        sum += (v - f64::from(black.at(row, col))) / white;
+                        count += 1.0;
+                    }
+                }
+            }
+            (sum / f64::max(count, 1.0)) as f32
+        };
+
+        #[cfg(feature = "rayon")]
+        let gray = (0..width * height).into_par_iter().map(block).collect();
+        #[cfg(not(feature = "rayon"))]
+        let gray = (0..width * height).map(block).collect();
+        Ok((gray, width, height))
+    }
+
+    /// Luma of the largest embedded jpeg
+    #[cfg(feature = "jpeg")]
+    fn preview_luma(&mut self) -> Result<(Vec<f32>, usize, usize), LibrawError> {
+        let list = self.thumbs_list();
+        let index = list
+            .thumblist
+            .iter()
+            .take(list.thumbcount.max(0) as usize)
+            .enumerate()
+            .filter(|(_, thumb)| {
+                thumb.tformat
+                    == sys::LibRaw_internal_thumbnail_formats_LIBRAW_INTERNAL_THUMBNAIL_JPEG
+            })
+            .max_by_key(|(_, thumb)| thumb.twidth as u32 * thumb.theight as u32)
+            .map(|(index, _)| index)
+            .ok_or(LibrawError::UnsupportedThumbnail)?;
+        self.unpack_thumb_ex(index as _)?;
+        let luma = self.thumbnail_image()?.into_luma8();
+        let (width, height) = (luma.width() as usize, luma.height() as usize);
+        let gray = luma.into_raw().into_iter().map(|v| f32::from(v) / 255.0);
+        Ok((gray.collect(), width, height))
+    }
+}
+
+/// The per pixel Laplacian or squared gradient, 0 on the border
+fn response(gray: &[f32], width: usize, height: usize, metric: FocusMetric) -> Vec<f32> {
+    let at = |x: usize, y: usize| gray[y * width + x];
+    let row = |(y, out): (usize, &mut [f32])| {
+        if y == 0 || y == height - 1 {
+            return;
+        }
+        for (x, out) in out.iter_mut().enumerate().take(width - 1).skip(1) {
+            *out = match metric {
+                FocusMetric::LaplacianVariance => {
+                    at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y)
+                }
+                FocusMetric::Tenengrad => {
+                    let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                        - at(x - 1, y - 1)
+                        - 2.0 * at(x - 1, y)
+                        - at(x - 1, y + 1);
+                    let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                        - at(x - 1, y - 1)
+                        - 2.0 * at(x, y - 1)
+                        - at(x + 1, y - 1);
+                    gx * gx + gy * gy
+                }
+            };
+        }
+    };
+
+    let mut out = vec![0.0; width * height];
+    #[cfg(feature = "rayon")]
+    out.par_chunks_mut(width).enumerate().for_each(row);
+    #[cfg(not(feature = "rayon"))]
+    out.chunks_mut(width).enumerate().for_each(row);
+    out
+}
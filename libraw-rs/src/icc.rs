@@ -0,0 +1,417 @@
+//! Generates matrix / TRC ICC profiles for the color spaces libraw can output and reads the
+//! profile embedded in some raw files
+use crate::colorspace::{GammaCurve, OutputColorSpace, D50, D65};
+use crate::matrix::Matrix3;
+use crate::traits::LRString;
+use crate::Processor;
+#[cfg(feature = "lcms")]
+use crate::{path_to_cstr, LibrawError};
+use std::ffi::CStr;
+#[cfg(feature = "lcms")]
+use std::ffi::CString;
+#[cfg(feature = "lcms")]
+use std::path::{Path, PathBuf};
+
+/// Number of entries in the sampled tone curves
+const CURVE_POINTS: usize = 1024;
+
+/// How the ICC profile embedded in the raw file (Phase One, Leaf, some DNGs) is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmbeddedProfileUsage {
+    /// Only available through [Processor::embedded_icc_profile]
+    #[default]
+    Ignore,
+    /// Attached to exported camera rgb images (`output_color` 0), which otherwise have none
+    Attach,
+    /// Also replaces [Processor::camera_icc_profile] as the description of the camera rgb
+    CameraProfile,
+}
+
+/// The profile / device class from the ICC header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileClass {
+    Input,
+    Display,
+    Output,
+    DeviceLink,
+    ColorSpace,
+    Abstract,
+    NamedColor,
+    Unknown([u8; 4]),
+}
+
+impl ProfileClass {
+    /// Describes a device that captures colors (scanner / camera)
+    pub fn is_input(&self) -> bool {
+        *self == ProfileClass::Input
+    }
+
+    /// Describes a device that reproduces colors (monitor / printer)
+    pub fn is_output(&self) -> bool {
+        matches!(self, ProfileClass::Display | ProfileClass::Output)
+    }
+}
+
+/// The interesting parts of an ICC profile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IccSummary {
+    pub description: Option<String>,
+    /// The data color space signature, e.g. "RGB", "CMYK" or "GRAY"
+    pub color_space: String,
+    pub class: ProfileClass,
+    /// Major and minor version
+    pub version: (u8, u8),
+}
+
+impl IccSummary {
+    /// Read the header and the desc tag, None if it isn't an ICC profile
+    pub fn parse(icc: &[u8]) -> Option<Self> {
+        if icc.len() < 132 || &icc[36..40] != b"acsp" {
+            return None;
+        }
+        let class = match &icc[12..16] {
+            b"scnr" => ProfileClass::Input,
+            b"mntr" => ProfileClass::Display,
+            b"prtr" => ProfileClass::Output,
+            b"link" => ProfileClass::DeviceLink,
+            b"spac" => ProfileClass::ColorSpace,
+            b"abst" => ProfileClass::Abstract,
+            b"nmcl" => ProfileClass::NamedColor,
+            v => ProfileClass::Unknown([v[0], v[1], v[2], v[3]]),
+        };
+        Some(Self {
+            description: find_tag(icc, b"desc").and_then(parse_text),
+            color_space: String::from_utf8_lossy(&icc[16..20]).trim().to_string(),
+            class,
+            version: (icc[8], icc[9] >> 4),
+        })
+    }
+}
+
+impl OutputColorSpace {
+    /// Build an ICC v2 display profile for this color space with the given output gamma
+    ///
+    /// Returns None for camera RGB since libraw doesn't know it's primaries
+    pub fn icc_profile(&self, gamma: &GammaCurve) -> Option<Vec<u8>> {
+        let to_xyz = self.to_xyz_d50()?;
+        let description = match gamma.is_linear() {
+            true => format!("{} (Linear)", self.name()),
+            false => self.name().to_string(),
+        };
+        Some(matrix_trc_profile(
+            &description,
+            &to_xyz,
+            &Matrix3::bradford(self.white(), D50),
+            gamma,
+        ))
+    }
+}
+
+impl Processor {
+    /// The ICC profile for the current `output_color` and `gamm` params
+    ///
+    /// When `output_profile` is set LCMS converts the image to it, so that file is returned
+    /// instead. For camera rgb this is the embedded profile unless [EmbeddedProfileUsage::Ignore]
+    pub fn output_icc_profile(&self) -> Option<Vec<u8>> {
+        let params = &self.inner().params;
+        if !params.output_profile.is_null() {
+            let path = unsafe { CStr::from_ptr(params.output_profile) };
+            return std::fs::read(path.to_str().ok()?).ok();
+        }
+        match OutputColorSpace::try_from(params.output_color).ok()? {
+            OutputColorSpace::Raw if self.embedded_profile != EmbeddedProfileUsage::Ignore => {
+                self.embedded_icc_profile().map(<[u8]>::to_vec)
+            }
+            space => space.icc_profile(&GammaCurve::from_params(&params.gamm)),
+        }
+    }
+
+    /// The ICC profile stored in the raw file (`color.profile`)
+    pub fn embedded_icc_profile(&self) -> Option<&[u8]> {
+        let color = self.color();
+        if color.profile.is_null() || color.profile_length == 0 {
+            return None;
+        }
+        Some(unsafe {
+            std::slice::from_raw_parts(color.profile as *const u8, color.profile_length as usize)
+        })
+    }
+
+    /// Parse the header and description of [Self::embedded_icc_profile]
+    pub fn embedded_icc_summary(&self) -> Option<IccSummary> {
+        IccSummary::parse(self.embedded_icc_profile()?)
+    }
+
+    pub fn set_embedded_profile_usage(&mut self, usage: EmbeddedProfileUsage) {
+        self.embedded_profile = usage;
+    }
+
+    pub fn embedded_profile_usage(&self) -> EmbeddedProfileUsage {
+        self.embedded_profile
+    }
+
+    /// Matrix converting white balanced camera rgb (libraw's output with `output_color` 0) to
+    /// D50 adapted XYZ
+    ///
+    /// DNG files use their ColorMatrix / ForwardMatrix interpolated for the white balance
+    /// libraw applies ([Self::applied_white_balance]), other files `rgb_cam` (which libraw
+    /// derives from `cam_xyz` and uses regardless of the white balance) followed by sRGB to XYZ
+    /// and a Bradford adaptation from D65 to D50
+    pub fn camera_to_xyz_d50(&self) -> Option<Matrix3> {
+        let mut calibration = self.color_calibration();
+        if !calibration.dng.is_empty() {
+            if let Some(multipliers) = self.applied_white_balance() {
+                calibration.as_shot_neutral = Some(multipliers.map(|v| 1.0 / v));
+            }
+            let temperature = calibration.as_shot_temperature().unwrap_or(6500.0);
+            if let Some(m) = calibration.white_balanced_to_xyz_d50(temperature) {
+                return Some(m);
+            }
+        }
+        calibration.rgb_cam_to_xyz_d50()
+    }
+
+    /// A linear ICC profile for the camera rgb libraw outputs with `output_color` 0
+    ///
+    /// With [EmbeddedProfileUsage::CameraProfile] the embedded profile is returned instead when
+    /// it is an rgb input / display profile
+    pub fn camera_icc_profile(&self) -> Option<Vec<u8>> {
+        if self.embedded_profile == EmbeddedProfileUsage::CameraProfile {
+            let usable = self.embedded_icc_summary().map_or(false, |summary| {
+                summary.color_space == "RGB"
+                    && matches!(summary.class, ProfileClass::Input | ProfileClass::Display)
+            });
+            if usable {
+                return self.embedded_icc_profile().map(<[u8]>::to_vec);
+            }
+        }
+        Some(matrix_trc_profile(
+            &format!(
+                "{} {}",
+                self.idata().make.as_ascii(),
+                self.idata().model.as_ascii()
+            ),
+            &self.camera_to_xyz_d50()?,
+            &Matrix3::bradford(D65, D50),
+            &GammaCurve::new(GammaCurve::LINEAR.0, GammaCurve::LINEAR.1),
+        ))
+    }
+}
+
+/// The camera profile libraw applies with LCMS during `dcraw_process`
+#[cfg(feature = "lcms")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CameraProfile {
+    /// The profile embedded in the raw file (`"embed"`)
+    Embedded,
+    File(PathBuf),
+}
+
+/// Owns the strings `output_profile` / `camera_profile` point to
+#[cfg(feature = "lcms")]
+#[derive(Debug, Default)]
+pub(crate) struct LcmsProfiles {
+    output: Option<CString>,
+    camera: Option<CString>,
+}
+
+#[cfg(feature = "lcms")]
+impl Processor {
+    /// Convert the processed image to this ICC profile with LCMS (`output_profile`)
+    pub fn set_output_profile(&mut self, path: Option<&Path>) -> Result<(), LibrawError> {
+        self.lcms_profiles.output = path.map(path_to_cstr).transpose()?;
+        self.params().output_profile = profile_ptr(&self.lcms_profiles.output);
+        Ok(())
+    }
+
+    /// Describe the camera rgb with an ICC profile instead of the color matrix (`camera_profile`)
+    pub fn set_camera_profile(
+        &mut self,
+        profile: Option<CameraProfile>,
+    ) -> Result<(), LibrawError> {
+        self.lcms_profiles.camera = match profile {
+            None => None,
+            Some(CameraProfile::Embedded) => Some(CString::new("embed")?),
+            Some(CameraProfile::File(path)) => Some(path_to_cstr(path)?),
+        };
+        self.params().camera_profile = profile_ptr(&self.lcms_profiles.camera);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "lcms")]
+fn profile_ptr(profile: &Option<CString>) -> *mut core::ffi::c_char {
+    profile
+        .as_ref()
+        .map_or(core::ptr::null_mut(), |profile| profile.as_ptr() as *mut _)
+}
+
+/// Build an ICC v2 rgb display profile
+///
+/// `to_xyz` converts linear rgb into D50 adapted XYZ and `adaptation` is the chromatic adaptation
+/// matrix from the color space's white point to D50 (stored in the chad tag)
+pub fn matrix_trc_profile(
+    description: &str,
+    to_xyz: &Matrix3,
+    adaptation: &Matrix3,
+    gamma: &GammaCurve,
+) -> Vec<u8> {
+    let column = |c: usize| [to_xyz[0][c], to_xyz[1][c], to_xyz[2][c]];
+    let curve = curve_tag(gamma);
+
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", desc_tag(description)),
+        (b"cprt", text_tag("No copyright, use freely")),
+        (b"wtpt", xyz_tag(D50)),
+        (b"rXYZ", xyz_tag(column(0))),
+        (b"gXYZ", xyz_tag(column(1))),
+        (b"bXYZ", xyz_tag(column(2))),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+        (b"chad", sf32_tag(adaptation)),
+    ];
+
+    let table_len = 4 + 12 * tags.len();
+    let mut data = Vec::new();
+    let mut table = Vec::with_capacity(table_len);
+    table.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+    for (signature, tag) in &tags {
+        let offset = 128 + table_len + data.len();
+        table.extend_from_slice(*signature);
+        table.extend_from_slice(&(offset as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        data.extend_from_slice(tag);
+        // Tags have to start on 4 byte boundaries
+        data.resize((data.len() + 3) & !3, 0);
+    }
+
+    let size = 128 + table.len() + data.len();
+    let mut profile = Vec::with_capacity(size);
+    profile.extend_from_slice(&(size as u32).to_be_bytes());
+    profile.extend_from_slice(&[0; 4]); // Preferred CMM
+    profile.extend_from_slice(&0x0210_0000_u32.to_be_bytes()); // Version 2.1
+    profile.extend_from_slice(b"mntr");
+    profile.extend_from_slice(b"RGB ");
+    profile.extend_from_slice(b"XYZ ");
+    profile.extend_from_slice(&[0; 12]); // Date
+    profile.extend_from_slice(b"acsp");
+    profile.extend_from_slice(&[0; 4]); // Platform
+    profile.extend_from_slice(&[0; 4]); // Flags
+    profile.extend_from_slice(&[0; 4]); // Manufacturer
+    profile.extend_from_slice(&[0; 4]); // Model
+    profile.extend_from_slice(&[0; 8]); // Attributes
+    profile.extend_from_slice(&[0; 4]); // Perceptual intent
+    for v in D50 {
+        profile.extend_from_slice(&s15fixed16(v));
+    }
+    profile.extend_from_slice(&[0; 4]); // Creator
+    profile.extend_from_slice(&[0; 16]); // Profile ID
+    profile.extend_from_slice(&[0; 28]); // Reserved
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&data);
+    profile
+}
+
+fn s15fixed16(v: f64) -> [u8; 4] {
+    ((v * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for v in xyz {
+        tag.extend_from_slice(&s15fixed16(v));
+    }
+    tag
+}
+
+fn sf32_tag(m: &Matrix3) -> Vec<u8> {
+    let mut tag = b"sf32\0\0\0\0".to_vec();
+    for v in m.0.iter().flatten() {
+        tag.extend_from_slice(&s15fixed16(*v));
+    }
+    tag
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+/// textDescriptionType from ICC v2, only the ascii part is filled
+fn desc_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    tag.extend_from_slice(&[0; 4]); // Unicode language code
+    tag.extend_from_slice(&[0; 4]); // Unicode count
+    tag.extend_from_slice(&[0; 2]); // ScriptCode code
+    tag.push(0); // ScriptCode count
+    tag.extend_from_slice(&[0; 67]);
+    tag
+}
+
+fn curve_tag(gamma: &GammaCurve) -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    if gamma.is_linear() {
+        // A curve with no entries is the identity
+        tag.extend_from_slice(&0_u32.to_be_bytes());
+        return tag;
+    }
+    tag.extend_from_slice(&(CURVE_POINTS as u32).to_be_bytes());
+    for i in 0..CURVE_POINTS {
+        let v = gamma.decode(i as f64 / (CURVE_POINTS - 1) as f64);
+        tag.extend_from_slice(&((v * 65535.0).round() as u16).to_be_bytes());
+    }
+    tag
+}
+
+/// The data of a tag from the tag table
+fn find_tag<'a>(icc: &'a [u8], signature: &[u8; 4]) -> Option<&'a [u8]> {
+    let be32 = |at: usize| -> Option<usize> {
+        Some(u32::from_be_bytes(icc.get(at..at + 4)?.try_into().ok()?) as usize)
+    };
+    // The count comes from the profile, never look past the entries that fit in it
+    let entries = icc.len().saturating_sub(132) / 12;
+    (0..be32(128)?).take(entries).find_map(|i| {
+        let entry = 132 + i * 12;
+        if icc.get(entry..entry + 4)? != signature {
+            return None;
+        }
+        let (offset, size) = (be32(entry + 4)?, be32(entry + 8)?);
+        icc.get(offset..offset.checked_add(size)?)
+    })
+}
+
+/// textDescriptionType (v2), multiLocalizedUnicodeType (v4) or textType
+fn parse_text(tag: &[u8]) -> Option<String> {
+    let be32 = |at: usize| -> Option<usize> {
+        Some(u32::from_be_bytes(tag.get(at..at + 4)?.try_into().ok()?) as usize)
+    };
+    let ascii = |bytes: &[u8]| {
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+    };
+    match tag.get(..4)? {
+        b"desc" => Some(ascii(tag.get(12..12 + be32(8)?)?)),
+        b"text" => Some(ascii(tag.get(8..)?)),
+        b"mluc" => {
+            // Use the first record
+            let (length, offset) = (be32(20)?, be32(24)?);
+            let utf16: Vec<u16> = tag
+                .get(offset..offset + length)?
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            Some(
+                String::from_utf16_lossy(&utf16)
+                    .trim_end_matches('\0')
+                    .to_string(),
+            )
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,161 @@
+//! A minimal little endian TIFF / EXIF structure writer
+//!
+//! Used for the EXIF blobs we embed into jpeg / png / tiff files and for writing tiff and dng
+//! files. Every value that doesn't fit in the 4 byte entry is appended after the IFD and
+//! sub-IFDs are written recursively so all offsets are relative to the start of the TIFF header.
+use std::collections::BTreeMap;
+
+/// Tags that point to sub IFDs
+pub const EXIF_IFD: u16 = 0x8769;
+pub const GPS_IFD: u16 = 0x8825;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Byte(Vec<u8>),
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    Undefined(Vec<u8>),
+    SRational(Vec<(i32, i32)>),
+    /// A pointer to one or more sub IFDs (written as LONG offsets)
+    Ifd(Vec<Ifd>),
+    /// Image data, written as LONG offsets to each of the chunks
+    Offsets(Vec<Vec<u8>>),
+}
+
+impl Value {
+    fn type_and_count(&self) -> (u16, usize) {
+        use Value::*;
+        match self {
+            Byte(v) => (1, v.len()),
+            Ascii(s) => (2, s.len() + 1),
+            Short(v) => (3, v.len()),
+            Long(v) => (4, v.len()),
+            Rational(v) => (5, v.len()),
+            Undefined(v) => (7, v.len()),
+            SRational(v) => (10, v.len()),
+            Ifd(v) => (4, v.len()),
+            Offsets(v) => (4, v.len()),
+        }
+    }
+
+    /// The serialized bytes for all values except Ifd and Offsets
+    fn bytes(&self) -> Vec<u8> {
+        use Value::*;
+        match self {
+            Byte(v) | Undefined(v) => v.clone(),
+            Ascii(s) => s.bytes().chain(Some(0)).collect(),
+            Short(v) => v.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Long(v) => v.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Rational(v) => v
+                .iter()
+                .flat_map(|(n, d)| n.to_le_bytes().into_iter().chain(d.to_le_bytes()))
+                .collect(),
+            SRational(v) => v
+                .iter()
+                .flat_map(|(n, d)| n.to_le_bytes().into_iter().chain(d.to_le_bytes()))
+                .collect(),
+            Ifd(_) | Offsets(_) => Vec::new(),
+        }
+    }
+
+    /// Approximate an unsigned value with a rational
+    pub fn rational(v: f64) -> (u32, u32) {
+        let v = v.max(0.0);
+        if v == 0.0 {
+            (0, 1)
+        } else if v < 1.0 {
+            // Exposure times are usually 1/x
+            let den = (1.0 / v).round().max(1.0);
+            if ((1.0 / den) - v).abs() / v < 1e-3 {
+                (1, den as u32)
+            } else {
+                ((v * 1_000_000.0).round() as u32, 1_000_000)
+            }
+        } else {
+            ((v * 10_000.0).round().min(u32::MAX as f64) as u32, 10_000)
+        }
+    }
+
+    /// Approximate a signed value with a rational
+    pub fn srational(v: f64) -> (i32, i32) {
+        ((v * 10_000.0).round() as i32, 10_000)
+    }
+}
+
+/// An image file directory, entries are kept sorted by tag as required by the spec
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ifd {
+    entries: BTreeMap<u16, Value>,
+}
+
+impl Ifd {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, tag: u16, value: Value) -> &mut Self {
+        self.entries.insert(tag, value);
+        self
+    }
+
+    /// Serialize as a complete little endian TIFF structure with this as the first IFD
+    pub fn to_tiff(&self) -> Vec<u8> {
+        let mut buf = b"II*\0\0\0\0\0".to_vec();
+        let offset = self.write(&mut buf);
+        buf[4..8].copy_from_slice(&offset.to_le_bytes());
+        buf
+    }
+
+    /// Append the IFD and all the data it references to buf and return it's offset
+    fn write(&self, buf: &mut Vec<u8>) -> u32 {
+        align(buf);
+        let start = buf.len();
+        buf.extend_from_slice(&(self.entries.len() as u16).to_le_bytes());
+        buf.resize(start + 2 + 12 * self.entries.len() + 4, 0);
+
+        for (i, (tag, value)) in self.entries.iter().enumerate() {
+            let (field_type, count) = value.type_and_count();
+            let payload = match value {
+                Value::Ifd(ifds) => Value::Long(ifds.iter().map(|ifd| ifd.write(buf)).collect()),
+                Value::Offsets(chunks) => Value::Long(
+                    chunks
+                        .iter()
+                        .map(|chunk| {
+                            align(buf);
+                            let offset = buf.len() as u32;
+                            buf.extend_from_slice(chunk);
+                            offset
+                        })
+                        .collect(),
+                ),
+                _ => value.clone(),
+            }
+            .bytes();
+
+            let mut field = [0_u8; 4];
+            if payload.len() <= 4 {
+                field[..payload.len()].copy_from_slice(&payload);
+            } else {
+                align(buf);
+                field = (buf.len() as u32).to_le_bytes();
+                buf.extend_from_slice(&payload);
+            }
+
+            let entry = start + 2 + 12 * i;
+            buf[entry..entry + 2].copy_from_slice(&tag.to_le_bytes());
+            buf[entry + 2..entry + 4].copy_from_slice(&field_type.to_le_bytes());
+            buf[entry + 4..entry + 8].copy_from_slice(&(count as u32).to_le_bytes());
+            buf[entry + 8..entry + 12].copy_from_slice(&field);
+        }
+        start as u32
+    }
+}
+
+/// Values have to start on a word boundary
+fn align(buf: &mut Vec<u8>) {
+    if buf.len() % 2 == 1 {
+        buf.push(0);
+    }
+}
@@ -0,0 +1,452 @@
+//! Jpeg encoding backends and editing the metadata of jpeg previews
+//!
+//! Every `to_jpeg*` / `jpeg*` method of [crate::Processor] encodes through the processor's
+//! [JpegEncoder] with it's [JpegOptions]. [TurboJpeg] (the default) and [ImageJpeg] are always
+//! available, [MozJpeg] is behind the `mozjpeg` feature. Any other libjpeg compatible encoder
+//! (like jpegli) can be used by implementing [JpegEncoder].
+//!
+//! The orientation is patched in place in the existing EXIF (and XMP) so the capture time,
+//! camera, lens and GPS tags survive, ICC profiles and every other segment are left as is.
+use img_parts::jpeg::{markers, Jpeg, JpegSegment};
+use img_parts::{Bytes, ImageEXIF};
+
+use crate::ifd::Value;
+use crate::metadata::CameraMetadata;
+use crate::{LibrawError, Orientation};
+
+const ORIENTATION_TAG: u16 = 0x0112;
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+
+/// Resolution of the color channels relative to the luma, grayscale images only have luma
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ChromaSubsampling {
+    /// 4:4:4, full resolution color
+    Yuv444,
+    /// 4:2:2, half the horizontal color resolution
+    Yuv422,
+    /// 4:2:0, half the horizontal and vertical color resolution
+    #[default]
+    Yuv420,
+}
+
+/// Encoder settings, not every backend supports all of them
+///
+/// | Option | [TurboJpeg] | [MozJpeg] | [ImageJpeg] |
+/// |---|---|---|---|
+/// | quality | yes | yes | yes |
+/// | subsampling | yes | yes | ignored, always 4:4:4 |
+/// | progressive | ignored, always baseline | yes | ignored, always baseline |
+/// | optimize_huffman | yes | yes | ignored, standard tables |
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JpegOptions {
+    /// 1 to 100
+    pub quality: u8,
+    pub subsampling: ChromaSubsampling,
+    /// Write multiple scans of increasing detail instead of a single baseline scan
+    pub progressive: bool,
+    /// Build the huffman tables for each image instead of using the standard ones, smaller files
+    /// but slower to encode
+    pub optimize_huffman: bool,
+}
+
+impl Default for JpegOptions {
+    fn default() -> Self {
+        Self {
+            // The default of the image crate's encoder, which the jpeg methods used before
+            quality: 75,
+            subsampling: ChromaSubsampling::default(),
+            progressive: false,
+            optimize_huffman: false,
+        }
+    }
+}
+
+impl JpegOptions {
+    pub fn with_quality(self, quality: u8) -> Self {
+        Self { quality, ..self }
+    }
+}
+
+/// Encodes 8 bit pixels into a jpeg
+///
+/// `pixels` are tightly packed rows of [image::ColorType::L8] or [image::ColorType::Rgb8]
+pub trait JpegEncoder: Send + Sync {
+    fn encode(
+        &self,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        color: image::ColorType,
+        options: &JpegOptions,
+    ) -> Result<Vec<u8>, LibrawError>;
+}
+
+/// Checks that the buffer matches the dimensions and returns the number of channels
+fn channels(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    color: image::ColorType,
+) -> Result<usize, LibrawError> {
+    let channels = match color {
+        image::ColorType::L8 => 1,
+        image::ColorType::Rgb8 => 3,
+        color => return Err(LibrawError::InvalidColor(color.bits_per_pixel())),
+    };
+    if pixels.len() != width as usize * height as usize * channels {
+        return Err(LibrawError::EncodingError);
+    }
+    Ok(channels)
+}
+
+/// The pure rust encoder from the image crate
+///
+/// Only the quality is configurable, it always writes baseline jpegs without chroma subsampling
+/// (4:4:4, whatever the image docs say) and with the standard huffman tables
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImageJpeg;
+
+impl JpegEncoder for ImageJpeg {
+    fn encode(
+        &self,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        color: image::ColorType,
+        options: &JpegOptions,
+    ) -> Result<Vec<u8>, LibrawError> {
+        channels(pixels, width, height, color)?;
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, options.quality)
+            .encode(pixels, width, height, color)?;
+        Ok(jpeg)
+    }
+}
+
+/// libjpeg-turbo through the turbojpeg api, the fastest backend and the default
+///
+/// `progressive` is ignored, use [MozJpeg] for progressive files
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TurboJpeg;
+
+impl JpegEncoder for TurboJpeg {
+    fn encode(
+        &self,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        color: image::ColorType,
+        options: &JpegOptions,
+    ) -> Result<Vec<u8>, LibrawError> {
+        let channels = channels(pixels, width, height, color)?;
+        let (format, subsamp) = match (channels, options.subsampling) {
+            (1, _) => (turbojpeg::PixelFormat::GRAY, turbojpeg::Subsamp::Gray),
+            (_, ChromaSubsampling::Yuv444) => {
+                (turbojpeg::PixelFormat::RGB, turbojpeg::Subsamp::None)
+            }
+            (_, ChromaSubsampling::Yuv422) => {
+                (turbojpeg::PixelFormat::RGB, turbojpeg::Subsamp::Sub2x1)
+            }
+            (_, ChromaSubsampling::Yuv420) => {
+                (turbojpeg::PixelFormat::RGB, turbojpeg::Subsamp::Sub2x2)
+            }
+        };
+        let image = turbojpeg::Image {
+            pixels,
+            width: width as usize,
+            pitch: width as usize * channels,
+            height: height as usize,
+            format,
+        };
+        let compress = || -> turbojpeg::Result<Vec<u8>> {
+            let mut compressor = turbojpeg::Compressor::new()?;
+            compressor.set_quality(options.quality.into())?;
+            compressor.set_subsamp(subsamp)?;
+            compressor.set_optimize(options.optimize_huffman)?;
+            compressor.compress_to_vec(image)
+        };
+        compress().map_err(|e| LibrawError::CustomError(e.into()))
+    }
+}
+
+/// mozjpeg, the smallest files for a given quality but slower than [TurboJpeg]
+///
+/// Baseline files are written with mozjpeg's fastest settings which also turns off trellis
+/// quantization
+#[cfg(feature = "mozjpeg")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MozJpeg;
+
+#[cfg(feature = "mozjpeg")]
+impl JpegEncoder for MozJpeg {
+    fn encode(
+        &self,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        color: image::ColorType,
+        options: &JpegOptions,
+    ) -> Result<Vec<u8>, LibrawError> {
+        let channels = channels(pixels, width, height, color)?;
+        let mut compress = match channels {
+            1 => mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_GRAYSCALE),
+            _ => mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB),
+        };
+        if options.progressive {
+            compress.set_progressive_mode();
+        } else {
+            compress.set_fastest_defaults();
+        }
+        compress.set_size(width as usize, height as usize);
+        compress.set_quality(options.quality.into());
+        compress.set_optimize_coding(options.optimize_huffman);
+        if channels == 3 {
+            let chroma = match options.subsampling {
+                ChromaSubsampling::Yuv444 => (1, 1),
+                ChromaSubsampling::Yuv422 => (2, 1),
+                ChromaSubsampling::Yuv420 => (2, 2),
+            };
+            compress.set_chroma_sampling_pixel_sizes((1, 1), chroma);
+        }
+        let mut compress = compress.start_compress(Vec::new())?;
+        compress.write_scanlines(pixels)?;
+        Ok(compress.finish()?)
+    }
+}
+
+/// Reduce 16 bit pixels (in native byte order) to 8 bits, 8 bit colors are returned as is
+pub(crate) fn to_8bit(
+    pixels: &[u8],
+    color: image::ColorType,
+) -> Result<(std::borrow::Cow<'_, [u8]>, image::ColorType), LibrawError> {
+    let color8 = match color {
+        image::ColorType::L8 | image::ColorType::Rgb8 => return Ok((pixels.into(), color)),
+        image::ColorType::L16 => image::ColorType::L8,
+        image::ColorType::Rgb16 => image::ColorType::Rgb8,
+        color => return Err(LibrawError::InvalidColor(color.bits_per_pixel())),
+    };
+    let pixels = pixels
+        .chunks_exact(2)
+        .map(|c| (u16::from_ne_bytes([c[0], c[1]]) >> 8) as u8)
+        .collect::<Vec<_>>();
+    Ok((pixels.into(), color8))
+}
+
+/// Set the orientation of a jpeg keeping the rest of it's metadata
+///
+/// The Orientation tag in IFD0 is overwritten (or added) and `tiff:Orientation` in the XMP
+/// packet is updated to match. If the jpeg doesn't have any EXIF one is created from `fallback`
+/// or with only the orientation if that is None as well.
+pub fn set_orientation(
+    jpeg: Vec<u8>,
+    orientation: Orientation,
+    fallback: Option<&CameraMetadata>,
+) -> Result<Vec<u8>, LibrawError> {
+    if Orientation::new(orientation.0).is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Orientation must be between 1 and 8",
+        )
+        .into());
+    }
+    let mut jpeg = Jpeg::from_bytes(Bytes::from(jpeg))?;
+
+    // Segments are replaced where they are so their order doesn't change
+    let mut has_exif = false;
+    for segment in jpeg.segments_mut() {
+        if segment.marker() != markers::APP1 {
+            continue;
+        }
+        let contents = segment.contents();
+        let patched = if let Some(tiff) = contents.strip_prefix(EXIF_HEADER) {
+            patch_tiff_orientation(tiff, orientation.0).map(|tiff| {
+                has_exif = true;
+                [EXIF_HEADER, &tiff].concat()
+            })
+        } else if let Some(xmp) = contents.strip_prefix(XMP_HEADER) {
+            std::str::from_utf8(xmp)
+                .ok()
+                .and_then(|xmp| patch_xmp_orientation(xmp, orientation.0))
+                .map(|xmp| [XMP_HEADER, xmp.as_bytes()].concat())
+        } else {
+            None
+        };
+        if let Some(contents) = patched {
+            *segment = JpegSegment::new_with_contents(markers::APP1, contents.into());
+        }
+    }
+    if !has_exif {
+        // Also replaces an EXIF segment that couldn't be parsed
+        let exif = exif_with_orientation(None, orientation, fallback);
+        jpeg.set_exif(Some(exif.into()));
+    }
+
+    let mut buffer = Vec::new();
+    jpeg.encoder().write_to(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// The TIFF structure of `exif` with the orientation patched, or one created from `fallback`
+/// if there is no EXIF or it can't be parsed
+pub(crate) fn exif_with_orientation(
+    exif: Option<&[u8]>,
+    orientation: Orientation,
+    fallback: Option<&CameraMetadata>,
+) -> Vec<u8> {
+    if let Some(exif) = exif.and_then(|exif| patch_tiff_orientation(exif, orientation.0)) {
+        return exif;
+    }
+    let mut ifd = fallback.map(|m| m.tiff_ifd()).unwrap_or_default();
+    ifd.set(ORIENTATION_TAG, Value::Short(vec![orientation.0.into()]));
+    ifd.to_tiff()
+}
+
+/// Copy the EXIF, XMP and ICC profile segments from `source` into `jpeg`, replacing the ones it
+/// has
+///
+/// Used to keep the metadata when a preview is decoded and re-encoded
+pub fn copy_metadata(source: &[u8], jpeg: Vec<u8>) -> Result<Vec<u8>, LibrawError> {
+    let source = Jpeg::from_bytes(Bytes::copy_from_slice(source))?;
+    let mut jpeg = Jpeg::from_bytes(Bytes::from(jpeg))?;
+    let is_metadata = |segment: &JpegSegment| match segment.marker() {
+        markers::APP1 => {
+            segment.contents().starts_with(EXIF_HEADER)
+                || segment.contents().starts_with(XMP_HEADER)
+        }
+        markers::APP2 => segment.contents().starts_with(ICC_HEADER),
+        _ => false,
+    };
+
+    jpeg.segments_mut().retain(|segment| !is_metadata(segment));
+    // Metadata goes right after SOI / JFIF
+    let position = jpeg
+        .segments()
+        .iter()
+        .position(|segment| segment.marker() != markers::APP0)
+        .unwrap_or(0);
+    let metadata = source
+        .segments()
+        .iter()
+        .filter(|segment| is_metadata(segment))
+        .cloned()
+        .collect::<Vec<_>>();
+    jpeg.segments_mut().splice(position..position, metadata);
+
+    let mut buffer = Vec::new();
+    jpeg.encoder().write_to(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Overwrite or add the orientation in IFD0 of a TIFF structure
+///
+/// Returns None if the structure can't be parsed
+fn patch_tiff_orientation(tiff: &[u8], orientation: u8) -> Option<Vec<u8>> {
+    let little_endian = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |buf: &[u8], offset: usize| -> Option<u16> {
+        let bytes = buf.get(offset..offset + 2)?.try_into().ok()?;
+        Some(match little_endian {
+            true => u16::from_le_bytes(bytes),
+            false => u16::from_be_bytes(bytes),
+        })
+    };
+    let u32_at = |buf: &[u8], offset: usize| -> Option<u32> {
+        let bytes = buf.get(offset..offset + 4)?.try_into().ok()?;
+        Some(match little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    };
+    let u16_bytes = |v: u16| match little_endian {
+        true => v.to_le_bytes(),
+        false => v.to_be_bytes(),
+    };
+    let u32_bytes = |v: u32| match little_endian {
+        true => v.to_le_bytes(),
+        false => v.to_be_bytes(),
+    };
+
+    let ifd0 = u32_at(tiff, 4)? as usize;
+    let count = u16_at(tiff, ifd0)? as usize;
+    let next_ifd = u32_at(tiff, ifd0 + 2 + 12 * count)?;
+    let mut tiff = tiff.to_vec();
+    let mut entries = Vec::with_capacity(count + 1);
+    for i in 0..count {
+        let entry = ifd0 + 2 + 12 * i;
+        let tag = u16_at(&tiff, entry)?;
+        if tag == ORIENTATION_TAG && u16_at(&tiff, entry + 2)? == 3 {
+            // SHORT values are stored in the entry itself so it can be patched in place
+            tiff[entry + 8..entry + 10].copy_from_slice(&u16_bytes(orientation.into()));
+            return Some(tiff);
+        }
+        if tag != ORIENTATION_TAG {
+            entries.push((tag, tiff[entry..entry + 12].to_vec()));
+        }
+    }
+
+    // Write a copy of IFD0 with the orientation added at the end, every other offset is relative
+    // to the header so they stay valid
+    let mut entry = u16_bytes(ORIENTATION_TAG).to_vec();
+    entry.extend_from_slice(&u16_bytes(3));
+    entry.extend_from_slice(&u32_bytes(1));
+    entry.extend_from_slice(&u16_bytes(orientation.into()));
+    entry.extend_from_slice(&[0, 0]);
+    entries.push((ORIENTATION_TAG, entry));
+    entries.sort_by_key(|(tag, _)| *tag);
+
+    if tiff.len() % 2 == 1 {
+        tiff.push(0);
+    }
+    let offset = tiff.len() as u32;
+    tiff.extend_from_slice(&u16_bytes(entries.len() as u16));
+    for (_, entry) in entries {
+        tiff.extend_from_slice(&entry);
+    }
+    tiff.extend_from_slice(&u32_bytes(next_ifd));
+    tiff[4..8].copy_from_slice(&u32_bytes(offset));
+    Some(tiff)
+}
+
+/// Replace the value of `tiff:Orientation` in both the attribute and element forms
+///
+/// Returns None if the packet doesn't mention the orientation
+fn patch_xmp_orientation(xmp: &str, orientation: u8) -> Option<String> {
+    let mut patched = String::with_capacity(xmp.len());
+    let mut rest = xmp;
+    let mut found = false;
+    while let Some(start) = rest.find("tiff:Orientation") {
+        let after = &rest[start + "tiff:Orientation".len()..];
+        let value_start = if rest[..start].ends_with("</") {
+            // The closing tag of the element form
+            None
+        } else if let Some(attr) = after.strip_prefix("=\"") {
+            Some((after.len() - attr.len(), '"'))
+        } else if let Some(attr) = after.strip_prefix("='") {
+            Some((after.len() - attr.len(), '\''))
+        } else {
+            after
+                .strip_prefix('>')
+                .map(|el| (after.len() - el.len(), '<'))
+        };
+        let Some((offset, end)) = value_start else {
+            patched.push_str(&rest[..start + "tiff:Orientation".len()]);
+            rest = after;
+            continue;
+        };
+        let value = &after[offset..];
+        let Some(len) = value.find(end) else {
+            break;
+        };
+        patched.push_str(&rest[..start + "tiff:Orientation".len() + offset]);
+        patched.push_str(&orientation.to_string());
+        rest = &value[len..];
+        found = true;
+    }
+    patched.push_str(rest);
+    found.then_some(patched)
+}
@@ -0,0 +1,230 @@
+//! Black and white (saturation) levels of the raw data
+//!
+//! libraw keeps the black level in three parts: `black` for the whole sensor, `cblack[0..4]`
+//! per CFA color and an optional repeating pattern with `cblack[4]` rows and `cblack[5]` columns
+//! starting at `cblack[6]`. The pattern is indexed in visible area coordinates like the CFA. The
+//! values are read from `rawdata.color`, which keeps the unpacked levels after processing.
+use crate::{fcol, LibrawError, Processor};
+
+/// A multiple of the bayer (up to 8 rows) and xtrans (6) periods
+const CFA_PERIODS: usize = 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlackLevels {
+    /// Black level of every pixel (`black`)
+    pub black: u32,
+    /// Added for each CFA color (`cblack[0..4]`)
+    pub per_color: [u32; 4],
+    /// Rows and columns of [Self::pattern]
+    pub pattern_size: (usize, usize),
+    /// Added per position of the repeating pattern, row major
+    pub pattern: Vec<u32>,
+    /// `idata.filters` to map positions to CFA colors
+    pub filters: u32,
+    /// `idata.xtrans`, the CFA colors when `filters` is 9
+    pub xtrans: [[u8; 6]; 6],
+}
+
+impl BlackLevels {
+    /// The black level of a pixel in visible area coordinates
+    pub fn at(&self, row: usize, col: usize) -> u32 {
+        let (rows, cols) = self.pattern_size;
+        let pattern = match rows > 0 && cols > 0 {
+            true => self.pattern[(row % rows) * cols + col % cols],
+            false => 0,
+        };
+        self.black + self.per_color[self.color(row, col)] + pattern
+    }
+
+    /// The CFA color of a pixel in visible area coordinates, like [crate::cfa_color]
+    pub fn color(&self, row: usize, col: usize) -> usize {
+        match self.filters {
+            0 => 0,
+            9 => usize::from(self.xtrans[row % 6][col % 6]),
+            filters => fcol(filters, row, col),
+        }
+    }
+
+    /// Rows and columns after which the CFA colors repeat
+    pub fn cfa_period(&self) -> (usize, usize) {
+        match self.filters {
+            0 => (1, 1),
+            9 => (6, 6),
+            // fcol reads 4 bits per row for 8 rows
+            f if f == f.rotate_right(8) => (2, 2),
+            f if f == f.rotate_right(16) => (4, 2),
+            _ => (8, 2),
+        }
+    }
+
+    /// The black levels of one period of the CFA and black pattern combined
+    ///
+    /// This is 2x2 for bayer sensors with a per color (or no) pattern and 6x6 for xtrans
+    pub fn per_cfa_position(&self) -> Vec<Vec<u32>> {
+        let (rows, cols) = self.pattern_size;
+        let (cfa_rows, cfa_cols) = self.cfa_period();
+        let (rows, cols) = (lcm(rows.max(1), cfa_rows), lcm(cols.max(1), cfa_cols));
+        (0..rows)
+            .map(|row| (0..cols).map(|col| self.at(row, col)).collect())
+            .collect()
+    }
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    let gcd = |mut a: usize, mut b: usize| {
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        a
+    };
+    a / gcd(a, b) * b
+}
+
+/// White levels of the raw data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaturationLevels {
+    /// The nominal white level (`maximum`)
+    pub maximum: u32,
+    /// The largest value found in the raw data (`data_maximum`), 0 when not computed
+    pub data_maximum: u32,
+    /// Per channel linear response limit from the makernotes (`linear_max`), 0 when unknown
+    pub linear_max: [u32; 4],
+}
+
+impl SaturationLevels {
+    /// The level each CFA color clips at, `linear_max` when known otherwise `maximum`
+    pub fn per_color(&self) -> [u32; 4] {
+        self.linear_max.map(|v| match v {
+            0 => self.maximum,
+            v => v,
+        })
+    }
+}
+
+/// A masked (optical black) area of the sensor in raw coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaskedArea {
+    pub top: usize,
+    pub left: usize,
+    pub bottom: usize,
+    pub right: usize,
+}
+
+/// Statistics of the masked pixels of one CFA color
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelStats {
+    pub count: u64,
+    pub mean: f64,
+    pub stddev: f64,
+    pub min: u16,
+    pub max: u16,
+}
+
+impl Processor {
+    /// The black levels of the unpacked raw data
+    pub fn black_levels(&self) -> BlackLevels {
+        let color = &self.rawdata().color;
+        let cblack = &color.cblack;
+        let (rows, cols) = (cblack[4] as usize, cblack[5] as usize);
+        let (pattern_size, pattern) = match rows * cols {
+            0 => ((0, 0), Vec::new()),
+            len if 6 + len <= cblack.len() => ((rows, cols), cblack[6..6 + len].to_vec()),
+            _ => ((0, 0), Vec::new()),
+        };
+        BlackLevels {
+            black: color.black,
+            per_color: [cblack[0], cblack[1], cblack[2], cblack[3]],
+            pattern_size,
+            pattern,
+            filters: self.rawdata().iparams.filters,
+            xtrans: self
+                .rawdata()
+                .iparams
+                .xtrans
+                .map(|row| row.map(|c| c as u8)),
+        }
+    }
+
+    /// The white levels of the unpacked raw data
+    pub fn saturation_levels(&self) -> SaturationLevels {
+        let color = &self.rawdata().color;
+        SaturationLevels {
+            maximum: color.maximum,
+            data_maximum: color.data_maximum,
+            linear_max: color.linear_max.map(|v| u32::try_from(v).unwrap_or(0)),
+        }
+    }
+
+    /// The mean of the masked pixels per CFA color as measured by libraw (`black_stat`)
+    pub fn black_stat_means(&self) -> [Option<f64>; 4] {
+        let stat = self.rawdata().color.black_stat;
+        [0, 1, 2, 3].map(|c| match stat[4 + c] {
+            0 => None,
+            count => Some(f64::from(stat[c]) / f64::from(count)),
+        })
+    }
+
+    /// The non empty masked areas (`sizes.mask`) clamped to the raw image
+    pub fn masked_areas(&self) -> Vec<MaskedArea> {
+        let sizes = &self.rawdata().sizes;
+        let (height, width) = (sizes.raw_height as usize, sizes.raw_width as usize);
+        sizes
+            .mask
+            .iter()
+            .map(|m| MaskedArea {
+                top: m[0].max(0) as usize,
+                left: m[1].max(0) as usize,
+                bottom: (m[2].max(0) as usize).min(height),
+                right: (m[3].max(0) as usize).min(width),
+            })
+            .filter(|area| area.bottom > area.top && area.right > area.left)
+            .collect()
+    }
+
+    /// Mean, standard deviation and range of the masked pixels per CFA color
+    ///
+    /// Only for single channel raw data (bayer / xtrans / monochrome), colors without masked
+    /// pixels are None
+    pub fn optical_black_stats(&self) -> Result<[Option<ChannelStats>; 4], LibrawError> {
+        let raw = self.raw_image().ok_or(LibrawError::UnsupportedCfa)?;
+        let sizes = &self.rawdata().sizes;
+        let cfa = self.black_levels();
+        let pitch = sizes.raw_pitch as usize / 2;
+        // Masked areas can be above or left of the visible area, shift by a multiple of every
+        // CFA period instead of going negative
+        let (row_shift, col_shift) = (
+            CFA_PERIODS - sizes.top_margin as usize % CFA_PERIODS,
+            CFA_PERIODS - sizes.left_margin as usize % CFA_PERIODS,
+        );
+
+        let mut sums = [(0_u64, 0_f64, 0_f64, u16::MAX, 0_u16); 4];
+        for area in self.masked_areas() {
+            for row in area.top..area.bottom {
+                for col in area.left..area.right {
+                    let color = cfa.color(row + row_shift, col + col_shift);
+                    let v = raw[row * pitch + col];
+                    let sum = &mut sums[color];
+                    sum.0 += 1;
+                    sum.1 += f64::from(v);
+                    sum.2 += f64::from(v).powi(2);
+                    sum.3 = sum.3.min(v);
+                    sum.4 = sum.4.max(v);
+                }
+            }
+        }
+        Ok(sums.map(|(count, sum, squares, min, max)| {
+            if count == 0 {
+                return None;
+            }
+            let mean = sum / count as f64;
+            let variance = (squares / count as f64 - mean * mean).max(0.0);
+            Some(ChannelStats {
+                count,
+                mean,
+                stddev: variance.sqrt(),
+                min,
+                max,
+            })
+        }))
+    }
+}
@@ -1,13 +1,45 @@
 #[macro_use]
 pub mod error;
+#[cfg(feature = "avif")]
+pub mod avif;
+pub mod bayer;
+pub mod calibration;
+pub mod cameras;
+pub mod capabilities;
+#[cfg(feature = "cms")]
+pub mod cms;
+pub mod colorspace;
 pub mod dcraw;
 pub mod defaults;
+pub mod dng;
 #[cfg(feature = "exif")]
 pub mod exif;
+#[cfg(feature = "exif")]
+pub mod exif_tree;
+#[cfg(feature = "export")]
+pub mod export;
+pub mod focus;
+pub mod icc;
+mod ifd;
+#[cfg(feature = "jpeg")]
+pub mod jpeg;
+pub mod levels;
+pub mod matrix;
+pub mod metadata;
 pub mod orientation;
+pub mod pixelshift;
+#[cfg(feature = "jpeg")]
+pub mod preview;
+pub mod processed;
 pub mod progress;
+#[cfg(feature = "jpeg")]
+pub mod resize;
+pub mod stats;
 pub mod structs;
 pub mod traits;
+#[cfg(feature = "webp")]
+pub mod webp;
+pub mod white_balance;
 
 use alloc::sync::Arc;
 pub use error::LibrawError;
@@ -17,6 +49,10 @@ use fast_image_resize as fr;
 use fr::{PixelType, ResizeOptions};
 #[cfg(feature = "jpeg")]
 use image::ColorType;
+pub use orientation::{Flip, Orientation};
+#[cfg(feature = "jpeg")]
+use resize::ResizeSpec;
+pub use sys::{verify_abi, AbiMismatch};
 
 extern crate alloc;
 extern crate libraw_sys as sys;
@@ -38,10 +74,31 @@ pub const fn version() -> Version {
     }
 }
 
+/// Returns the version of the linked libraw, which can differ from [version] with a system libraw
+pub fn runtime_version() -> Version {
+    let number = unsafe { sys::libraw_versionNumber() } as u64;
+    Version::new(number >> 16, (number >> 8) & 0xff, number & 0xff)
+}
+
+/// Returns the version string of the linked libraw, e.g. "0.21.2-Release"
+pub fn runtime_version_string() -> &'static str {
+    let version = unsafe { std::ffi::CStr::from_ptr(sys::libraw_version()) };
+    version.to_str().unwrap_or_default()
+}
+
 /// A struct wrapping the libraw_data_t type
 pub struct Processor {
     inner: NonNull<sys::libraw_data_t>,
     dropped: Arc<AtomicBool>,
+    #[cfg(feature = "jpeg")]
+    jpeg_encoder: Arc<dyn jpeg::JpegEncoder>,
+    #[cfg(feature = "jpeg")]
+    jpeg_options: jpeg::JpegOptions,
+    embedded_profile: icc::EmbeddedProfileUsage,
+    #[cfg(feature = "lcms")]
+    lcms_profiles: icc::LcmsProfiles,
+    /// The buffer passed to libraw_open_bayer
+    bayer_data: Option<Vec<u8>>,
 }
 
 /// You can pass the Processor to another thread since it doesn't use any thread_local values
@@ -122,10 +179,7 @@ impl Processor {
     pub fn new(option: LibrawConstructorFlags) -> Self {
         let inner = unsafe { sys::libraw_init(option as u32) };
         assert!(!inner.is_null());
-        Self {
-            inner: NonNull::new(inner).expect("Failed to initialize libraw"),
-            dropped: Arc::new(AtomicBool::new(false)),
-        }
+        Self::from_inner(NonNull::new(inner).expect("Failed to initialize libraw"))
     }
 
     pub fn try_new(option: LibrawConstructorFlags) -> Result<Self, LibrawError> {
@@ -135,10 +189,24 @@ impl Processor {
                 "Got back null pointer from libraw_init(0)".into(),
             ))
         } else {
-            Ok(Self {
-                inner: NonNull::new(inner).expect("Failed to initialize libraw"),
-                dropped: Arc::new(AtomicBool::new(false)),
-            })
+            Ok(Self::from_inner(
+                NonNull::new(inner).expect("Failed to initialize libraw"),
+            ))
+        }
+    }
+
+    fn from_inner(inner: NonNull<sys::libraw_data_t>) -> Self {
+        Self {
+            inner,
+            dropped: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "jpeg")]
+            jpeg_encoder: Arc::new(jpeg::TurboJpeg),
+            #[cfg(feature = "jpeg")]
+            jpeg_options: jpeg::JpegOptions::default(),
+            embedded_profile: icc::EmbeddedProfileUsage::default(),
+            #[cfg(feature = "lcms")]
+            lcms_profiles: icc::LcmsProfiles::default(),
+            bayer_data: None,
         }
     }
 
@@ -176,6 +244,7 @@ impl Processor {
                 std::io::Error::new(std::io::ErrorKind::NotFound, "Raw file not found").into(),
             );
         }
+        self.recycle()?;
         let c_path = path_to_cstr(&path)?;
         LibrawError::check(unsafe { sys::libraw_open_file(self.inner.as_ptr(), c_path.as_ptr()) })
     }
@@ -298,6 +367,16 @@ impl Processor {
         Ok(())
     }
 
+    /// Unpack unless the raw data of the opened file is already in memory
+    ///
+    /// `image` is only allocated by processing, so it can't tell if bayer files were unpacked
+    pub(crate) fn unpack_once(&mut self) -> Result<(), LibrawError> {
+        if self.rawdata().raw_alloc.is_null() {
+            self.unpack()?;
+        }
+        Ok(())
+    }
+
     /// Get the maximum colors
     pub fn get_color_maximum(&self) -> Result<i32, LibrawError> {
         let data = unsafe { sys::libraw_get_color_maximum(self.inner.as_ptr()) };
@@ -307,6 +386,7 @@ impl Processor {
     /// All other references should be invalid when we recycle so we take a mutable value to self
     pub fn recycle(&mut self) -> Result<(), LibrawError> {
         unsafe { sys::libraw_recycle(self.inner.as_ptr()) };
+        self.bayer_data = None;
         Ok(())
     }
 
@@ -316,6 +396,27 @@ impl Processor {
     pub fn adjust_sizes_info_only(&mut self) -> Result<(), LibrawError> {
         LibrawError::check(unsafe { sys::libraw_adjust_sizes_info_only(self.inner.as_ptr()) })
     }
+
+    /// Get the raw unpack parameters
+    ///
+    /// These need to be set before calling open / unpack
+    pub fn rawparams(&'_ mut self) -> &'_ mut sys::libraw_raw_unpack_params_t {
+        unsafe { &mut self.inner.as_mut().rawparams }
+    }
+
+    /// Get the unpacked bayer (or monochrome) data
+    ///
+    /// The slice contains `raw_height` rows of `raw_pitch / 2` pixels each and the visible area
+    /// starts at (`top_margin`, `left_margin`).
+    /// Returns None if unpack hasn't been called or the image isn't stored in raw_image
+    pub fn raw_image(&'_ self) -> Option<&'_ [u16]> {
+        let rawdata = self.rawdata();
+        if rawdata.raw_image.is_null() {
+            return None;
+        }
+        let len = rawdata.sizes.raw_height as usize * rawdata.sizes.raw_pitch as usize / 2;
+        Some(unsafe { std::slice::from_raw_parts(rawdata.raw_image, len) })
+    }
 }
 
 #[cfg(feature = "jpeg")]
@@ -347,26 +448,181 @@ impl Processor {
                 // Don't use a Vec since a Vec's internal memory representation is entirely dependent
                 // on the allocator used which might(is) be different in c/c++/rust
                 let jpeg = thumbnail_data.to_vec();
-                let jpeg = Orientation::from(Flip::from(flip)).add_to(jpeg)?;
+                let jpeg = self.set_jpeg_orientation(jpeg, Orientation::from(Flip::from(flip)))?;
                 Ok(jpeg)
             }
             ThumbnailFormat::Bitmap => {
                 // Since this is a bitmap we have to generate the thumbnail from the rgb data from
                 // here
-                let mut jpeg = Vec::new();
-                image::codecs::jpeg::JpegEncoder::new(&mut jpeg).encode(
+                let jpeg = self.encode_jpeg(
                     thumbnail_data,
                     thumbnail.twidth as u32,
                     thumbnail.theight as u32,
                     image::ColorType::Rgb8,
+                    None,
                 )?;
-                let jpeg = Orientation::from(Flip::from(flip)).add_to(jpeg)?;
+                let jpeg = self.set_jpeg_orientation(jpeg, Orientation::from(Flip::from(flip)))?;
                 Ok(jpeg)
             }
             _ => Err(LibrawError::UnsupportedThumbnail),
         }
     }
 
+    /// Same as get_jpeg, but with `bake_orientation` the thumbnail pixels are rotated according
+    /// to `sizes.flip` and the orientation tag is reset to 1
+    ///
+    /// Baking decodes and re-encodes the thumbnail with the given quality
+    pub fn get_jpeg_oriented(
+        &mut self,
+        quality: u8,
+        bake_orientation: bool,
+    ) -> Result<Vec<u8>, LibrawError> {
+        if !bake_orientation {
+            return self.get_jpeg();
+        }
+        let dynimg = self.thumbnail_image()?;
+        let orientation = Orientation::from(Flip::from(self.sizes().flip));
+        let mut jpeg = self.encode_baked(dynimg, orientation, quality)?;
+        if let Some(original) = self.thumbnail_jpeg() {
+            jpeg = jpeg::copy_metadata(original, jpeg)?;
+        }
+        self.set_jpeg_orientation(jpeg, Orientation::NONE)
+    }
+
+    /// Decode the embedded thumbnail without applying the orientation
+    pub(crate) fn thumbnail_image(&mut self) -> Result<image::DynamicImage, LibrawError> {
+        if unsafe { self.inner.as_ref().thumbnail.thumb.is_null() } {
+            self.unpack_thumb()?;
+        }
+        let thumbnail = self.thumbnail();
+        let thumbnail_data = unsafe {
+            std::slice::from_raw_parts(thumbnail.thumb as *const u8, thumbnail.tlength as usize)
+        };
+
+        match ThumbnailFormat::from(thumbnail.tformat) {
+            ThumbnailFormat::Jpeg => Ok(image::load_from_memory_with_format(
+                thumbnail_data,
+                image::ImageFormat::Jpeg,
+            )?),
+            ThumbnailFormat::Bitmap => Ok(image::DynamicImage::from(
+                image::RgbImage::from_raw(
+                    thumbnail.twidth as u32,
+                    thumbnail.theight as u32,
+                    thumbnail_data.to_vec(),
+                )
+                .ok_or(LibrawError::EncodingError)?,
+            )),
+            _ => Err(LibrawError::UnsupportedThumbnail),
+        }
+    }
+
+    /// The unpacked thumbnail if it is a jpeg
+    pub(crate) fn thumbnail_jpeg(&self) -> Option<&[u8]> {
+        let thumbnail = self.thumbnail();
+        if thumbnail.thumb.is_null()
+            || !matches!(
+                ThumbnailFormat::from(thumbnail.tformat),
+                ThumbnailFormat::Jpeg
+            )
+        {
+            return None;
+        }
+        Some(unsafe {
+            std::slice::from_raw_parts(thumbnail.thumb as *const u8, thumbnail.tlength as usize)
+        })
+    }
+
+    /// Process the raw data into an upright 8 or 16 bit bitmap
+    ///
+    /// Returns the pixels with their width, height and color type, 16 bit samples are in native
+    /// byte order
+    pub(crate) fn processed_pixels(
+        &mut self,
+        resize: Option<&ResizeSpec>,
+    ) -> Result<(Vec<u8>, u32, u32, ColorType), LibrawError> {
+        self.unpack_once()?;
+        self.dcraw_process()?;
+        let flip = self.sizes().flip;
+        let processed = self.dcraw_process_make_mem_image()?;
+        let (pixels, width, height, colortype) =
+            match processed.type_() {
+                ImageFormat::Bitmap => {
+                    let colortype = processed.color_type()?;
+                    let (width, height) = (processed.width(), processed.height());
+                    // The bytes of 16 bit images are kept in native order
+                    let pixels = processed.as_slice_u8().to_vec();
+                    (pixels, width, height, colortype)
+                }
+                ImageFormat::Jpeg => {
+                    // In memory jpegs aren't rotated by libraw
+                    let rgb = image::DynamicImage::try_from(processed)?.into_rgb8();
+                    let (width, height) = (rgb.width() as usize, rgb.height() as usize);
+                    let (pixels, width, height) = Orientation::from(Flip::from(flip))
+                        .apply_to_pixels(rgb.as_raw(), width, height, 3);
+                    (pixels, width as u32, height as u32, ColorType::Rgb8)
+                }
+            };
+
+        match resize {
+            Some(resize) => {
+                let (pixels, width, height) = resize.resize(pixels, width, height, colortype)?;
+                Ok((pixels, width, height, colortype))
+            }
+            None => Ok((pixels, width, height, colortype)),
+        }
+    }
+
+    /// Decode the embedded thumbnail into rgb pixels without applying the orientation
+    ///
+    /// The resize is applied to the upright image so width and height are swapped for thumbnails
+    /// that are rotated by 90 degrees. Returns the pixels with their width, height and orientation
+    pub(crate) fn thumbnail_pixels(
+        &mut self,
+        resize: Option<&ResizeSpec>,
+    ) -> Result<(Vec<u8>, u32, u32, Orientation), LibrawError> {
+        let rgb = self.thumbnail_image()?.into_rgb8();
+        let orientation = Orientation::from(Flip::from(self.sizes().flip));
+        let (width, height) = (rgb.width(), rgb.height());
+        match resize {
+            Some(resize) => {
+                let resize = match orientation.swaps_dimensions() {
+                    true => resize.transposed(),
+                    false => *resize,
+                };
+                let (pixels, width, height) =
+                    resize.resize(rgb.into_raw(), width, height, ColorType::Rgb8)?;
+                Ok((pixels, width, height, orientation))
+            }
+            None => Ok((rgb.into_raw(), width, height, orientation)),
+        }
+    }
+
+    /// Same as to_jpeg_oriented with the orientation baked, resized according to `resize`
+    pub fn to_jpeg_resized(
+        &mut self,
+        quality: u8,
+        resize: &ResizeSpec,
+    ) -> Result<Vec<u8>, LibrawError> {
+        let (pixels, width, height, colortype) = self.processed_pixels(Some(resize))?;
+        let jpeg = self.encode_jpeg(&pixels, width, height, colortype, Some(quality))?;
+        self.set_jpeg_orientation(jpeg, Orientation::NONE)
+    }
+
+    /// Re-encode the embedded thumbnail resized according to `resize`, the EXIF, XMP and ICC
+    /// profile of the thumbnail are kept
+    pub fn get_jpeg_resized(
+        &mut self,
+        quality: u8,
+        resize: &ResizeSpec,
+    ) -> Result<Vec<u8>, LibrawError> {
+        let (pixels, width, height, orientation) = self.thumbnail_pixels(Some(resize))?;
+        let mut jpeg = self.encode_jpeg(&pixels, width, height, ColorType::Rgb8, Some(quality))?;
+        if let Some(original) = self.thumbnail_jpeg() {
+            jpeg = jpeg::copy_metadata(original, jpeg)?;
+        }
+        self.set_jpeg_orientation(jpeg, orientation)
+    }
+
     /// Get the jpeg without rotation
     pub fn get_jpeg_no_rotation(&mut self) -> Result<Vec<u8>, LibrawError> {
         // First check if unpack_thumb has already been called.
@@ -393,12 +649,12 @@ impl Processor {
             ThumbnailFormat::Bitmap => {
                 // Since this is a bitmap we have to generate the thumbnail from the rgb data from
                 // here
-                let mut jpeg = Vec::new();
-                image::codecs::jpeg::JpegEncoder::new(&mut jpeg).encode(
+                let jpeg = self.encode_jpeg(
                     thumbnail_data,
                     thumbnail.twidth as u32,
                     thumbnail.theight as u32,
                     image::ColorType::Rgb8,
+                    None,
                 )?;
                 Ok(jpeg)
             }
@@ -409,6 +665,9 @@ impl Processor {
     /// This will generate a thumbnail from the raw buffer
     /// It is **slower** than jpeg_thumb since it will unpack the rgb data
     ///
+    /// Bitmaps are rotated by `dcraw_make_mem_image` and tagged with orientation 1, in memory
+    /// jpegs keep their pixels and are tagged with `sizes.flip`
+    ///
     /// resize_jpeg if it is true and the underlying data is a jpeg file then it will be resized to
     /// match the provided resolution
     /// Consider ~100ms
@@ -417,9 +676,7 @@ impl Processor {
 
         // Now check if libraw_unpack has been called already
         // If it has been call inner.image shouldn't be null
-        if unsafe { self.inner.as_ref().image.is_null() } {
-            self.unpack()?;
-        }
+        self.unpack_once()?;
         self.dcraw_process()?;
         let flip = self.sizes().flip;
         let _processed = self.dcraw_process_make_mem_image()?;
@@ -439,25 +696,126 @@ impl Processor {
                     16 => image::ColorType::Rgb16,
                     _ => return Err(LibrawError::InvalidColor(processed.bits)),
                 };
-                let mut jpeg = Vec::new();
-                image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, quality).encode(
+                let jpeg = self.encode_jpeg(
                     _processed.as_slice(),
                     processed.width as u32,
                     processed.height as u32,
                     colortype,
+                    Some(quality),
                 )?;
-                let jpeg = Orientation::from(Flip::from(flip)).add_to(jpeg)?;
+                // dcraw_make_mem_image already rotated the pixels
+                let jpeg = self.set_jpeg_orientation(jpeg, Orientation::NONE)?;
                 Ok(jpeg)
             }
             ImageFormat::Jpeg => {
                 // structure contain in-memory image of JPEG file. Only type, data_size and data fields are valid (and nonzero);
                 let jpeg = _processed.as_slice().to_vec();
-                let jpeg = Orientation::from(Flip::from(flip)).add_to(jpeg)?;
+                let jpeg = self.set_jpeg_orientation(jpeg, Orientation::from(Flip::from(flip)))?;
                 Ok(jpeg)
             }
         }
     }
 
+    /// Same as to_jpeg, but with `bake_orientation` the pixels are stored upright and the
+    /// orientation tag is set to 1
+    ///
+    /// `dcraw_make_mem_image` already rotates bitmaps according to `sizes.flip`, in memory jpegs
+    /// are decoded and rotated here
+    pub fn to_jpeg_oriented(
+        &mut self,
+        quality: u8,
+        bake_orientation: bool,
+    ) -> Result<Vec<u8>, LibrawError> {
+        if !bake_orientation {
+            return self.to_jpeg(quality);
+        }
+        self.unpack_once()?;
+        self.dcraw_process()?;
+        let flip = self.sizes().flip;
+        let processed = self.dcraw_process_make_mem_image()?;
+        let orientation = match processed.type_() {
+            ImageFormat::Bitmap => Orientation::NONE,
+            ImageFormat::Jpeg => Orientation::from(Flip::from(flip)),
+        };
+        let dynimg = image::DynamicImage::try_from(processed)?;
+        let jpeg = self.encode_baked(dynimg, orientation, quality)?;
+        self.set_jpeg_orientation(jpeg, Orientation::NONE)
+    }
+
+    /// Set the orientation tag keeping the EXIF, XMP and ICC profile already in the jpeg, if it
+    /// doesn't have any EXIF it is created from the file's metadata
+    fn set_jpeg_orientation(
+        &self,
+        jpeg: Vec<u8>,
+        orientation: Orientation,
+    ) -> Result<Vec<u8>, LibrawError> {
+        jpeg::set_orientation(
+            jpeg,
+            orientation,
+            Some(&metadata::CameraMetadata::from(self)),
+        )
+    }
+
+    /// Encode as an 8 bit jpeg with the pixels rotated by orientation
+    fn encode_baked(
+        &self,
+        dynimg: image::DynamicImage,
+        orientation: Orientation,
+        quality: u8,
+    ) -> Result<Vec<u8>, LibrawError> {
+        let (width, height) = (dynimg.width() as usize, dynimg.height() as usize);
+        let (pixels, width, height, colortype) = if dynimg.color().has_color() {
+            let (pixels, w, h) =
+                orientation.apply_to_pixels(dynimg.into_rgb8().as_raw(), width, height, 3);
+            (pixels, w, h, image::ColorType::Rgb8)
+        } else {
+            let (pixels, w, h) =
+                orientation.apply_to_pixels(dynimg.into_luma8().as_raw(), width, height, 1);
+            (pixels, w, h, image::ColorType::L8)
+        };
+        self.encode_jpeg(
+            &pixels,
+            width as u32,
+            height as u32,
+            colortype,
+            Some(quality),
+        )
+    }
+
+    /// Use a different backend for every `to_jpeg*` / `jpeg*` method, the default is
+    /// [jpeg::TurboJpeg]
+    pub fn set_jpeg_encoder(&mut self, encoder: impl jpeg::JpegEncoder + 'static) {
+        self.jpeg_encoder = Arc::new(encoder);
+    }
+
+    /// The quality passed to each method replaces `options.quality`, it is only used for
+    /// thumbnails that have to be encoded by get_jpeg
+    pub fn set_jpeg_options(&mut self, options: jpeg::JpegOptions) {
+        self.jpeg_options = options;
+    }
+
+    pub fn jpeg_options(&self) -> &jpeg::JpegOptions {
+        &self.jpeg_options
+    }
+
+    /// Encode with the configured encoder, 16 bit images are reduced to 8 bits
+    fn encode_jpeg(
+        &self,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        colortype: image::ColorType,
+        quality: Option<u8>,
+    ) -> Result<Vec<u8>, LibrawError> {
+        let options = match quality {
+            Some(quality) => self.jpeg_options.with_quality(quality),
+            None => self.jpeg_options,
+        };
+        let (pixels, colortype) = jpeg::to_8bit(pixels, colortype)?;
+        self.jpeg_encoder
+            .encode(&pixels, width, height, colortype, &options)
+    }
+
     /// Get the original without any rotation
     pub fn to_jpeg_no_rotation(
         &mut self,
@@ -468,9 +826,7 @@ impl Processor {
 
         // Now check if libraw_unpack has been called already
         // If it has been call inner.image shouldn't be null
-        if unsafe { self.inner.as_ref().image.is_null() } {
-            self.unpack()?;
-        }
+        self.unpack_once()?;
         self.dcraw_process()?;
         let _processed = self.dcraw_process_make_mem_image()?;
         let processed = _processed.raw();
@@ -484,65 +840,22 @@ impl Processor {
 
         match ImageFormat::from(processed.type_) {
             ImageFormat::Bitmap => {
-                let (colortype, pixelformat, subsamp) = match (processed.colors, processed.bits) {
-                    (3, 8) => (
-                        image::ColorType::Rgb8,
-                        turbojpeg::PixelFormat::RGB,
-                        turbojpeg::Subsamp::Sub2x2,
-                    ),
-                    (3, 16) => (
-                        image::ColorType::Rgb16,
-                        turbojpeg::PixelFormat::RGB,
-                        turbojpeg::Subsamp::Sub2x2,
-                    ),
-                    (1, 8) => (
-                        image::ColorType::L8,
-                        turbojpeg::PixelFormat::GRAY,
-                        turbojpeg::Subsamp::Gray,
-                    ),
-                    (1, 16) => (
-                        image::ColorType::L16,
-                        turbojpeg::PixelFormat::GRAY,
-                        turbojpeg::Subsamp::Gray,
-                    ),
-                    _ => return Err(LibrawError::InvalidColor(processed.bits)),
-                };
-
+                let colortype = _processed.color_type()?;
                 let pixels = _processed.as_slice();
                 let width = processed.width as u32;
                 let height = processed.height as u32;
-                let mut jpeg = Vec::new();
 
-                let img = if let Some(expected_width) = expected_width {
-                    let (img, w, h) = Self::resize_image_buffer(
+                let jpeg = if let Some(expected_width) = expected_width {
+                    // Only the width is constrained
+                    let (pixels, w, h) = ResizeSpec::fit(expected_width, u32::MAX).resize(
                         pixels.to_vec(),
-                        width as u32,
-                        height as u32,
-                        expected_width,
+                        width,
+                        height,
                         colortype,
                     )?;
-
-                    let img = turbojpeg::Image {
-                        pixels: img.buffer(),
-                        width: w as usize,
-                        height: h as usize,
-                        pitch: w as usize * processed.colors as usize,
-                        format: pixelformat,
-                    };
-                    jpeg = turbojpeg::compress(img, quality as i32, subsamp)
-                        .map_err(|_| LibrawError::ResizingError)?
-                        .to_vec();
+                    self.encode_jpeg(&pixels, w, h, colortype, Some(quality))?
                 } else {
-                    let img = turbojpeg::Image {
-                        pixels: _processed.as_slice(),
-                        width: processed.width as usize,
-                        height: processed.height as usize,
-                        pitch: processed.width as usize * processed.colors as usize,
-                        format: pixelformat,
-                    };
-                    jpeg = turbojpeg::compress(img, quality as i32, subsamp)
-                        .map_err(|_| LibrawError::ResizingError)?
-                        .to_vec();
+                    self.encode_jpeg(pixels, width, height, colortype, Some(quality))?
                 };
                 Ok(jpeg)
             }
@@ -565,9 +878,7 @@ impl Processor {
     ) -> Result<Vec<u8>, LibrawError> {
         // Now check if libraw_unpack has been called already
         // If it has been call inner.image shouldn't be null
-        if unsafe { self.inner.as_ref().image.is_null() } {
-            self.unpack()?;
-        }
+        self.unpack_once()?;
         self.dcraw_process()?;
         let flip = self.sizes().flip;
         let _processed = self.dcraw_process_make_mem_image()?;
@@ -579,50 +890,34 @@ impl Processor {
         //         processed.data_size as usize,
         //     )
         // };
-        let res = resolution.into_resolution();
+        let resize = ResizeSpec::from(resolution.into_resolution());
         match ImageFormat::from(processed.type_) {
             ImageFormat::Bitmap => {
-                let mut jpeg = std::io::Cursor::new(Vec::new());
-                let dynimg = match processed.bits {
-                    8 => image::DynamicImage::from(
-                        image::ImageBuffer::<image::Rgb<u8>, Vec<u8>>::from_raw(
-                            processed.width.into(),
-                            processed.height.into(),
-                            _processed.as_slice().to_vec(),
-                        )
-                        .ok_or(LibrawError::EncodingError)?,
-                    ),
-                    16 => image::DynamicImage::from(
-                        image::ImageBuffer::<image::Rgb<u16>, Vec<u16>>::from_raw(
-                            processed.width.into(),
-                            processed.height.into(),
-                            _processed.as_slice().to_vec(),
-                        )
-                        .ok_or(LibrawError::EncodingError)?,
-                    ),
-                    _ => return Err(LibrawError::InvalidColor(processed.bits)),
-                };
-                dynimg.write_to(&mut jpeg, image::ImageOutputFormat::Jpeg(quality))?;
-                let jpeg = jpeg.into_inner();
-                let jpeg = Orientation::from(Flip::from(flip)).add_to(jpeg)?;
+                let colortype = _processed.color_type()?;
+                let (pixels, width, height) = resize.resize(
+                    _processed.as_slice().to_vec(),
+                    processed.width as u32,
+                    processed.height as u32,
+                    colortype,
+                )?;
+                let jpeg = self.encode_jpeg(&pixels, width, height, colortype, Some(quality))?;
+                // dcraw_make_mem_image already rotated the pixels
+                let jpeg = self.set_jpeg_orientation(jpeg, Orientation::NONE)?;
                 Ok(jpeg)
             }
             ImageFormat::Jpeg => {
                 // structure contain in-memory image of JPEG file. Only type, data_size and data fields are valid (and nonzero);
                 let mut jpeg = _processed.as_slice().to_vec();
                 if resize_jpeg {
-                    use image::io::Reader;
-                    use std::io::Cursor;
-                    let dynimg = Reader::new(&mut Cursor::new(jpeg.drain(..)))
-                        .with_guessed_format()?
-                        .decode()?
-                        .thumbnail(res.width, res.height);
-                    dynimg.write_to(
-                        &mut Cursor::new(&mut jpeg),
-                        image::ImageOutputFormat::Jpeg(quality),
-                    )?;
+                    let rgb = image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg)?
+                        .into_rgb8();
+                    let (width, height) = (rgb.width(), rgb.height());
+                    let (pixels, width, height) =
+                        resize.resize(rgb.into_raw(), width, height, ColorType::Rgb8)?;
+                    jpeg =
+                        self.encode_jpeg(&pixels, width, height, ColorType::Rgb8, Some(quality))?;
                 }
-                let jpeg = Orientation::from(Flip::from(flip)).add_to(jpeg)?;
+                let jpeg = self.set_jpeg_orientation(jpeg, Orientation::from(Flip::from(flip)))?;
                 Ok(jpeg)
             }
         }
@@ -639,6 +934,24 @@ impl Processor {
         }
     }
 
+    /// Same as jpeg, but with `bake_orientation` the pixels of the thumbnail or the processed
+    /// image are stored upright and the orientation tag is set to 1
+    pub fn jpeg_oriented(
+        &mut self,
+        quality: u8,
+        bake_orientation: bool,
+    ) -> Result<Vec<u8>, LibrawError> {
+        if !bake_orientation {
+            return self.jpeg(quality);
+        }
+        let jpg = self.get_jpeg_oriented(quality, true);
+        if jpg.is_ok() {
+            jpg
+        } else {
+            self.to_jpeg_oriented(quality, true)
+        }
+    }
+
     /// This will first try get_jpeg and then fallback to to_jpeg but won't modify any exif data
     /// in it
     /// Might take from 5 ~ 500 ms depending on the image
@@ -651,6 +964,7 @@ impl Processor {
             self.to_jpeg_no_rotation(quality, None)
         }
     }
+    #[deprecated(note = "use preview with a PreviewStrategy")]
     pub fn jpeg_min_size(&mut self, quality: u8, threshold: u32) -> Result<Vec<u8>, LibrawError> {
         let t = self.thumbnail();
         if u32::from(t.theight * t.twidth) > threshold && self.unpack_thumb().is_ok() {
@@ -660,6 +974,7 @@ impl Processor {
         }
     }
 
+    #[deprecated(note = "use preview with a PreviewStrategy")]
     pub fn jpeg_thumb_or_else_post_process<F: Fn(&mut Self) -> bool>(
         &mut self,
         quality: u8,
@@ -672,6 +987,7 @@ impl Processor {
         }
     }
 
+    #[deprecated(note = "use ResizeSpec::resize")]
     pub fn resize_image_buffer<'a>(
         rgb_buffer: Vec<u8>,
         img_width: u32,
@@ -720,10 +1036,7 @@ impl ProcessorBuilder {
     }
 
     pub fn build(self) -> Processor {
-        Processor {
-            inner: self.inner,
-            dropped: Arc::new(AtomicBool::new(false)),
-        }
+        Processor::from_inner(self.inner)
     }
 
     pub fn with_params<P: IntoIterator<Item = Params>>(mut self, params: P) -> Self {
@@ -934,6 +1247,7 @@ impl From<sys::LibRaw_thumbnail_formats> for ThumbnailFormat {
 }
 
 /// The format the raw file might be encoded in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[non_exhaustive]
 #[cfg_attr(all(windows, target_env = "msvc"), repr(i32))]
 #[cfg_attr(all(windows, target_env = "gnu"), repr(u32))]
@@ -967,6 +1281,24 @@ fn path_to_cstr(path: impl AsRef<Path>) -> Result<CString, std::ffi::NulError> {
     CString::new(path)
 }
 
+/// The color index at (row, col) of the bayer pattern packed in `idata.filters`
+///
+/// Same as the FC macro in dcraw / libraw, row and col are relative to the visible area
+#[inline]
+pub(crate) fn fcol(filters: u32, row: usize, col: usize) -> usize {
+    (filters >> ((((row << 1) & 14) | (col & 1)) << 1) & 3) as usize
+}
+
+/// The CFA color of a visible area position including the fuji xtrans pattern (filters 9)
+#[inline]
+pub(crate) fn cfa_color(idata: &sys::libraw_iparams_t, row: usize, col: usize) -> usize {
+    match idata.filters {
+        0 => 0,
+        9 => idata.xtrans[row % 6][col % 6] as usize,
+        f => fcol(f, row, col),
+    }
+}
+
 #[cfg(windows)]
 fn path_to_widestring(
     path: impl AsRef<Path>,
@@ -1023,173 +1355,3 @@ impl IntoResolution for [u16; 2] {
         }
     }
 }
-
-/// exif::Tag::Orientation
-/// Possible values 1,2,3,4,5,6,7,8
-/// 2, 5, 7 and 4 are mirrored images and not implemented
-#[derive(Debug, Eq, PartialEq)]
-pub struct Orientation(pub u8);
-impl PartialEq<u8> for Orientation {
-    fn eq(&self, other: &u8) -> bool {
-        &self.0 == other
-    }
-}
-impl PartialEq<Orientation> for u8 {
-    fn eq(&self, other: &Orientation) -> bool {
-        self == &other.0
-    }
-}
-
-impl std::ops::Add for Orientation {
-    type Output = Self;
-    fn add(self, rhs: Orientation) -> Self::Output {
-        Self(match (self.0, rhs.0) {
-            (1, o) => o,
-            (o, 1) => o,
-
-            (2, 2) => 1,
-            (2, 3) => 4,
-            (2, 4) => 3,
-            (2, 5) => 6,
-            (2, 6) => 5,
-            (2, 7) => 8,
-            (2, 8) => 7,
-
-            (3, 2) => 4,
-            (3, 3) => 1,
-            (3, 4) => 2,
-            (3, 5) => 7,
-            (3, 6) => 8,
-            (3, 7) => 5,
-            (3, 8) => 6,
-
-            (4, 2) => 3,
-            (4, 3) => 2,
-            (4, 4) => 1,
-            (4, 5) => 8,
-            (4, 6) => 7,
-            (4, 7) => 6,
-            (4, 8) => 5,
-
-            (5, 2) => 8,
-            (5, 3) => 7,
-            (5, 4) => 6,
-            (5, 5) => 1,
-            (5, 6) => 4,
-            (5, 7) => 3,
-            (5, 8) => 2,
-
-            (6, 2) => 7,
-            (6, 3) => 8,
-            (6, 4) => 5,
-            (6, 5) => 2,
-            (6, 6) => 3,
-            (6, 7) => 4,
-            (6, 8) => 1,
-
-            (7, 2) => 6,
-            (7, 3) => 5,
-            (7, 4) => 8,
-            (7, 5) => 3,
-            (7, 6) => 2,
-            (7, 7) => 1,
-            (7, 8) => 4,
-
-            (8, 2) => 5,
-            (8, 3) => 6,
-            (8, 4) => 7,
-            (8, 5) => 4,
-            (8, 6) => 1,
-            (8, 7) => 2,
-            (8, 8) => 3,
-
-            (_, _) => 1,
-        })
-    }
-}
-impl std::ops::Neg for Orientation {
-    type Output = Self;
-    fn neg(self) -> Self::Output {
-        Self(match self.0 {
-            1 => 1,
-            2 => 2,
-            3 => 3,
-            4 => 4,
-            5 => 5,
-            6 => 8,
-            7 => 7,
-            8 => 6,
-            o => o,
-        })
-    }
-}
-impl Orientation {
-    pub const NONE: Self = Self(1);
-    pub const CW180: Self = Self(3);
-    pub const CW90: Self = Self(6);
-    pub const CW270: Self = Self(8);
-    pub const CCW90: Self = Self(8);
-
-    #[cfg(feature = "jpeg")]
-    pub fn add_to(self, mut buffer: Vec<u8>) -> Result<Vec<u8>, LibrawError> {
-        use img_parts::ImageEXIF;
-        if self.0 > 8 {
-            return Err(
-                std::io::Error::new(std::io::ErrorKind::Other, "Flip greater than 8").into(),
-            );
-        }
-
-        let mut jpeg =
-            img_parts::jpeg::Jpeg::from_bytes(img_parts::Bytes::from_iter(buffer.drain(..)))?;
-        Orientation::__remove_xmp(&mut jpeg);
-        jpeg.set_exif(Some(Self::exif_data_with_orientation(self.0).into()));
-        jpeg.encoder().write_to(&mut buffer)?;
-        Ok(buffer)
-    }
-
-    #[cfg(feature = "jpeg")]
-    fn __remove_xmp(jpeg: &mut img_parts::jpeg::Jpeg) {
-        jpeg.segments_mut().retain(|segment| {
-            !(segment.marker() == 0xe1 && segment.contents().starts_with(b"http://ns.adobe.com/"))
-        });
-    }
-
-    /// This encodes the orientation into a raw exif container data
-    #[cfg(feature = "jpeg")]
-    fn exif_data_with_orientation(o: u8) -> Vec<u8> {
-        vec![
-            0x4d, 0x4d, 0x0, 0x2a, 0x0, 0x0, 0x0, 0x8, 0x0, 0x1, 0x1, 0x12, 0x0, 0x3, 0x0, 0x0,
-            0x0, 0x1, 0x0, o, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
-        ]
-    }
-}
-
-/// libraw_data_t.sizes.flip
-/// Possible values 0, 3, 5, 6
-#[derive(Debug, Eq, PartialEq)]
-pub struct Flip(pub i32);
-impl Flip {
-    pub const NONE: Self = Self(0);
-    pub const CW180: Self = Self(3);
-    pub const CW90: Self = Self(6);
-    pub const CW270: Self = Self(5);
-    pub const CCW90: Self = Self(5);
-}
-
-impl From<i32> for Flip {
-    fn from(flip: i32) -> Self {
-        Self(flip)
-    }
-}
-
-impl From<Flip> for Orientation {
-    fn from(flip: Flip) -> Self {
-        match flip {
-            Flip::NONE => Orientation::NONE,
-            Flip::CW90 => Orientation::CW90,
-            Flip::CW180 => Orientation::CW180,
-            Flip::CW270 => Orientation::CW270,
-            _ => Orientation::NONE,
-        }
-    }
-}
@@ -0,0 +1,130 @@
+//! A small 3x3 matrix type for color conversions
+use std::ops::{Index, Mul};
+
+/// Row major 3x3 matrix
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Matrix3(pub [[f64; 3]; 3]);
+
+impl Matrix3 {
+    pub const IDENTITY: Self = Self([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+
+    /// The Bradford cone response matrix
+    pub const BRADFORD: Self = Self([
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ]);
+
+    pub fn from_f32(m: [[f32; 3]; 3]) -> Self {
+        Self(m.map(|row| row.map(f64::from)))
+    }
+
+    pub fn diagonal(d: [f64; 3]) -> Self {
+        Self([[d[0], 0.0, 0.0], [0.0, d[1], 0.0], [0.0, 0.0, d[2]]])
+    }
+
+    pub fn transpose(&self) -> Self {
+        let m = &self.0;
+        Self([
+            [m[0][0], m[1][0], m[2][0]],
+            [m[0][1], m[1][1], m[2][1]],
+            [m[0][2], m[1][2], m[2][2]],
+        ])
+    }
+
+    pub fn determinant(&self) -> f64 {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Returns None if the matrix is singular
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if det.abs() < f64::EPSILON {
+            return None;
+        }
+        let m = &self.0;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        Some(Self([
+            [
+                cofactor(1, 2, 1, 2) / det,
+                -cofactor(0, 2, 1, 2) / det,
+                cofactor(0, 1, 1, 2) / det,
+            ],
+            [
+                -cofactor(1, 2, 0, 2) / det,
+                cofactor(0, 2, 0, 2) / det,
+                -cofactor(0, 1, 0, 2) / det,
+            ],
+            [
+                cofactor(1, 2, 0, 1) / det,
+                -cofactor(0, 2, 0, 1) / det,
+                cofactor(0, 1, 0, 1) / det,
+            ],
+        ]))
+    }
+
+    pub fn mul_vec(&self, v: [f64; 3]) -> [f64; 3] {
+        self.0
+            .map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+    }
+
+    /// Scale every row so that it sums to 1 (maps white to white)
+    pub fn normalize_rows(&self) -> Self {
+        Self(self.0.map(|row| {
+            let sum: f64 = row.iter().sum();
+            if sum.abs() < f64::EPSILON {
+                row
+            } else {
+                row.map(|v| v / sum)
+            }
+        }))
+    }
+
+    /// Bradford chromatic adaptation from one XYZ white point to another
+    pub fn bradford(src_white: [f64; 3], dst_white: [f64; 3]) -> Self {
+        let src = Self::BRADFORD.mul_vec(src_white);
+        let dst = Self::BRADFORD.mul_vec(dst_white);
+        let scale = Self::diagonal([dst[0] / src[0], dst[1] / src[1], dst[2] / src[2]]);
+        Self::BRADFORD
+            .inverse()
+            .expect("Bradford matrix is invertible")
+            * scale
+            * Self::BRADFORD
+    }
+}
+
+impl Default for Matrix3 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Index<usize> for Matrix3 {
+    type Output = [f64; 3];
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.0[index]
+    }
+}
+
+impl Mul for Matrix3 {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut out = [[0.0; 3]; 3];
+        for (r, row) in out.iter_mut().enumerate() {
+            for (c, v) in row.iter_mut().enumerate() {
+                *v = (0..3).map(|k| self.0[r][k] * rhs.0[k][c]).sum();
+            }
+        }
+        Self(out)
+    }
+}
+
+/// Convert a CIE xy chromaticity to XYZ with Y = 1
+pub fn xy_to_xyz(x: f64, y: f64) -> [f64; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
@@ -0,0 +1,239 @@
+//! Camera metadata collected from libraw (`idata`, `imgother` and `lensinfo`) which can be
+//! serialized into an EXIF block for the images we write
+use serde::{Deserialize, Serialize};
+
+use crate::ifd::{Ifd, Value, EXIF_IFD, GPS_IFD};
+use crate::traits::LRString;
+use crate::Processor;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraMetadata {
+    pub make: String,
+    pub model: String,
+    pub software: String,
+    pub artist: String,
+    pub description: String,
+    pub iso_speed: f32,
+    /// Exposure time in seconds
+    pub shutter: f32,
+    pub aperture: f32,
+    pub focal_len: f32,
+    pub focal_len_35mm: u16,
+    pub lens_make: String,
+    pub lens: String,
+    pub lens_serial: String,
+    /// Min focal, max focal, max aperture at min focal and max aperture at max focal
+    pub lens_specification: [f32; 4],
+    /// Capture time as libraw computes it, the camera's date and time read as host local time
+    pub timestamp: i64,
+    pub gps: Option<GpsInfo>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GpsInfo {
+    /// Degrees, minutes and seconds
+    pub latitude: [f32; 3],
+    /// 'N' or 'S'
+    pub latitude_ref: char,
+    /// Degrees, minutes and seconds
+    pub longitude: [f32; 3],
+    /// 'E' or 'W'
+    pub longitude_ref: char,
+    /// Meters
+    pub altitude: f32,
+    /// 0 above sea level, 1 below
+    pub altitude_ref: u8,
+    /// UTC hours, minutes and seconds
+    pub timestamp: [f32; 3],
+}
+
+impl From<&Processor> for CameraMetadata {
+    fn from(processor: &Processor) -> Self {
+        let idata = processor.idata();
+        let other = processor.imgother();
+        let lens = processor.lensinfo();
+        let gps = &other.parsed_gps;
+        Self {
+            make: idata.make.as_ascii().to_string(),
+            model: idata.model.as_ascii().to_string(),
+            software: idata.software.as_ascii().to_string(),
+            artist: other.artist.as_ascii().to_string(),
+            description: other.desc.as_ascii().to_string(),
+            iso_speed: other.iso_speed,
+            shutter: other.shutter,
+            aperture: other.aperture,
+            focal_len: other.focal_len,
+            focal_len_35mm: lens.FocalLengthIn35mmFormat,
+            lens_make: lens.LensMake.as_ascii().to_string(),
+            lens: lens.Lens.as_ascii().to_string(),
+            lens_serial: lens.LensSerial.as_ascii().to_string(),
+            lens_specification: [
+                lens.MinFocal,
+                lens.MaxFocal,
+                lens.MaxAp4MinFocal,
+                lens.MaxAp4MaxFocal,
+            ],
+            #[allow(clippy::unnecessary_cast)] // time_t is 32 bits on some targets
+            timestamp: other.timestamp as i64,
+            gps: (gps.gpsparsed != 0).then_some(GpsInfo {
+                latitude: gps.latitude,
+                latitude_ref: gps.latref as u8 as char,
+                longitude: gps.longitude,
+                longitude_ref: gps.longref as u8 as char,
+                altitude: gps.altitude,
+                altitude_ref: gps.altref as u8,
+                timestamp: gps.gpstimestamp,
+            }),
+        }
+    }
+}
+
+impl CameraMetadata {
+    /// Serialize into a TIFF structure that can be used as the EXIF block of a jpeg (after the
+    /// Exif\0\0 header), a png eXIf chunk or a webp / avif file
+    pub fn to_exif(&self) -> Vec<u8> {
+        self.tiff_ifd().to_tiff()
+    }
+
+    /// IFD0 with the EXIF and GPS sub IFDs attached
+    pub(crate) fn tiff_ifd(&self) -> Ifd {
+        let mut ifd = Ifd::new();
+        let ascii = |ifd: &mut Ifd, tag: u16, s: &str| {
+            if !s.is_empty() {
+                ifd.set(tag, Value::Ascii(s.to_string()));
+            }
+        };
+        ascii(&mut ifd, 0x010e, &self.description);
+        ascii(&mut ifd, 0x010f, &self.make);
+        ascii(&mut ifd, 0x0110, &self.model);
+        ascii(&mut ifd, 0x0131, &self.software);
+        ascii(&mut ifd, 0x013b, &self.artist);
+
+        let mut exif = Ifd::new();
+        exif.set(0x9000, Value::Undefined(b"0231".to_vec()));
+        if self.timestamp > 0 {
+            let datetime = exif_datetime(self.timestamp);
+            ifd.set(0x0132, Value::Ascii(datetime.clone()));
+            exif.set(0x9003, Value::Ascii(datetime.clone()));
+            exif.set(0x9004, Value::Ascii(datetime));
+        }
+        if self.shutter > 0.0 {
+            exif.set(
+                0x829a,
+                Value::Rational(vec![Value::rational(self.shutter as f64)]),
+            );
+        }
+        if self.aperture > 0.0 {
+            exif.set(
+                0x829d,
+                Value::Rational(vec![Value::rational(self.aperture as f64)]),
+            );
+        }
+        if self.iso_speed > 0.0 {
+            exif.set(
+                0x8827,
+                Value::Short(vec![self.iso_speed.min(65535.0) as u16]),
+            );
+        }
+        if self.focal_len > 0.0 {
+            exif.set(
+                0x920a,
+                Value::Rational(vec![Value::rational(self.focal_len as f64)]),
+            );
+        }
+        if self.focal_len_35mm > 0 {
+            exif.set(0xa405, Value::Short(vec![self.focal_len_35mm]));
+        }
+        if self.lens_specification[0] > 0.0 {
+            exif.set(
+                0xa432,
+                Value::Rational(
+                    self.lens_specification
+                        .map(|v| Value::rational(v as f64))
+                        .to_vec(),
+                ),
+            );
+        }
+        ascii(&mut exif, 0xa433, &self.lens_make);
+        ascii(&mut exif, 0xa434, &self.lens);
+        ascii(&mut exif, 0xa435, &self.lens_serial);
+        ifd.set(EXIF_IFD, Value::Ifd(vec![exif]));
+
+        if let Some(gps) = &self.gps {
+            ifd.set(GPS_IFD, Value::Ifd(vec![gps.ifd()]));
+        }
+        ifd
+    }
+}
+
+impl GpsInfo {
+    fn ifd(&self) -> Ifd {
+        let dms = |v: [f32; 3]| Value::Rational(v.map(|v| Value::rational(v as f64)).to_vec());
+        let mut ifd = Ifd::new();
+        ifd.set(0x0000, Value::Byte(vec![2, 3, 0, 0]));
+        if self.latitude_ref.is_ascii_alphabetic() {
+            ifd.set(0x0001, Value::Ascii(self.latitude_ref.to_string()));
+        }
+        ifd.set(0x0002, dms(self.latitude));
+        if self.longitude_ref.is_ascii_alphabetic() {
+            ifd.set(0x0003, Value::Ascii(self.longitude_ref.to_string()));
+        }
+        ifd.set(0x0004, dms(self.longitude));
+        ifd.set(0x0005, Value::Byte(vec![self.altitude_ref]));
+        ifd.set(
+            0x0006,
+            Value::Rational(vec![Value::rational(self.altitude as f64)]),
+        );
+        ifd.set(0x0007, dms(self.timestamp));
+        ifd
+    }
+}
+
+/// Format a timestamp from libraw as an EXIF "YYYY:MM:DD HH:MM:SS" string
+///
+/// libraw turns the date and time of the file into a timestamp with `mktime`, which reads it as
+/// host local time, so it is converted back with the local time zone to get the camera's clock
+pub fn exif_datetime(timestamp: i64) -> String {
+    #[allow(clippy::unnecessary_cast)] // time_t is 32 bits on some targets
+    let time = timestamp as libc::time_t;
+    let mut tm: libc::tm = unsafe { core::mem::zeroed() };
+    #[cfg(unix)]
+    let local = !unsafe { libc::localtime_r(&time, &mut tm) }.is_null();
+    #[cfg(windows)]
+    let local = unsafe { libc::localtime_s(&mut tm, &time) } == 0;
+    match local {
+        true => format!(
+            "{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min,
+            tm.tm_sec
+        ),
+        false => offset_datetime(timestamp, 0),
+    }
+}
+
+/// Format a unix timestamp as an EXIF date and time in a time zone `offset` seconds east of UTC
+pub fn offset_datetime(timestamp: i64, offset: i64) -> String {
+    let timestamp = timestamp + offset;
+    let days = timestamp.div_euclid(86400);
+    let secs = timestamp.rem_euclid(86400);
+    // Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}:{month:02}:{day:02} {:02}:{:02}:{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
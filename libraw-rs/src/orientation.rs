@@ -1 +1,277 @@
+//! The EXIF orientation tag and libraw's flip values
+//!
+//! Both describe one of the 8 symmetries of a rectangle (the dihedral group D4), 4 rotations and
+//! 4 mirrored rotations.
+#[cfg(feature = "jpeg")]
+use crate::LibrawError;
 
+/// exif::Tag::Orientation
+/// Possible values 1,2,3,4,5,6,7,8
+/// 2, 4, 5 and 7 are the mirrored orientations
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Orientation(pub u8);
+impl PartialEq<u8> for Orientation {
+    fn eq(&self, other: &u8) -> bool {
+        &self.0 == other
+    }
+}
+impl PartialEq<Orientation> for u8 {
+    fn eq(&self, other: &Orientation) -> bool {
+        self == &other.0
+    }
+}
+
+/// Composition of two orientations, `a + b` is the same as applying `b` and then `a`
+impl std::ops::Add for Orientation {
+    type Output = Self;
+    fn add(self, rhs: Orientation) -> Self::Output {
+        Self(match (self.0, rhs.0) {
+            (1, o) => o,
+            (o, 1) => o,
+
+            (2, 2) => 1,
+            (2, 3) => 4,
+            (2, 4) => 3,
+            (2, 5) => 6,
+            (2, 6) => 5,
+            (2, 7) => 8,
+            (2, 8) => 7,
+
+            (3, 2) => 4,
+            (3, 3) => 1,
+            (3, 4) => 2,
+            (3, 5) => 7,
+            (3, 6) => 8,
+            (3, 7) => 5,
+            (3, 8) => 6,
+
+            (4, 2) => 3,
+            (4, 3) => 2,
+            (4, 4) => 1,
+            (4, 5) => 8,
+            (4, 6) => 7,
+            (4, 7) => 6,
+            (4, 8) => 5,
+
+            (5, 2) => 8,
+            (5, 3) => 7,
+            (5, 4) => 6,
+            (5, 5) => 1,
+            (5, 6) => 4,
+            (5, 7) => 3,
+            (5, 8) => 2,
+
+            (6, 2) => 7,
+            (6, 3) => 8,
+            (6, 4) => 5,
+            (6, 5) => 2,
+            (6, 6) => 3,
+            (6, 7) => 4,
+            (6, 8) => 1,
+
+            (7, 2) => 6,
+            (7, 3) => 5,
+            (7, 4) => 8,
+            (7, 5) => 3,
+            (7, 6) => 2,
+            (7, 7) => 1,
+            (7, 8) => 4,
+
+            (8, 2) => 5,
+            (8, 3) => 6,
+            (8, 4) => 7,
+            (8, 5) => 4,
+            (8, 6) => 1,
+            (8, 7) => 2,
+            (8, 8) => 3,
+
+            (_, _) => 1,
+        })
+    }
+}
+/// The inverse orientation, `o + -o` is always [Orientation::NONE]
+impl std::ops::Neg for Orientation {
+    type Output = Self;
+    fn neg(self) -> Self::Output {
+        Self(match self.0 {
+            1 => 1,
+            2 => 2,
+            3 => 3,
+            4 => 4,
+            5 => 5,
+            6 => 8,
+            7 => 7,
+            8 => 6,
+            o => o,
+        })
+    }
+}
+impl Orientation {
+    pub const NONE: Self = Self(1);
+    pub const CW180: Self = Self(3);
+    pub const CW90: Self = Self(6);
+    pub const CW270: Self = Self(8);
+    pub const CCW90: Self = Self(8);
+    pub const MIRROR_HORIZONTAL: Self = Self(2);
+    pub const MIRROR_VERTICAL: Self = Self(4);
+    /// Mirrored along the top left to bottom right diagonal
+    pub const TRANSPOSE: Self = Self(5);
+    /// Mirrored along the top right to bottom left diagonal
+    pub const TRANSVERSE: Self = Self(7);
+
+    /// Returns None for values outside 1..=8
+    pub fn new(orientation: u8) -> Option<Self> {
+        matches!(orientation, 1..=8).then_some(Self(orientation))
+    }
+
+    pub fn is_mirrored(&self) -> bool {
+        matches!(self.0, 2 | 4 | 5 | 7)
+    }
+
+    /// Whether width and height are swapped when the orientation is applied
+    pub fn swaps_dimensions(&self) -> bool {
+        matches!(self.0, 5..=8)
+    }
+
+    /// From `thumbs_list.thumblist[n].tflip` which uses the same encoding as `sizes.flip`
+    /// and is 0xffff when the thumbnail orientation is unknown
+    pub fn from_tflip(tflip: u16) -> Option<Self> {
+        match tflip {
+            0..=7 => Some(Flip(tflip.into()).into()),
+            _ => None,
+        }
+    }
+
+    /// Rotate / mirror an interleaved pixel buffer so it displays correctly without the EXIF tag
+    ///
+    /// Returns the new buffer with it's width and height (swapped for orientations 5 to 8)
+    pub fn apply_to_pixels<T: Copy>(
+        self,
+        pixels: &[T],
+        width: usize,
+        height: usize,
+        channels: usize,
+    ) -> (Vec<T>, usize, usize) {
+        // Same as dcraw's flip_index, every output pixel is read from the transformed position
+        let flip = Flip::from(self).0;
+        let (out_width, out_height) = match flip & 4 {
+            0 => (width, height),
+            _ => (height, width),
+        };
+        let mut out = Vec::with_capacity(pixels.len());
+        for row in 0..out_height {
+            for col in 0..out_width {
+                let (mut src_row, mut src_col) = match flip & 4 {
+                    0 => (row, col),
+                    _ => (col, row),
+                };
+                if flip & 2 != 0 {
+                    src_row = height - 1 - src_row;
+                }
+                if flip & 1 != 0 {
+                    src_col = width - 1 - src_col;
+                }
+                let offset = (src_row * width + src_col) * channels;
+                out.extend_from_slice(&pixels[offset..offset + channels]);
+            }
+        }
+        (out, out_width, out_height)
+    }
+
+    /// Replace the EXIF of a jpeg with one that only has this orientation and remove the XMP
+    ///
+    /// Use [crate::jpeg::set_orientation] to keep the rest of the metadata
+    #[cfg(feature = "jpeg")]
+    pub fn add_to(self, mut buffer: Vec<u8>) -> Result<Vec<u8>, LibrawError> {
+        use img_parts::ImageEXIF;
+        if self.0 > 8 {
+            return Err(
+                std::io::Error::new(std::io::ErrorKind::Other, "Flip greater than 8").into(),
+            );
+        }
+
+        let mut jpeg =
+            img_parts::jpeg::Jpeg::from_bytes(img_parts::Bytes::from_iter(buffer.drain(..)))?;
+        Orientation::__remove_xmp(&mut jpeg);
+        jpeg.set_exif(Some(Self::exif_data_with_orientation(self.0).into()));
+        jpeg.encoder().write_to(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    #[cfg(feature = "jpeg")]
+    fn __remove_xmp(jpeg: &mut img_parts::jpeg::Jpeg) {
+        jpeg.segments_mut().retain(|segment| {
+            !(segment.marker() == 0xe1 && segment.contents().starts_with(b"http://ns.adobe.com/"))
+        });
+    }
+
+    /// This encodes the orientation into a raw exif container data
+    #[cfg(feature = "jpeg")]
+    fn exif_data_with_orientation(o: u8) -> Vec<u8> {
+        vec![
+            0x4d, 0x4d, 0x0, 0x2a, 0x0, 0x0, 0x0, 0x8, 0x0, 0x1, 0x1, 0x12, 0x0, 0x3, 0x0, 0x0,
+            0x0, 0x1, 0x0, o, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+        ]
+    }
+}
+
+/// libraw_data_t.sizes.flip
+/// Possible values 0 to 7, the bits are applied in the order 4 (transpose), 2 (flip rows) and 1
+/// (flip columns)
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Flip(pub i32);
+impl Flip {
+    pub const NONE: Self = Self(0);
+    pub const CW180: Self = Self(3);
+    pub const CW90: Self = Self(6);
+    pub const CW270: Self = Self(5);
+    pub const CCW90: Self = Self(5);
+    pub const MIRROR_HORIZONTAL: Self = Self(1);
+    pub const MIRROR_VERTICAL: Self = Self(2);
+    pub const TRANSPOSE: Self = Self(4);
+    pub const TRANSVERSE: Self = Self(7);
+}
+
+impl From<i32> for Flip {
+    fn from(flip: i32) -> Self {
+        Self(flip)
+    }
+}
+
+/// EXIF orientation for every flip value, the inverse of dcraw's `"50132467"[orientation & 7]`
+const FLIP_TO_ORIENTATION: [u8; 8] = [1, 2, 4, 3, 5, 8, 6, 7];
+
+impl From<Flip> for Orientation {
+    fn from(flip: Flip) -> Self {
+        match flip.0 {
+            f @ 0..=7 => Orientation(FLIP_TO_ORIENTATION[f as usize]),
+            _ => Orientation::NONE,
+        }
+    }
+}
+
+impl From<Orientation> for Flip {
+    fn from(orientation: Orientation) -> Self {
+        match orientation.0 {
+            o @ 1..=8 => Flip(b"50132467"[o as usize & 7] as i32 - b'0' as i32),
+            _ => Flip::NONE,
+        }
+    }
+}
+
+/// The value of EXIF tag 0x0112
+impl TryFrom<u16> for Orientation {
+    type Error = u16;
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            1..=8 => Ok(Self(value as u8)),
+            v => Err(v),
+        }
+    }
+}
+
+impl From<Orientation> for u16 {
+    fn from(orientation: Orientation) -> Self {
+        orientation.0.into()
+    }
+}
@@ -0,0 +1,396 @@
+//! Merge the sub-frames of pixel shift raws (Pentax, Sony) into a full rgb image without
+//! demosaicing.
+//!
+//! LibRaw only decodes a single frame at a time (selected with `rawparams.shot_select`) so every
+//! frame is unpacked in it's own Processor and combined here using the bayer pattern from
+//! `idata.filters`. Sony ARQ files are already combined by libraw into 4 component (RGBG) pixels,
+//! those only need the two greens averaged. The separate ARW frames a Sony body writes next to
+//! the ARQ can be merged with [merge_files].
+use std::path::Path;
+
+use crate::{fcol, LibrawError, Processor};
+
+/// Options for merging the frames of a pixel shift raw
+#[derive(Debug, Clone)]
+pub struct PixelShiftOptions {
+    /// The (row, col) sensor offset of each frame in a group of 4 in the order they are stored in
+    /// the file, the scene pixel at (row, col) lands on (row + dr, col + dc) of the frame
+    pub offsets: [(usize, usize); 4],
+    /// For 16 shot files, the (row, col) half pixel offset of each group of 4 frames on the
+    /// doubled output grid
+    pub group_offsets: [(usize, usize); 4],
+    /// If set, pixels where the two green samples differ by more than this fraction are
+    /// considered to be moving and are filled from the first frame only
+    pub motion_threshold: Option<f32>,
+}
+
+impl Default for PixelShiftOptions {
+    fn default() -> Self {
+        Self {
+            offsets: [(0, 0), (0, 1), (1, 1), (1, 0)],
+            group_offsets: [(0, 0), (0, 1), (1, 1), (1, 0)],
+            motion_threshold: None,
+        }
+    }
+}
+
+/// A merged 16 bit rgb image, the values are black subtracted but not white balanced or
+/// converted from the camera color space
+#[derive(Debug, Clone)]
+pub struct PixelShiftImage {
+    width: u32,
+    height: u32,
+    data: Vec<u16>,
+    motion_mask: Option<Vec<bool>>,
+}
+
+impl PixelShiftImage {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn bits(&self) -> u16 {
+        16
+    }
+    pub fn colors(&self) -> u16 {
+        3
+    }
+    pub fn as_slice_u16(&self) -> &[u16] {
+        &self.data
+    }
+    pub fn into_vec(self) -> Vec<u16> {
+        self.data
+    }
+    /// Pixels that were detected as moving between frames (only if motion_threshold was set)
+    pub fn motion_mask(&self) -> Option<&[bool]> {
+        self.motion_mask.as_deref()
+    }
+}
+
+/// A single black subtracted frame cropped to the visible area
+#[derive(Debug, Clone)]
+pub struct PixelShiftFrame {
+    width: usize,
+    height: usize,
+    filters: u32,
+    data: Vec<u16>,
+}
+
+impl PixelShiftFrame {
+    /// A frame of `width` x `height` bayer pixels with the pattern in libraw's `filters` format
+    pub fn new(
+        width: usize,
+        height: usize,
+        filters: u32,
+        data: Vec<u16>,
+    ) -> Result<Self, LibrawError> {
+        // filters < 1000 are the fuji xtrans / leaf patterns which can't be shifted like this
+        if filters < 1000 {
+            return Err(LibrawError::UnsupportedCfa);
+        }
+        if data.len() != width * height || width < 2 || height < 2 {
+            return Err(LibrawError::UnsupportedPixelShift);
+        }
+        Ok(Self {
+            width,
+            height,
+            filters,
+            data,
+        })
+    }
+
+    /// Copy the visible area of the frame unpacked in `processor` and subtract the black level
+    /// from it
+    pub fn from_processor(processor: &Processor) -> Result<Self, LibrawError> {
+        let filters = processor.idata().filters;
+        let raw = processor
+            .raw_image()
+            .ok_or(LibrawError::UnsupportedPixelShift)?;
+        let sizes = &processor.rawdata().sizes;
+        let black = processor.black_levels();
+        let pitch = sizes.raw_pitch as usize / 2;
+        let (top, left) = (sizes.top_margin as usize, sizes.left_margin as usize);
+        let (width, height) = (sizes.width as usize, sizes.height as usize);
+
+        let mut data = Vec::with_capacity(width * height);
+        for row in 0..height {
+            let line = &raw[(row + top) * pitch + left..][..width];
+            data.extend(line.iter().enumerate().map(|(col, &v)| {
+                let black = u16::try_from(black.at(row, col)).unwrap_or(u16::MAX);
+                v.saturating_sub(black)
+            }));
+        }
+        Self::new(width, height, filters, data)
+    }
+}
+
+impl Processor {
+    /// Select which frame of a multi shot raw gets decoded on the next open
+    pub fn set_shot_select(&mut self, shot: u32) {
+        self.rawparams().shot_select = shot;
+    }
+
+    /// The visible area of the unpacked 4 component pixels with the black level subtracted
+    fn black_subtracted_rgbg(&self) -> Option<(usize, usize, Vec<[u16; 4]>)> {
+        let rawdata = self.rawdata();
+        if rawdata.color4_image.is_null() {
+            return None;
+        }
+        let sizes = &rawdata.sizes;
+        let pitch = sizes.raw_pitch as usize / 8;
+        let raw = unsafe {
+            std::slice::from_raw_parts(rawdata.color4_image, sizes.raw_height as usize * pitch)
+        };
+        let black = self.black_levels();
+        let black = [0, 1, 2, 3]
+            .map(|c| u16::try_from(black.black + black.per_color[c]).unwrap_or(u16::MAX));
+        let (top, left) = (sizes.top_margin as usize, sizes.left_margin as usize);
+        let (width, height) = (sizes.width as usize, sizes.height as usize);
+
+        let mut data = Vec::with_capacity(width * height);
+        for row in 0..height {
+            let line = &raw[(row + top) * pitch + left..][..width];
+            data.extend(
+                line.iter()
+                    .map(|pixel| [0, 1, 2, 3].map(|c| pixel[c].saturating_sub(black[c]))),
+            );
+        }
+        Some((width, height, data))
+    }
+}
+
+/// Merge all the frames of a pixel shift raw file
+pub fn merge_file(
+    path: impl AsRef<Path>,
+    options: &PixelShiftOptions,
+) -> Result<PixelShiftImage, LibrawError> {
+    merge_with(|p| p.open(path.as_ref()), options)
+}
+
+/// Merge all the frames of a pixel shift raw file in memory
+pub fn merge_buffer(
+    buffer: impl AsRef<[u8]>,
+    options: &PixelShiftOptions,
+) -> Result<PixelShiftImage, LibrawError> {
+    merge_with(|p| p.open_buffer(buffer.as_ref()), options)
+}
+
+/// Merge 4 or 16 single frame raw files, in the order they were shot
+pub fn merge_files(
+    paths: &[impl AsRef<Path>],
+    options: &PixelShiftOptions,
+) -> Result<PixelShiftImage, LibrawError> {
+    let frames = paths
+        .iter()
+        .map(|path| {
+            let mut processor = Processor::default();
+            processor.open(path)?;
+            processor.unpack()?;
+            PixelShiftFrame::from_processor(&processor)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    merge_frames(&frames, options)
+}
+
+fn merge_with(
+    open: impl Fn(&mut Processor) -> Result<(), LibrawError>,
+    options: &PixelShiftOptions,
+) -> Result<PixelShiftImage, LibrawError> {
+    let mut processor = Processor::default();
+    open(&mut processor)?;
+    let shots = processor.idata().raw_count;
+    if shots != 4 && shots != 16 {
+        processor.unpack()?;
+        return match processor.black_subtracted_rgbg() {
+            Some((width, height, data)) => merge_rgbg(width, height, &data, options),
+            None => Err(LibrawError::InvalidShotCount(shots)),
+        };
+    }
+
+    let mut frames = Vec::with_capacity(shots as usize);
+    for shot in 0..shots {
+        processor.set_shot_select(shot);
+        open(&mut processor)?;
+        processor.unpack()?;
+        frames.push(PixelShiftFrame::from_processor(&processor)?);
+    }
+    merge_frames(&frames, options)
+}
+
+/// Merge 4 or 16 frames in the order they are stored in the file
+pub fn merge_frames(
+    frames: &[PixelShiftFrame],
+    options: &PixelShiftOptions,
+) -> Result<PixelShiftImage, LibrawError> {
+    if frames.len() != 4 && frames.len() != 16 {
+        return Err(LibrawError::InvalidShotCount(frames.len() as u32));
+    }
+    if frames
+        .iter()
+        .any(|f| f.width != frames[0].width || f.height != frames[0].height)
+    {
+        return Err(LibrawError::CustomError(
+            "Pixel shift frames have different sizes".into(),
+        ));
+    }
+
+    let groups = frames
+        .chunks(4)
+        .map(|group| merge_four(group, options))
+        .collect::<Vec<_>>();
+
+    match groups.len() {
+        1 => Ok(groups.into_iter().next().expect("One group")),
+        _ => Ok(interleave_groups(groups, options)),
+    }
+}
+
+/// Merge `width` x `height` black subtracted RGBG pixels like the ones libraw decodes from Sony
+/// ARQ files
+///
+/// The frames are already combined so moving pixels are only reported in the motion mask, there
+/// is no single frame to fill them from.
+pub fn merge_rgbg(
+    width: usize,
+    height: usize,
+    pixels: &[[u16; 4]],
+    options: &PixelShiftOptions,
+) -> Result<PixelShiftImage, LibrawError> {
+    if pixels.len() != width * height {
+        return Err(LibrawError::UnsupportedPixelShift);
+    }
+    let data = pixels
+        .iter()
+        .flat_map(|&[r, g1, b, g2]| [r, ((g1 as u32 + g2 as u32) / 2) as u16, b])
+        .collect();
+    let motion_mask = options.motion_threshold.map(|threshold| {
+        pixels
+            .iter()
+            .map(|&[_, g1, _, g2]| {
+                let (g1, g2) = (g1 as f32, g2 as f32);
+                (g1 - g2).abs() > threshold * g1.max(g2).max(1.0)
+            })
+            .collect()
+    });
+
+    Ok(PixelShiftImage {
+        width: width as u32,
+        height: height as u32,
+        data,
+        motion_mask,
+    })
+}
+
+/// Combine 4 frames shifted by one pixel so that every output pixel has a red, blue and two
+/// green samples
+fn merge_four(frames: &[PixelShiftFrame], options: &PixelShiftOptions) -> PixelShiftImage {
+    let (width, height) = (frames[0].width, frames[0].height);
+    let mut data = vec![0_u16; width * height * 3];
+    let mut motion_mask = options
+        .motion_threshold
+        .map(|_| vec![false; width * height]);
+
+    for row in 0..height {
+        for col in 0..width {
+            let idx = row * width + col;
+            let mut rgb = [0_u32; 3];
+            let mut greens = [0_u16; 2];
+            let mut green_count = 0;
+            for (frame, &(dr, dc)) in frames.iter().zip(options.offsets.iter()) {
+                let (r, c) = (shifted(row, dr, height), shifted(col, dc, width));
+                let value = frame.data[r * width + c];
+                match fcol(frame.filters, r, c) {
+                    0 => rgb[0] = value as u32,
+                    2 => rgb[2] = value as u32,
+                    _ => {
+                        if green_count < 2 {
+                            greens[green_count] = value;
+                        }
+                        green_count += 1;
+                    }
+                }
+            }
+            rgb[1] = (greens[0] as u32 + greens[1] as u32) / 2;
+
+            if let (Some(threshold), Some(mask)) = (options.motion_threshold, &mut motion_mask) {
+                let (g1, g2) = (greens[0] as f32, greens[1] as f32);
+                if (g1 - g2).abs() > threshold * g1.max(g2).max(1.0) {
+                    mask[idx] = true;
+                    rgb = superpixel(&frames[0], row, col);
+                }
+            }
+
+            data[idx * 3..idx * 3 + 3].copy_from_slice(&rgb.map(|v| v as u16));
+        }
+    }
+
+    PixelShiftImage {
+        width: width as u32,
+        height: height as u32,
+        data,
+        motion_mask,
+    }
+}
+
+/// The position a frame shifted by `offset` recorded `pos` at, at the far edge it steps back by
+/// whole bayer periods so the color stays the same
+fn shifted(pos: usize, offset: usize, len: usize) -> usize {
+    let mut pos = pos + offset;
+    while pos >= len {
+        pos = pos.saturating_sub(2);
+    }
+    pos
+}
+
+/// Rgb from the 2x2 bayer block containing (row, col) in a single frame
+fn superpixel(frame: &PixelShiftFrame, row: usize, col: usize) -> [u32; 3] {
+    let (row, col) = (
+        (row & !1).min(frame.height.saturating_sub(2)),
+        (col & !1).min(frame.width.saturating_sub(2)),
+    );
+    let mut sum = [0_u32; 3];
+    let mut count = [0_u32; 3];
+    for r in row..row + 2 {
+        for c in col..col + 2 {
+            let channel = match fcol(frame.filters, r, c) {
+                3 => 1,
+                c => c,
+            };
+            sum[channel] += frame.data[r * frame.width + c] as u32;
+            count[channel] += 1;
+        }
+    }
+    [0, 1, 2].map(|c| sum[c] / count[c].max(1))
+}
+
+/// Place the 4 merged groups of a 16 shot file on a grid with twice the resolution
+fn interleave_groups(groups: Vec<PixelShiftImage>, options: &PixelShiftOptions) -> PixelShiftImage {
+    let (width, height) = (groups[0].width as usize, groups[0].height as usize);
+    let out_width = width * 2;
+    let mut data = vec![0_u16; out_width * height * 2 * 3];
+    let mut motion_mask = options
+        .motion_threshold
+        .map(|_| vec![false; out_width * height * 2]);
+
+    for (group, &(dr, dc)) in groups.iter().zip(options.group_offsets.iter()) {
+        for row in 0..height {
+            for col in 0..width {
+                let src = row * width + col;
+                let dst = (row * 2 + dr) * out_width + col * 2 + dc;
+                data[dst * 3..dst * 3 + 3].copy_from_slice(&group.data[src * 3..src * 3 + 3]);
+                if let (Some(mask), Some(group_mask)) = (&mut motion_mask, &group.motion_mask) {
+                    mask[dst] = group_mask[src];
+                }
+            }
+        }
+    }
+
+    PixelShiftImage {
+        width: out_width as u32,
+        height: height as u32 * 2,
+        data,
+        motion_mask,
+    }
+}
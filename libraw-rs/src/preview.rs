@@ -0,0 +1,308 @@
+//! Getting a preview of at least a given size the cheapest way the file allows
+//!
+//! In order of cost the paths are a big enough embedded jpeg from `thumbs_list`, a `half_size`
+//! decode binned down 2x2 in rust until it is just above the target, and a full decode. When a
+//! path fails the next one is tried.
+use std::time::{Duration, Instant};
+
+use image::ColorType;
+
+use crate::resize::{bin_2x2, ResizeSpec};
+use crate::{sys, Flip, LibrawError, Orientation, Processor};
+
+/// Which path produced a preview
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PreviewSource {
+    /// The embedded jpeg at this index of `thumbs_list`
+    Embedded(usize),
+    /// `half_size` processing binned 2x2 this many times
+    HalfSize { binned: u32 },
+    /// Full resolution processing
+    Full,
+}
+
+/// Time spent in every step, steps that didn't run are zero
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PreviewTimings {
+    /// Unpacking the raw data or the thumbnail and decoding the thumbnail
+    pub decode: Duration,
+    /// dcraw_process and dcraw_make_mem_image
+    pub process: Duration,
+    /// Binning and resizing
+    pub resize: Duration,
+    /// Encoding the jpeg and writing the metadata
+    pub encode: Duration,
+}
+
+impl PreviewTimings {
+    pub fn total(&self) -> Duration {
+        self.decode + self.process + self.resize + self.encode
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Preview {
+    pub jpeg: Vec<u8>,
+    pub source: PreviewSource,
+    pub timings: PreviewTimings,
+}
+
+/// The target size of a preview and which paths may be used to get it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreviewStrategy {
+    /// The preview has at least this many pixels unless the image itself is smaller
+    pub min_pixels: u32,
+    /// Use an embedded jpeg that is big enough
+    pub embedded: bool,
+    /// Use `half_size` and `no_interpolation` when half the resolution is big enough
+    pub half_size: bool,
+    /// Applied after the cheapest path is picked, embedded jpegs are re-encoded when set
+    pub resize: Option<ResizeSpec>,
+    /// Quality of the jpegs that are encoded
+    pub quality: u8,
+}
+
+impl Default for PreviewStrategy {
+    /// At least 2 MP from any path
+    fn default() -> Self {
+        Self {
+            min_pixels: 2_000_000,
+            embedded: true,
+            half_size: true,
+            resize: None,
+            quality: 90,
+        }
+    }
+}
+
+impl PreviewStrategy {
+    pub fn new(min_pixels: u32) -> Self {
+        Self {
+            min_pixels,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_resize(self, resize: ResizeSpec) -> Self {
+        Self {
+            resize: Some(resize),
+            ..self
+        }
+    }
+
+    /// The paths to try in order, cheapest first, for an image of `width` x `height`
+    ///
+    /// `embedded` has the size of every thumbnail in `thumbs_list`, None for thumbnails that
+    /// aren't jpegs. All sizes are upright. A path is only used when its image has at least
+    /// `min_pixels` and is big enough for `resize` without upscaling, full processing is always
+    /// the last resort.
+    pub fn plan(
+        &self,
+        width: u32,
+        height: u32,
+        embedded: &[Option<(u32, u32)>],
+    ) -> Vec<PreviewSource> {
+        let mut plan = Vec::with_capacity(3);
+        if self.embedded {
+            let smallest = embedded
+                .iter()
+                .enumerate()
+                .filter_map(|(index, size)| size.map(|size| (index, size)))
+                .filter(|(_, (width, height))| self.is_big_enough(*width, *height))
+                .min_by_key(|(_, (width, height))| u64::from(*width) * u64::from(*height));
+            if let Some((index, _)) = smallest {
+                plan.push(PreviewSource::Embedded(index));
+            }
+        }
+        let (width, height) = (width / 2, height / 2);
+        if self.half_size && self.is_big_enough(width, height) {
+            plan.push(PreviewSource::HalfSize {
+                binned: self.bins(width, height),
+            });
+        }
+        plan.push(PreviewSource::Full);
+        plan
+    }
+
+    /// How many times an image can be binned 2x2 and stay big enough
+    fn bins(&self, mut width: u32, mut height: u32) -> u32 {
+        let mut binned = 0;
+        while self.is_big_enough(width / 2, height / 2) {
+            (width, height) = (width / 2, height / 2);
+            binned += 1;
+        }
+        binned
+    }
+
+    fn is_big_enough(&self, width: u32, height: u32) -> bool {
+        let pixels = u64::from(width) * u64::from(height);
+        let resizable = match self.resize {
+            Some(resize) => {
+                let (w, h) = resize.dimensions(width, height);
+                w <= width && h <= height
+            }
+            None => true,
+        };
+        width > 0 && height > 0 && pixels >= u64::from(self.min_pixels) && resizable
+    }
+}
+
+impl Processor {
+    /// Get a jpeg preview of at least `strategy.min_pixels` from the cheapest path
+    ///
+    /// When a path fails (a thumbnail that can't be unpacked or decoded for example) the next
+    /// one of [PreviewStrategy::plan] is tried, the error is only returned if full processing
+    /// fails as well. The params changed for the half size path are restored afterwards
+    pub fn preview(&mut self, strategy: &PreviewStrategy) -> Result<Preview, LibrawError> {
+        let flip = Orientation::from(Flip::from(self.sizes().flip));
+        let upright = |width: u16, height: u16, orientation: Orientation| match orientation
+            .swaps_dimensions()
+        {
+            true => (height.into(), width.into()),
+            false => (width.into(), height.into()),
+        };
+        let list = self.thumbs_list();
+        let embedded = list
+            .thumblist
+            .iter()
+            .take(list.thumbcount.max(0) as usize)
+            .map(|thumb| {
+                let jpeg = thumb.tformat
+                    == sys::LibRaw_internal_thumbnail_formats_LIBRAW_INTERNAL_THUMBNAIL_JPEG;
+                let orientation = Orientation::from_tflip(thumb.tflip).unwrap_or(flip);
+                jpeg.then(|| upright(thumb.twidth, thumb.theight, orientation))
+            })
+            .collect::<Vec<_>>();
+        let sizes = self.sizes();
+        let (width, height) = upright(sizes.width, sizes.height, flip);
+
+        let mut result = Err(LibrawError::UnsupportedThumbnail);
+        for source in strategy.plan(width, height, &embedded) {
+            result = match source {
+                PreviewSource::Embedded(index) => self.embedded_preview(index, strategy),
+                PreviewSource::HalfSize { .. } => self.half_size_preview(strategy),
+                PreviewSource::Full => self.processed_preview(strategy, false),
+            };
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    fn half_size_preview(&mut self, strategy: &PreviewStrategy) -> Result<Preview, LibrawError> {
+        let params = self.params();
+        let saved = (params.half_size, params.no_interpolation);
+        params.half_size = 1;
+        params.no_interpolation = 1;
+        let preview = self.processed_preview(strategy, true);
+        let params = self.params();
+        (params.half_size, params.no_interpolation) = saved;
+        preview
+    }
+
+    fn embedded_preview(
+        &mut self,
+        index: usize,
+        strategy: &PreviewStrategy,
+    ) -> Result<Preview, LibrawError> {
+        let mut timings = PreviewTimings::default();
+        let start = Instant::now();
+        self.unpack_thumb_ex(index as _)?;
+        // Previews can have their own orientation
+        let orientation = Orientation::from_tflip(self.thumbs_list().thumblist[index].tflip)
+            .unwrap_or_else(|| Orientation::from(Flip::from(self.sizes().flip)));
+        timings.decode = start.elapsed();
+
+        let jpeg = match strategy.resize {
+            Some(resize) => {
+                let start = Instant::now();
+                let rgb = self.thumbnail_image()?.into_rgb8();
+                timings.decode += start.elapsed();
+
+                let start = Instant::now();
+                let resize = match orientation.swaps_dimensions() {
+                    true => resize.transposed(),
+                    false => resize,
+                };
+                let (width, height) = (rgb.width(), rgb.height());
+                let (pixels, width, height) =
+                    resize.resize(rgb.into_raw(), width, height, ColorType::Rgb8)?;
+                timings.resize = start.elapsed();
+
+                let start = Instant::now();
+                let mut jpeg = self.encode_jpeg(
+                    &pixels,
+                    width,
+                    height,
+                    ColorType::Rgb8,
+                    Some(strategy.quality),
+                )?;
+                if let Some(original) = self.thumbnail_jpeg() {
+                    jpeg = crate::jpeg::copy_metadata(original, jpeg)?;
+                }
+                let jpeg = self.set_jpeg_orientation(jpeg, orientation)?;
+                timings.encode = start.elapsed();
+                jpeg
+            }
+            None => {
+                let start = Instant::now();
+                let original = self
+                    .thumbnail_jpeg()
+                    .ok_or(LibrawError::UnsupportedThumbnail)?
+                    .to_vec();
+                let jpeg = self.set_jpeg_orientation(original, orientation)?;
+                timings.encode = start.elapsed();
+                jpeg
+            }
+        };
+        Ok(Preview {
+            jpeg,
+            source: PreviewSource::Embedded(index),
+            timings,
+        })
+    }
+
+    fn processed_preview(
+        &mut self,
+        strategy: &PreviewStrategy,
+        half_size: bool,
+    ) -> Result<Preview, LibrawError> {
+        let mut timings = PreviewTimings::default();
+        let start = Instant::now();
+        self.unpack_once()?;
+        timings.decode = start.elapsed();
+
+        let start = Instant::now();
+        let (mut pixels, mut width, mut height, colortype) = self.processed_pixels(None)?;
+        timings.process = start.elapsed();
+
+        let start = Instant::now();
+        let binned = match half_size {
+            true => strategy.bins(width, height),
+            false => 0,
+        };
+        for _ in 0..binned {
+            (pixels, width, height) = bin_2x2(&pixels, width, height, colortype)?;
+        }
+        if let Some(resize) = strategy.resize {
+            (pixels, width, height) = resize.resize(pixels, width, height, colortype)?;
+        }
+        timings.resize = start.elapsed();
+
+        let start = Instant::now();
+        let jpeg = self.encode_jpeg(&pixels, width, height, colortype, Some(strategy.quality))?;
+        let jpeg = self.set_jpeg_orientation(jpeg, Orientation::NONE)?;
+        timings.encode = start.elapsed();
+
+        let source = match half_size {
+            true => PreviewSource::HalfSize { binned },
+            false => PreviewSource::Full,
+        };
+        Ok(Preview {
+            jpeg,
+            source,
+            timings,
+        })
+    }
+}
@@ -0,0 +1,200 @@
+//! Conversions from ProcessedImage into other image containers
+use std::ptr::NonNull;
+
+use crate::error::InternalLibrawError;
+use crate::{sys, ImageFormat, LibrawError, Orientation, ProcessedImage};
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for u8 {}
+    impl Sealed for u16 {}
+}
+
+/// The component types of a processed image, `u8` and `u16`
+pub trait Sample: private::Sealed + Copy {}
+impl Sample for u8 {}
+impl Sample for u16 {}
+
+impl ProcessedImage {
+    /// Copy rust pixels into a bitmap allocated the way `dcraw_make_mem_image` does, so it can be
+    /// passed wherever libraw's output is expected
+    ///
+    /// `data` holds `height` rows of `width * colors` samples, the bit depth follows T
+    pub fn from_bitmap<T: Sample>(
+        width: u16,
+        height: u16,
+        colors: u16,
+        data: &[T],
+    ) -> Result<Self, LibrawError> {
+        let len = usize::from(width) * usize::from(height) * usize::from(colors);
+        if !matches!(colors, 1 | 3) || data.len() != len {
+            return Err(LibrawError::UnsupportedImageFormat);
+        }
+        let data_size = std::mem::size_of_val(data);
+        let header = std::mem::size_of::<sys::libraw_processed_image_t>();
+        // libraw frees the image with free(), so it has to come from malloc
+        let inner =
+            unsafe { libc::calloc(1, header + data_size) } as *mut sys::libraw_processed_image_t;
+        let mut inner = NonNull::new(inner).ok_or(InternalLibrawError::UnsufficientMemory)?;
+        unsafe {
+            let raw = inner.as_mut();
+            raw.type_ = sys::LibRaw_image_formats_LIBRAW_IMAGE_BITMAP;
+            raw.width = width;
+            raw.height = height;
+            raw.colors = colors;
+            raw.bits = (std::mem::size_of::<T>() * 8) as u16;
+            raw.data_size = data_size as u32;
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                raw.data.as_mut_ptr(),
+                data_size,
+            );
+        }
+        Ok(Self { inner })
+    }
+
+    /// Move the pixel data into a rust owned Vec<T> and free the libraw allocation
+    ///
+    /// The memory is allocated by libraw's allocator so it can't be adopted by a Vec, this does
+    /// exactly one copy. T must match the number of bits in the image
+    pub fn into_owned_vec<T: Sample>(self) -> Result<Vec<T>, LibrawError> {
+        self.check_sample::<T>()?;
+        Ok(self.as_slice::<T>().to_vec())
+    }
+
+    /// Fails unless T is u8 for 8 bit and u16 for 16 bit images
+    fn check_sample<T: Sample>(&self) -> Result<(), LibrawError> {
+        match std::mem::size_of::<T>() * 8 == usize::from(self.bits()) {
+            true => Ok(()),
+            false => Err(LibrawError::InvalidColor(self.bits())),
+        }
+    }
+
+    /// Rotate / mirror the pixels in place so the image displays correctly without an EXIF
+    /// orientation tag, width and height are swapped for orientations 5 to 8
+    ///
+    /// Note that `dcraw_make_mem_image` already applies `sizes.flip` (unless `user_flip` is 0)
+    pub fn apply_orientation(&mut self, orientation: Orientation) -> Result<(), LibrawError> {
+        if self.type_() != ImageFormat::Bitmap {
+            return Err(LibrawError::UnsupportedImageFormat);
+        }
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        let colors = self.colors() as usize;
+        let (out_width, out_height) = match self.bits() {
+            8 => {
+                let (pixels, w, h) =
+                    orientation.apply_to_pixels(self.as_slice_u8(), width, height, colors);
+                self.as_mut_slice::<u8>()[..pixels.len()].copy_from_slice(&pixels);
+                (w, h)
+            }
+            16 => {
+                let (pixels, w, h) =
+                    orientation.apply_to_pixels(self.as_slice_u16(), width, height, colors);
+                self.as_mut_slice::<u16>()[..pixels.len()].copy_from_slice(&pixels);
+                (w, h)
+            }
+            bits => return Err(LibrawError::InvalidColor(bits)),
+        };
+        let raw = unsafe { self.inner.as_mut() };
+        raw.width = out_width as u16;
+        raw.height = out_height as u16;
+        Ok(())
+    }
+
+    /// The matching L8 / L16 / Rgb8 / Rgb16 color type of a bitmap
+    #[cfg(feature = "jpeg")]
+    pub fn color_type(&self) -> Result<image::ColorType, LibrawError> {
+        if self.type_() != ImageFormat::Bitmap {
+            return Err(LibrawError::UnsupportedImageFormat);
+        }
+        match (self.colors(), self.bits()) {
+            (1, 8) => Ok(image::ColorType::L8),
+            (1, 16) => Ok(image::ColorType::L16),
+            (3, 8) => Ok(image::ColorType::Rgb8),
+            (3, 16) => Ok(image::ColorType::Rgb16),
+            (_, bits) => Err(LibrawError::InvalidColor(bits)),
+        }
+    }
+
+    fn as_mut_slice<T: Sample>(&mut self) -> &mut [T] {
+        unsafe {
+            let raw = self.inner.as_mut();
+            std::slice::from_raw_parts_mut(
+                raw.data.as_mut_ptr() as *mut T,
+                raw.data_size as usize / std::mem::size_of::<T>(),
+            )
+        }
+    }
+
+    /// Borrow the pixels as a (height, width, colors) array without copying
+    ///
+    /// T must match the number of bits in the image (u8 for 8 bit and u16 for 16 bit images)
+    #[cfg(feature = "ndarray")]
+    pub fn as_array_view<T: Sample>(&self) -> Result<ndarray::ArrayView3<'_, T>, LibrawError> {
+        if self.type_() != ImageFormat::Bitmap {
+            return Err(LibrawError::UnsupportedImageFormat);
+        }
+        self.check_sample::<T>()?;
+        let shape = (
+            self.height() as usize,
+            self.width() as usize,
+            self.colors() as usize,
+        );
+        ndarray::ArrayView3::from_shape(shape, self.as_slice::<T>())
+            .map_err(|e| LibrawError::CustomError(e.into()))
+    }
+}
+
+/// Converts a bitmap into the matching L8 / L16 / Rgb8 / Rgb16 image with one copy and decodes
+/// jpegs
+#[cfg(feature = "jpeg")]
+impl TryFrom<ProcessedImage> for image::DynamicImage {
+    type Error = LibrawError;
+    fn try_from(processed: ProcessedImage) -> Result<Self, Self::Error> {
+        use image::{ImageBuffer, Luma, Rgb};
+        if processed.type_() == ImageFormat::Jpeg {
+            return Ok(image::load_from_memory_with_format(
+                processed.as_slice_u8(),
+                image::ImageFormat::Jpeg,
+            )?);
+        }
+
+        let (width, height) = (processed.width(), processed.height());
+        let dynimg = match (processed.colors(), processed.bits()) {
+            (1, 8) => image::DynamicImage::from(
+                ImageBuffer::<Luma<u8>, Vec<u8>>::from_raw(
+                    width,
+                    height,
+                    processed.into_owned_vec()?,
+                )
+                .ok_or(LibrawError::EncodingError)?,
+            ),
+            (1, 16) => image::DynamicImage::from(
+                ImageBuffer::<Luma<u16>, Vec<u16>>::from_raw(
+                    width,
+                    height,
+                    processed.into_owned_vec()?,
+                )
+                .ok_or(LibrawError::EncodingError)?,
+            ),
+            (3, 8) => image::DynamicImage::from(
+                ImageBuffer::<Rgb<u8>, Vec<u8>>::from_raw(
+                    width,
+                    height,
+                    processed.into_owned_vec()?,
+                )
+                .ok_or(LibrawError::EncodingError)?,
+            ),
+            (3, 16) => image::DynamicImage::from(
+                ImageBuffer::<Rgb<u16>, Vec<u16>>::from_raw(
+                    width,
+                    height,
+                    processed.into_owned_vec()?,
+                )
+                .ok_or(LibrawError::EncodingError)?,
+            ),
+            (_, bits) => return Err(LibrawError::InvalidColor(bits)),
+        };
+        Ok(dynimg)
+    }
+}
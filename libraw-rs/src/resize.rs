@@ -0,0 +1,231 @@
+//! Resizing of 8 / 16 bit rgb and grayscale pixels with fast_image_resize
+use fast_image_resize as fr;
+use image::ColorType;
+
+use crate::{LibrawError, Resolution};
+
+/// How the target width and height are used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ResizeMode {
+    /// Scale to fit inside width x height keeping the aspect ratio
+    #[default]
+    Fit,
+    /// Scale to cover width x height keeping the aspect ratio and crop the overflow evenly from
+    /// both sides
+    Fill,
+    /// Scale to exactly width x height, ignoring the aspect ratio
+    Exact,
+    /// Scale so the longer edge is `width` long, `height` is ignored
+    LongEdge,
+    /// Scale so the shorter edge is `width` long, `height` is ignored
+    ShortEdge,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ResizeFilter {
+    Nearest,
+    Box,
+    Bilinear,
+    Hamming,
+    CatmullRom,
+    Mitchell,
+    #[default]
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for fr::ResizeAlg {
+    fn from(filter: ResizeFilter) -> Self {
+        use fr::FilterType;
+        match filter {
+            ResizeFilter::Nearest => fr::ResizeAlg::Nearest,
+            ResizeFilter::Box => fr::ResizeAlg::Convolution(FilterType::Box),
+            ResizeFilter::Bilinear => fr::ResizeAlg::Convolution(FilterType::Bilinear),
+            ResizeFilter::Hamming => fr::ResizeAlg::Convolution(FilterType::Hamming),
+            ResizeFilter::CatmullRom => fr::ResizeAlg::Convolution(FilterType::CatmullRom),
+            ResizeFilter::Mitchell => fr::ResizeAlg::Convolution(FilterType::Mitchell),
+            ResizeFilter::Lanczos3 => fr::ResizeAlg::Convolution(FilterType::Lanczos3),
+        }
+    }
+}
+
+/// The target size of a preview, see [ResizeMode] for how width and height are used
+///
+/// Images smaller than the target are scaled up unless `upscale` is false
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResizeSpec {
+    pub mode: ResizeMode,
+    pub filter: ResizeFilter,
+    pub width: u32,
+    pub height: u32,
+    /// If false the output is never larger than the input, [ResizeMode::Fill] and
+    /// [ResizeMode::Exact] clamp the target to the input size
+    pub upscale: bool,
+}
+
+impl ResizeSpec {
+    pub fn new(mode: ResizeMode, width: u32, height: u32) -> Self {
+        Self {
+            mode,
+            filter: ResizeFilter::default(),
+            width,
+            height,
+            upscale: true,
+        }
+    }
+
+    pub fn fit(width: u32, height: u32) -> Self {
+        Self::new(ResizeMode::Fit, width, height)
+    }
+
+    pub fn fill(width: u32, height: u32) -> Self {
+        Self::new(ResizeMode::Fill, width, height)
+    }
+
+    pub fn exact(width: u32, height: u32) -> Self {
+        Self::new(ResizeMode::Exact, width, height)
+    }
+
+    pub fn long_edge(size: u32) -> Self {
+        Self::new(ResizeMode::LongEdge, size, size)
+    }
+
+    pub fn short_edge(size: u32) -> Self {
+        Self::new(ResizeMode::ShortEdge, size, size)
+    }
+
+    pub fn with_filter(self, filter: ResizeFilter) -> Self {
+        Self { filter, ..self }
+    }
+
+    pub fn with_upscale(self, upscale: bool) -> Self {
+        Self { upscale, ..self }
+    }
+
+    /// The same target with width and height swapped, for resizing pixels that are rotated by 90
+    /// degrees afterwards
+    pub fn transposed(self) -> Self {
+        Self {
+            width: self.height,
+            height: self.width,
+            ..self
+        }
+    }
+
+    /// The output size for an image of `width` x `height`, at least 1 x 1
+    pub fn dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        let (w, h) = (width as f64, height as f64);
+        let scaled = |scale: f64| {
+            let scale = match self.upscale {
+                true => scale,
+                false => scale.min(1.0),
+            };
+            (
+                ((w * scale).round() as u32).max(1),
+                ((h * scale).round() as u32).max(1),
+            )
+        };
+        match self.mode {
+            ResizeMode::Fit => scaled((self.width as f64 / w).min(self.height as f64 / h)),
+            ResizeMode::Fill | ResizeMode::Exact if !self.upscale => {
+                (self.width.min(width).max(1), self.height.min(height).max(1))
+            }
+            ResizeMode::Fill | ResizeMode::Exact => (self.width.max(1), self.height.max(1)),
+            ResizeMode::LongEdge => scaled(self.width as f64 / w.max(h)),
+            ResizeMode::ShortEdge => scaled(self.width as f64 / w.min(h)),
+        }
+    }
+
+    /// Resize tightly packed pixels, 16 bit samples are in native byte order
+    ///
+    /// Returns the pixels with their new width and height
+    pub fn resize(
+        &self,
+        pixels: Vec<u8>,
+        width: u32,
+        height: u32,
+        colortype: ColorType,
+    ) -> Result<(Vec<u8>, u32, u32), LibrawError> {
+        let pixel_type = pixel_type(colortype)?;
+        let (out_width, out_height) = self.dimensions(width, height);
+        if (out_width, out_height) == (width, height) {
+            return Ok((pixels, width, height));
+        }
+
+        let src = fr::images::Image::from_vec_u8(width, height, pixels, pixel_type)
+            .map_err(|_| LibrawError::ResizingError)?;
+        let mut dst = fr::images::Image::new(out_width, out_height, pixel_type);
+        let mut options = fr::ResizeOptions::new().resize_alg(self.filter.into());
+        if self.mode == ResizeMode::Fill {
+            options = options.fit_into_destination(Some((0.5, 0.5)));
+        }
+        fr::Resizer::new().resize(&src, &mut dst, &options)?;
+        Ok((dst.into_vec(), out_width, out_height))
+    }
+}
+
+/// Average every 2x2 block of pixels, an odd last row or column is dropped
+///
+/// Much faster than a filtered resize for halving, 16 bit samples are in native byte order
+pub fn bin_2x2(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    colortype: ColorType,
+) -> Result<(Vec<u8>, u32, u32), LibrawError> {
+    pixel_type(colortype)?;
+    let channels = colortype.channel_count() as usize;
+    let bytes = colortype.bytes_per_pixel() as usize / channels;
+    let (width, height) = (width as usize, height as usize);
+    if pixels.len() != width * height * channels * bytes {
+        return Err(LibrawError::ResizingError);
+    }
+    let sample = |i: usize| -> u32 {
+        match bytes {
+            1 => pixels[i] as u32,
+            _ => u16::from_ne_bytes([pixels[2 * i], pixels[2 * i + 1]]) as u32,
+        }
+    };
+
+    let (out_width, out_height) = (width / 2, height / 2);
+    let stride = width * channels;
+    let mut out = Vec::with_capacity(out_width * out_height * channels * bytes);
+    for row in 0..out_height {
+        for col in 0..out_width {
+            for c in 0..channels {
+                let i = 2 * row * stride + 2 * col * channels + c;
+                let sum = sample(i)
+                    + sample(i + channels)
+                    + sample(i + stride)
+                    + sample(i + stride + channels);
+                let average = (sum + 2) / 4;
+                match bytes {
+                    1 => out.push(average as u8),
+                    _ => out.extend_from_slice(&(average as u16).to_ne_bytes()),
+                }
+            }
+        }
+    }
+    Ok((out, out_width as u32, out_height as u32))
+}
+
+/// Fits inside the resolution like [image::DynamicImage::thumbnail], smaller images are kept
+/// at their size
+impl From<Resolution> for ResizeSpec {
+    fn from(resolution: Resolution) -> Self {
+        Self::fit(resolution.width, resolution.height).with_upscale(false)
+    }
+}
+
+pub(crate) fn pixel_type(colortype: ColorType) -> Result<fr::PixelType, LibrawError> {
+    Ok(match colortype {
+        ColorType::L8 => fr::PixelType::U8,
+        ColorType::La8 => fr::PixelType::U8x2,
+        ColorType::Rgb8 => fr::PixelType::U8x3,
+        ColorType::Rgba8 => fr::PixelType::U8x4,
+        ColorType::L16 => fr::PixelType::U16,
+        ColorType::La16 => fr::PixelType::U16x2,
+        ColorType::Rgb16 => fr::PixelType::U16x3,
+        ColorType::Rgba16 => fr::PixelType::U16x4,
+        _ => return Err(LibrawError::ResizingError),
+    })
+}
@@ -0,0 +1,236 @@
+//! Histograms, clipping and exposure statistics straight from the raw mosaic
+//!
+//! Works on `rawdata.raw_image` without running `dcraw_process`, so only `unpack` is needed.
+//! Values are black subtracted with [crate::levels::BlackLevels] and normalized by the white
+//! level of their CFA color. Channels are libraw's CFA color indices (the second green of a
+//! bayer sensor is channel 3). With the `rayon` feature the rows are processed in parallel.
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::{LibrawError, Processor};
+
+/// The middle gray exposures are measured against
+const MIDDLE_GRAY: f64 = 0.18;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatsOptions {
+    /// Number of histogram bins over the normalized range 0..=1
+    pub bins: usize,
+    /// Normalized level from which a pixel counts as clipped
+    pub clip_level: f64,
+}
+
+impl Default for StatsOptions {
+    fn default() -> Self {
+        Self {
+            bins: 256,
+            clip_level: 0.98,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawStats {
+    /// Normalized histogram per channel, values outside 0..=1 go to the first / last bin
+    pub histograms: [Vec<u64>; 4],
+    pub counts: [u64; 4],
+    pub clipped: [u64; 4],
+    /// Mean normalized value per channel
+    pub means: [f64; 4],
+    /// Which channels are green, used for the luminance statistics
+    pub green: [bool; 4],
+    /// Geometric mean of the green pixels
+    pub log_average_luminance: f64,
+}
+
+impl RawStats {
+    /// Fraction of the pixels of a channel at or above the clip level
+    pub fn clipped_fraction(&self, channel: usize) -> f64 {
+        match self.counts[channel] {
+            0 => 0.0,
+            count => self.clipped[channel] as f64 / count as f64,
+        }
+    }
+
+    /// Normalized value below which `fraction` (0..=1) of the pixels of a channel are
+    pub fn percentile(&self, channel: usize, fraction: f64) -> f64 {
+        percentile(&self.histograms[channel], fraction)
+    }
+
+    /// Median of the green pixels
+    pub fn median_luminance(&self) -> f64 {
+        percentile(&self.green_histogram(), 0.5)
+    }
+
+    /// Stops the exposure could be raised before the brightest 0.1% of any channel clips,
+    /// negative when more than that is already clipped
+    pub fn headroom_ev(&self) -> f64 {
+        let brightest = (0..4)
+            .filter(|c| self.counts[*c] > 0)
+            .map(|c| self.percentile(c, 0.999))
+            .fold(0.0, f64::max);
+        match brightest > 0.0 {
+            true => -brightest.log2(),
+            false => f64::INFINITY,
+        }
+    }
+
+    /// Stops the log average luminance is above (positive) or below middle gray
+    pub fn exposure_ev(&self) -> f64 {
+        (self.log_average_luminance / MIDDLE_GRAY).log2()
+    }
+
+    fn green_histogram(&self) -> Vec<u64> {
+        let mut histogram = vec![0; self.histograms[0].len()];
+        for (channel, _) in self.green.iter().enumerate().filter(|(_, green)| **green) {
+            for (bin, v) in histogram.iter_mut().zip(&self.histograms[channel]) {
+                *bin += v;
+            }
+        }
+        histogram
+    }
+}
+
+impl RawStats {
+    /// Statistics of a `width` x `height` mosaic, `pixel(row, col)` gives the channel and the
+    /// black subtracted, normalized value of a pixel
+    pub fn from_pixels<F>(
+        width: usize,
+        height: usize,
+        green: [bool; 4],
+        options: &StatsOptions,
+        pixel: F,
+    ) -> Self
+    where
+        F: Fn(usize, usize) -> (usize, f64) + Sync,
+    {
+        let bins = options.bins.max(1);
+        let add_row = |mut acc: Accumulator, row: usize| {
+            for col in 0..width {
+                let (c, v) = pixel(row, col);
+                acc.add(c, v, green[c], options.clip_level);
+            }
+            acc
+        };
+
+        #[cfg(feature = "rayon")]
+        let acc = (0..height)
+            .into_par_iter()
+            .fold(|| Accumulator::new(bins), add_row)
+            .reduce(|| Accumulator::new(bins), Accumulator::merge);
+        #[cfg(not(feature = "rayon"))]
+        let acc = (0..height).fold(Accumulator::new(bins), add_row);
+
+        RawStats {
+            means: [0, 1, 2, 3].map(|c| match acc.counts[c] {
+                0 => 0.0,
+                count => acc.sums[c] / count as f64,
+            }),
+            log_average_luminance: match acc.log_count {
+                0 => 0.0,
+                count => (acc.log_sum / count as f64).exp(),
+            },
+            histograms: acc.histograms,
+            counts: acc.counts,
+            clipped: acc.clipped,
+            green,
+        }
+    }
+}
+
+impl Processor {
+    /// Statistics of the visible area of the unpacked raw data
+    pub fn raw_stats(&self, options: &StatsOptions) -> Result<RawStats, LibrawError> {
+        let raw = self.raw_image().ok_or(LibrawError::UnsupportedCfa)?;
+        let rawdata = self.rawdata();
+        let (sizes, idata) = (&rawdata.sizes, &rawdata.iparams);
+        let pitch = sizes.raw_pitch as usize / 2;
+        let (top, left) = (sizes.top_margin as usize, sizes.left_margin as usize);
+        let (width, height) = (sizes.width as usize, sizes.height as usize);
+
+        let black = self.black_levels();
+        let white = self.saturation_levels().per_color();
+        let green = [0, 1, 2, 3].map(|c| idata.cdesc[c] as u8 == b'G');
+        Ok(RawStats::from_pixels(
+            width,
+            height,
+            green,
+            options,
+            |row, col| {
+                let c = black.color(row, col);
+                let black = f64::from(black.at(row, col));
+                let range = (f64::from(white[c]) - black).max(1.0);
+                let v = f64::from(raw[(row + top) * pitch + left + col]);
+                (c, (v - black) / range)
+            },
+        ))
+    }
+}
+
+/// Partial sums of a block of rows
+struct Accumulator {
+    histograms: [Vec<u64>; 4],
+    counts: [u64; 4],
+    clipped: [u64; 4],
+    sums: [f64; 4],
+    log_sum: f64,
+    log_count: u64,
+}
+
+impl Accumulator {
+    fn new(bins: usize) -> Self {
+        Self {
+            histograms: [(); 4].map(|_| vec![0; bins]),
+            counts: [0; 4],
+            clipped: [0; 4],
+            sums: [0.0; 4],
+            log_sum: 0.0,
+            log_count: 0,
+        }
+    }
+
+    fn add(&mut self, channel: usize, v: f64, green: bool, clip_level: f64) {
+        let last = self.histograms[channel].len() - 1;
+        let bin = (v.clamp(0.0, 1.0) * last as f64).round() as usize;
+        self.histograms[channel][bin] += 1;
+        self.counts[channel] += 1;
+        self.clipped[channel] += u64::from(v >= clip_level);
+        self.sums[channel] += v;
+        if green {
+            // Floor so black pixels don't pull the geometric mean to zero
+            self.log_sum += v.max(1.0 / 65536.0).ln();
+            self.log_count += 1;
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    fn merge(mut self, other: Self) -> Self {
+        for c in 0..4 {
+            for (a, b) in self.histograms[c].iter_mut().zip(&other.histograms[c]) {
+                *a += b;
+            }
+            self.counts[c] += other.counts[c];
+            self.clipped[c] += other.clipped[c];
+            self.sums[c] += other.sums[c];
+        }
+        self.log_sum += other.log_sum;
+        self.log_count += other.log_count;
+        self
+    }
+}
+
+fn percentile(histogram: &[u64], fraction: f64) -> f64 {
+    let total: u64 = histogram.iter().sum();
+    if total == 0 || histogram.len() < 2 {
+        return 0.0;
+    }
+    let target = (fraction.clamp(0.0, 1.0) * total as f64).ceil() as u64;
+    let mut seen = 0;
+    for (bin, count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen >= target.max(1) {
+            return bin as f64 / (histogram.len() - 1) as f64;
+        }
+    }
+    1.0
+}
@@ -37,6 +37,8 @@ pub struct LibrawImageSizes {
     pub flip: i32,
     pub raw_aspect: u16,
     pub raw_inset_crops: [LibrawRawInsetCrops; 2usize],
+    /// Masked (optical black) areas as top, left, bottom, right in raw coordinates
+    pub mask: [[i32; 4usize]; 8usize],
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -90,8 +92,13 @@ pub struct LibrawDngLevels {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LibrawColordata {
+    /// Per channel black levels in 0..4, the dimensions of a repeating black level pattern in
+    /// 4 (rows) and 5 (columns) and the pattern from 6, all relative to `black`
+    /// (see [crate::levels::BlackLevels])
     pub cblack: Vec<u32>,
     pub black: u32,
+    /// Sums (0..4) and counts (4..8) per channel of the masked pixels libraw measured
+    pub black_stat: [u32; 8usize],
     pub linear_max: [u32; 4usize],
     pub maximum: u32,
     pub cam_mul: [f32; 4usize],
@@ -215,6 +222,7 @@ impl From<&libraw_colordata_t> for LibrawColordata {
         Self {
             cblack: value.cblack.to_vec(),
             black: value.black,
+            black_stat: value.black_stat,
             linear_max: value.linear_max,
             maximum: value.maximum,
             cam_mul: value.cam_mul,
@@ -244,6 +252,7 @@ impl From<&libraw_image_sizes_t> for LibrawImageSizes {
             flip: libraw_sizes.flip,
             raw_aspect: libraw_sizes.raw_aspect,
             raw_inset_crops: libraw_sizes.raw_inset_crops.map(|x| x.into()),
+            mask: libraw_sizes.mask,
         }
     }
 }
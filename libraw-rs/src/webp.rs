@@ -0,0 +1,140 @@
+//! Lossy WebP previews of the processed image or the embedded thumbnail
+//!
+//! The orientation is kept in the EXIF and the ICC profile of the output color space (or the one
+//! of the thumbnail) is embedded
+use image::ColorType;
+use img_parts::jpeg::Jpeg;
+use img_parts::riff::{RiffChunk, RiffContent};
+use img_parts::webp::{WebP, CHUNK_EXIF, CHUNK_ICCP, CHUNK_VP8X};
+use img_parts::{Bytes, ImageEXIF, ImageICC};
+
+use crate::metadata::CameraMetadata;
+use crate::resize::ResizeSpec;
+use crate::{jpeg, LibrawError, Orientation, Processor};
+
+impl Processor {
+    /// Process the raw data into a webp, 16 bit images are reduced to 8 bits
+    pub fn to_webp(&mut self, quality: u8) -> Result<Vec<u8>, LibrawError> {
+        self.processed_webp(quality, None)
+    }
+
+    /// Same as to_webp but resized according to `resize`
+    pub fn to_webp_resized(
+        &mut self,
+        quality: u8,
+        resize: &ResizeSpec,
+    ) -> Result<Vec<u8>, LibrawError> {
+        self.processed_webp(quality, Some(resize))
+    }
+
+    /// Transcode the embedded thumbnail into a webp keeping it's EXIF and ICC profile
+    pub fn get_webp(&mut self, quality: u8) -> Result<Vec<u8>, LibrawError> {
+        self.thumbnail_webp(quality, None)
+    }
+
+    /// Same as get_webp but resized according to `resize`
+    pub fn get_webp_resized(
+        &mut self,
+        quality: u8,
+        resize: &ResizeSpec,
+    ) -> Result<Vec<u8>, LibrawError> {
+        self.thumbnail_webp(quality, Some(resize))
+    }
+
+    fn processed_webp(
+        &mut self,
+        quality: u8,
+        resize: Option<&ResizeSpec>,
+    ) -> Result<Vec<u8>, LibrawError> {
+        let (pixels, width, height, colortype) = self.processed_pixels(resize)?;
+        let webp = encode(&pixels, width, height, colortype, quality)?;
+        // The processed pixels are already upright
+        let exif = jpeg::exif_with_orientation(
+            None,
+            Orientation::NONE,
+            Some(&CameraMetadata::from(&*self)),
+        );
+        with_metadata(webp, exif, self.output_icc_profile())
+    }
+
+    fn thumbnail_webp(
+        &mut self,
+        quality: u8,
+        resize: Option<&ResizeSpec>,
+    ) -> Result<Vec<u8>, LibrawError> {
+        let (pixels, width, height, orientation) = self.thumbnail_pixels(resize)?;
+        let (exif, icc) = match self.thumbnail_jpeg() {
+            Some(thumbnail) => {
+                let thumbnail = Jpeg::from_bytes(Bytes::copy_from_slice(thumbnail))?;
+                (thumbnail.exif(), thumbnail.icc_profile())
+            }
+            None => (None, None),
+        };
+        let exif = jpeg::exif_with_orientation(
+            exif.as_deref(),
+            orientation,
+            Some(&CameraMetadata::from(&*self)),
+        );
+        let webp = encode(&pixels, width, height, ColorType::Rgb8, quality)?;
+        with_metadata(webp, exif, icc.map(|icc| icc.to_vec()))
+    }
+}
+
+fn encode(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    colortype: ColorType,
+    quality: u8,
+) -> Result<Vec<u8>, LibrawError> {
+    let (pixels, colortype) = jpeg::to_8bit(pixels, colortype)?;
+    // libwebp only takes rgb(a)
+    let pixels = match colortype {
+        ColorType::L8 => pixels.iter().flat_map(|&v| [v, v, v]).collect::<Vec<_>>(),
+        _ => pixels.into_owned(),
+    };
+    let webp = ::webp::Encoder::from_rgb(&pixels, width, height)
+        .encode_simple(false, quality.into())
+        .map_err(|_| LibrawError::EncodingError)?;
+    Ok(webp.to_vec())
+}
+
+fn with_metadata(
+    webp: Vec<u8>,
+    exif: Vec<u8>,
+    icc_profile: Option<Vec<u8>>,
+) -> Result<Vec<u8>, LibrawError> {
+    let mut webp = WebP::from_bytes(Bytes::from(webp))?;
+    webp.set_icc_profile(icc_profile.map(Bytes::from));
+    webp.set_exif(Some(Bytes::from(exif)));
+    set_vp8x_flags(&mut webp);
+    let mut buffer = Vec::new();
+    webp.encoder().write_to(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// img-parts only writes the VP8X flags when it converts a simple file, so the chunk added last
+/// wouldn't be flagged and readers would ignore it
+fn set_vp8x_flags(webp: &mut WebP) {
+    const ICC: u8 = 0x20;
+    const EXIF: u8 = 0x08;
+    let (icc, exif) = (webp.has_chunk(CHUNK_ICCP), webp.has_chunk(CHUNK_EXIF));
+    for chunk in webp.chunks_mut() {
+        if chunk.id() != CHUNK_VP8X {
+            continue;
+        }
+        let data = match chunk.content().data() {
+            Some(data) if !data.is_empty() => data,
+            _ => continue,
+        };
+        let mut data = data.to_vec();
+        data[0] &= !(ICC | EXIF);
+        if icc {
+            data[0] |= ICC;
+        }
+        if exif {
+            data[0] |= EXIF;
+        }
+        *chunk = RiffChunk::new(CHUNK_VP8X, RiffContent::Data(Bytes::from(data)));
+    }
+}
@@ -0,0 +1,273 @@
+//! White balance presets, temperature / tint and the camera's white balance tables
+//!
+//! Temperature and tint use the same scale as Adobe's raw converters: the temperature is the
+//! nearest point on the Planckian locus in CIE 1960 uv and the tint the distance from the locus
+//! along the isotemperature line, times -3000 so positive values are magenta. Converting to
+//! multipliers goes through the camera color matrices ([crate::calibration::ColorCalibration])
+//! so the same numbers give the same white on every body.
+use crate::matrix::xy_to_xyz;
+use crate::{LibrawError, Processor};
+
+const TINT_SCALE: f64 = -3000.0;
+/// Range of the Planckian locus approximation
+const MIN_TEMPERATURE: f64 = 1667.0;
+const MAX_TEMPERATURE: f64 = 25000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WhiteBalance {
+    /// The camera's white balance (`use_camera_wb`)
+    AsShot,
+    /// Gray world over the whole image (`use_auto_wb`)
+    Auto,
+    /// The camera's daylight preset, or libraw's daylight multipliers from the color matrix
+    Daylight,
+    /// Correlated color temperature in kelvin and tint
+    Kelvin { temp: f64, tint: f64 },
+    /// Raw multipliers for R, G, B, G2 (`user_mul`)
+    Multipliers([f32; 4]),
+    /// Gray world over a patch that should be neutral, `[x, y, width, height]` (`greybox`)
+    FromPatch([u32; 4]),
+}
+
+/// A white balance preset reported by the camera (`WB_Coeffs`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WhiteBalancePreset {
+    /// LibRaw_whitebalance_code
+    pub code: u32,
+    pub name: &'static str,
+    /// R, G, B, G2 multipliers normalized to green
+    pub multipliers: [f32; 4],
+}
+
+/// Multipliers the camera reports for a color temperature (`WBCT_Coeffs`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WhiteBalanceTemperature {
+    pub temperature: f32,
+    /// R, G, B, G2 multipliers normalized to green
+    pub multipliers: [f32; 4],
+}
+
+impl Processor {
+    /// Set the white balance params for the next `dcraw_process`
+    ///
+    /// Kelvin needs the camera matrices so the file has to be opened first
+    pub fn set_white_balance(&mut self, white_balance: WhiteBalance) -> Result<(), LibrawError> {
+        let user_mul = match white_balance {
+            WhiteBalance::Kelvin { temp, tint } => self
+                .kelvin_multipliers(temp, tint)
+                .ok_or(LibrawError::MissingColorMatrix)?,
+            WhiteBalance::Multipliers(multipliers) => multipliers,
+            WhiteBalance::Daylight => self
+                .white_balance_presets()
+                .into_iter()
+                .find(|preset| preset.code == sys::LibRaw_whitebalance_code_LIBRAW_WBI_Daylight)
+                .map_or([0.0; 4], |preset| preset.multipliers),
+            _ => [0.0; 4],
+        };
+        let params = self.params();
+        params.use_camera_wb = matches!(white_balance, WhiteBalance::AsShot) as i32;
+        params.use_auto_wb = matches!(
+            white_balance,
+            WhiteBalance::Auto | WhiteBalance::FromPatch(_)
+        ) as i32;
+        params.greybox = match white_balance {
+            WhiteBalance::FromPatch(patch) => patch,
+            _ => [0, 0, u32::MAX, u32::MAX],
+        };
+        params.user_mul = user_mul;
+        Ok(())
+    }
+
+    /// R, G, B, G2 multipliers for a temperature and tint using the camera matrices
+    pub fn kelvin_multipliers(&self, temperature: f64, tint: f64) -> Option<[f32; 4]> {
+        let calibration = self.color_calibration();
+        let (x, y) = temperature_tint_to_xy(temperature, tint);
+        let neutral = calibration
+            .xyz_to_camera(temperature)
+            .mul_vec(xy_to_xyz(x, y));
+        if neutral.iter().any(|v| *v <= 0.0) {
+            return None;
+        }
+        let [r, g, b] = neutral.map(|v| (neutral[1] / v) as f32);
+        Some([r, g, b, g])
+    }
+
+    /// The R, G, B multipliers (green = 1) `dcraw_process` applies with the current params
+    ///
+    /// `user_mul` wins over the camera white balance (`use_camera_wb`), which wins over `pre_mul`.
+    /// `pre_mul` holds libraw's daylight multipliers until the image is processed and the applied
+    /// ones afterwards, so with `use_auto_wb` this is only accurate after `dcraw_process`
+    pub fn applied_white_balance(&self) -> Option<[f64; 3]> {
+        let params = &self.inner().params;
+        let color = self.color();
+        let multipliers = if params.user_mul[0] > 0.0 {
+            params.user_mul
+        } else if params.use_camera_wb != 0 && color.cam_mul.iter().take(3).all(|v| *v > 0.0) {
+            color.cam_mul
+        } else {
+            color.pre_mul
+        };
+        if multipliers.iter().take(3).any(|v| *v <= 0.0) {
+            return None;
+        }
+        let [r, g, b, _] = normalize(multipliers);
+        Some([r, g, b].map(f64::from))
+    }
+
+    /// Temperature and tint of the as shot white balance, for initializing a slider
+    pub fn as_shot_temperature_tint(&self) -> Option<(f64, f64)> {
+        let calibration = self.color_calibration();
+        calibration.temperature_tint(calibration.as_shot_neutral?)
+    }
+
+    /// The named presets the camera stores in the makernotes
+    pub fn white_balance_presets(&self) -> Vec<WhiteBalancePreset> {
+        self.color()
+            .WB_Coeffs
+            .iter()
+            .zip(0..)
+            .filter(|(coeffs, _)| coeffs[0] > 0 && coeffs[1] > 0 && coeffs[2] > 0)
+            .map(|(coeffs, code)| WhiteBalancePreset {
+                code,
+                name: preset_name(code),
+                multipliers: normalize(coeffs.map(|v| v as f32)),
+            })
+            .collect()
+    }
+
+    /// The multipliers the camera stores per color temperature, sorted by temperature
+    pub fn white_balance_temperatures(&self) -> Vec<WhiteBalanceTemperature> {
+        let mut temperatures: Vec<_> = self
+            .color()
+            .WBCT_Coeffs
+            .iter()
+            .filter(|ct| ct[0] > 0.0 && ct[1] > 0.0 && ct[2] > 0.0 && ct[3] > 0.0)
+            .map(|ct| WhiteBalanceTemperature {
+                temperature: ct[0],
+                multipliers: normalize([ct[1], ct[2], ct[3], ct[4]]),
+            })
+            .collect();
+        temperatures.sort_by(|a, b| a.temperature.total_cmp(&b.temperature));
+        temperatures
+    }
+}
+
+/// CIE 1931 xy of a temperature and tint
+pub fn temperature_tint_to_xy(temperature: f64, tint: f64) -> (f64, f64) {
+    let (u, v) = planckian_uv(temperature);
+    let (nu, nv) = locus_normal(temperature);
+    let offset = tint / TINT_SCALE;
+    uv_to_xy(u + nu * offset, v + nv * offset)
+}
+
+/// Temperature and tint of a CIE 1931 xy
+pub fn xy_to_temperature_tint((x, y): (f64, f64)) -> (f64, f64) {
+    let (u, v) = xy_to_uv(x, y);
+    let distance = |mired: f64| {
+        let (lu, lv) = planckian_uv(1e6 / mired);
+        (u - lu).powi(2) + (v - lv).powi(2)
+    };
+    // The distance to the locus is unimodal in mired over the range of the approximation
+    let (mut low, mut high) = (1e6 / MAX_TEMPERATURE, 1e6 / MIN_TEMPERATURE);
+    for _ in 0..100 {
+        let a = low + (high - low) / 3.0;
+        let b = high - (high - low) / 3.0;
+        match distance(a) < distance(b) {
+            true => high = b,
+            false => low = a,
+        }
+    }
+    let temperature = 1e6 / ((low + high) / 2.0);
+    let (lu, lv) = planckian_uv(temperature);
+    let (nu, nv) = locus_normal(temperature);
+    let tint = ((u - lu) * nu + (v - lv) * nv) * TINT_SCALE;
+    (temperature, tint)
+}
+
+/// Point on the Planckian locus (Kim et al. cubic spline) in CIE 1960 uv
+fn planckian_uv(temperature: f64) -> (f64, f64) {
+    let t = temperature.clamp(MIN_TEMPERATURE, MAX_TEMPERATURE);
+    let (t1, t2, t3) = (1e3 / t, 1e6 / (t * t), 1e9 / (t * t * t));
+    let x = match t <= 4000.0 {
+        true => -0.2661239 * t3 - 0.2343589 * t2 + 0.8776956 * t1 + 0.179910,
+        false => -3.0258469 * t3 + 2.1070379 * t2 + 0.2226347 * t1 + 0.240390,
+    };
+    let (a, b, c, d) = match t {
+        t if t <= 2222.0 => (-1.1063814, -1.34811020, 2.18555832, -0.20219683),
+        t if t <= 4000.0 => (-0.9549476, -1.37418593, 2.09137015, -0.16748867),
+        _ => (3.0817580, -5.87338670, 3.75112997, -0.37001483),
+    };
+    let y = a * x.powi(3) + b * x.powi(2) + c * x + d;
+    xy_to_uv(x, y)
+}
+
+/// Unit normal of the locus in uv pointing towards green
+fn locus_normal(temperature: f64) -> (f64, f64) {
+    let mired = 1e6 / temperature.clamp(MIN_TEMPERATURE, MAX_TEMPERATURE);
+    let (u0, v0) = planckian_uv(1e6 / (mired - 0.5));
+    let (u1, v1) = planckian_uv(1e6 / (mired + 0.5));
+    let (du, dv) = (u1 - u0, v1 - v0);
+    let length = du.hypot(dv);
+    let (nu, nv) = (-dv / length, du / length);
+    match nv < 0.0 {
+        true => (-nu, -nv),
+        false => (nu, nv),
+    }
+}
+
+fn xy_to_uv(x: f64, y: f64) -> (f64, f64) {
+    let d = -2.0 * x + 12.0 * y + 3.0;
+    (4.0 * x / d, 6.0 * y / d)
+}
+
+fn uv_to_xy(u: f64, v: f64) -> (f64, f64) {
+    let d = 2.0 * u - 8.0 * v + 4.0;
+    (3.0 * u / d, 2.0 * v / d)
+}
+
+/// Divide by green, a missing second green is set to the first
+fn normalize(multipliers: [f32; 4]) -> [f32; 4] {
+    let [r, g, b, g2] = multipliers;
+    let g2 = if g2 > 0.0 { g2 } else { g };
+    [r, g, b, g2].map(|v| v / g)
+}
+
+fn preset_name(code: u32) -> &'static str {
+    match code {
+        0 => "Unknown",
+        1 => "Daylight",
+        2 => "Fluorescent",
+        3 => "Tungsten",
+        4 => "Flash",
+        9 => "Fine Weather",
+        10 => "Cloudy",
+        11 => "Shade",
+        12 => "Daylight Fluorescent",
+        13 => "Day White Fluorescent",
+        14 => "Cool White Fluorescent",
+        15 => "White Fluorescent",
+        16 => "Warm White Fluorescent",
+        17 => "Standard Light A",
+        18 => "Standard Light B",
+        19 => "Standard Light C",
+        20 => "D55",
+        21 => "D65",
+        22 => "D75",
+        23 => "D50",
+        24 => "ISO Studio Tungsten",
+        64 => "Sunset",
+        65 => "Underwater",
+        66 => "Fluorescent High",
+        67 => "Mercury Vapor",
+        81 => "As Shot",
+        82 => "Auto",
+        83 => "Custom",
+        85..=88 => "Auto",
+        90..=95 => "Custom",
+        96..=100 => "PC Set",
+        110 => "Measured",
+        120 => "Black & White",
+        254 => "Kelvin",
+        _ => "Other",
+    }
+}
@@ -5,21 +5,43 @@ pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 fn main() -> Result<()> {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-env-changed=LIBRAW_DIR");
+    println!("cargo:rerun-if-env-changed=LIBRAW_SYS_USE_PKG_CONFIG");
+    println!("cargo:rerun-if-env-changed=LIBRAW_SYS_STATIC");
+    println!("cargo:rerun-if-env-changed=RAWSPEED_DIR");
 
     let _out_dir = &std::env::var_os("OUT_DIR").unwrap();
     let out_dir = Path::new(_out_dir);
 
-    let libraw_dir = std::env::var("LIBRAW_DIR")
-        .ok()
-        .and_then(|p| {
-            shellexpand::full(&p)
-                .ok()
-                .and_then(|p| dunce::canonicalize(p.to_string()).ok())
-        })
-        .unwrap_or(PathBuf::from(concat!(
-            env!("CARGO_MANIFEST_DIR"),
-            "/vendor"
-        )));
+    let system = cfg!(feature = "system")
+        || std::env::var("LIBRAW_SYS_USE_PKG_CONFIG").as_deref() == Ok("1");
+    if system
+        && cfg!(any(
+            feature = "lcms",
+            feature = "jasper",
+            feature = "rawspeed"
+        ))
+    {
+        return Err(
+            "The lcms, jasper and rawspeed features only apply to the vendored build, \
+            a system LibRaw has to be compiled with them instead"
+                .into(),
+        );
+    }
+
+    let libraw_dir = match system {
+        true => system_libraw()?,
+        false => std::env::var("LIBRAW_DIR")
+            .ok()
+            .and_then(|p| {
+                shellexpand::full(&p)
+                    .ok()
+                    .and_then(|p| dunce::canonicalize(p.to_string()).ok())
+            })
+            .unwrap_or(PathBuf::from(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/vendor"
+            ))),
+    };
 
     // println!("cargo:rerun-if-changed={}", libraw_dir.display());
 
@@ -33,7 +55,21 @@ fn main() -> Result<()> {
         .to_string_lossy()
     );
 
-    build(out_dir, &libraw_dir)?;
+    check_version(&libraw_dir);
+
+    if system && cfg!(any(feature = "jpeg", feature = "zlib")) {
+        println!(
+            "cargo:warning=The jpeg and zlib features are built but unused with a system LibRaw, \
+            disable the default features of libraw-sys"
+        );
+    }
+
+    if !system {
+        build(out_dir, &libraw_dir)?;
+    }
+    abi_shim(&libraw_dir);
+    #[cfg(feature = "rawspeed")]
+    rawspeed_shim(&libraw_dir);
 
     #[cfg(all(feature = "bindgen"))]
     bindings(out_dir, &libraw_dir)?;
@@ -43,6 +79,166 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Find LibRaw with pkg-config, preferring the thread safe libraw_r, and return the directory
+/// containing libraw/libraw.h
+///
+/// pkg-config prints the link flags, set `LIBRAW_SYS_STATIC=1` to link statically
+fn system_libraw() -> Result<PathBuf> {
+    let (major, minor) = bindings_version();
+    let (min, max) = (format!("{major}.{minor}"), format!("{major}.{}", minor + 1));
+    let statik = std::env::var("LIBRAW_SYS_STATIC").as_deref() == Ok("1");
+
+    let mut errors = Vec::new();
+    for name in ["libraw_r", "libraw"] {
+        match pkg_config::Config::new()
+            .range_version(min.as_str()..max.as_str())
+            .statik(statik)
+            .probe(name)
+        {
+            Ok(library) => {
+                return library
+                    .include_paths
+                    .iter()
+                    .find_map(|path| match path.join("libraw").join("libraw.h").exists() {
+                        true => Some(path.clone()),
+                        // Some .pc files list ${includedir}/libraw
+                        false if path.join("libraw.h").exists() => path.parent().map(Into::into),
+                        false => None,
+                    })
+                    .ok_or_else(|| format!("{name}.pc doesn't point to libraw/libraw.h").into());
+            }
+            Err(e) => errors.push(format!("{name}: {e}")),
+        }
+    }
+    Err(format!(
+        "No system LibRaw {min}.x found, the bindings only match the struct layout of that \
+        version.\nUse the vendored build (without the `system` feature and \
+        LIBRAW_SYS_USE_PKG_CONFIG) or install a compatible LibRaw.\n{}",
+        errors.join("\n")
+    )
+    .into())
+}
+
+/// The LibRaw version of the prebuilt bindings for the target, the struct layouts only match
+/// releases with the same major and minor version
+fn bindings_version() -> (u32, u32) {
+    let target_os = std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let target_env = std::env::var("CARGO_CFG_TARGET_ENV").unwrap_or_default();
+    let file = match (target_os.as_str(), target_env.as_str()) {
+        ("windows", "gnu") => "windows_gnu.rs",
+        ("windows", _) => "windows.rs",
+        ("macos", _) => "macos.rs",
+        _ => "linux.rs",
+    };
+    let bindings = Path::new(env!("CARGO_MANIFEST_DIR")).join("src").join(file);
+    let bindings = std::fs::read_to_string(&bindings).expect("Unable to read the bindings");
+    let constant = |name: &str| {
+        bindings
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("pub const {name}: u32 = ")))
+            .and_then(|value| value.trim_end_matches(';').parse().ok())
+            .unwrap_or_else(|| panic!("{name} is missing from {file}"))
+    };
+    (
+        constant("LIBRAW_MAJOR_VERSION"),
+        constant("LIBRAW_MINOR_VERSION"),
+    )
+}
+
+/// Fail unless libraw_version.h matches the prebuilt bindings, with bindgen they are generated
+/// from the same headers
+fn check_version(libraw_dir: impl AsRef<Path>) {
+    if cfg!(feature = "bindgen") {
+        return;
+    }
+    let header = libraw_dir.as_ref().join("libraw").join("libraw_version.h");
+    // Missing sources are reported by build()
+    let Ok(header) = std::fs::read_to_string(header) else {
+        return;
+    };
+    let define = |name: &str| {
+        header.lines().find_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("#define"), Some(define), Some(value)) if define == name => {
+                    value.parse().ok()
+                }
+                _ => None,
+            }
+        })
+    };
+    let headers = (
+        define("LIBRAW_MAJOR_VERSION"),
+        define("LIBRAW_MINOR_VERSION"),
+    );
+    let (major, minor) = bindings_version();
+    if let (Some(header_major), Some(header_minor)) = headers {
+        if (header_major, header_minor) != (major, minor) {
+            panic!(
+                "LibRaw {header_major}.{header_minor} in {} doesn't match the bindings for \
+                {major}.{minor}, enable the bindgen feature to generate bindings for it",
+                libraw_dir.as_ref().display()
+            );
+        }
+    }
+}
+
+/// Compile abi.c which exports the struct sizes of the build's headers for verify_abi
+fn abi_shim(libraw_dir: impl AsRef<Path>) {
+    println!("cargo:rerun-if-changed=src/abi.c");
+    cc::Build::new()
+        .file(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("src")
+                .join("abi.c"),
+        )
+        .include(libraw_dir.as_ref())
+        .warnings(false)
+        .compile("raw_sys_abi");
+}
+
+/// The RawSpeed (v1) checkout and build, with the headers in RawSpeed/ and the library in lib/
+#[cfg(feature = "rawspeed")]
+fn rawspeed_dir() -> PathBuf {
+    std::env::var_os("RAWSPEED_DIR")
+        .map(PathBuf::from)
+        .expect("Set RAWSPEED_DIR to a RawSpeed build for the rawspeed feature")
+}
+
+#[cfg(feature = "rawspeed")]
+fn rawspeed_includes() -> Vec<PathBuf> {
+    let dir = rawspeed_dir();
+    vec![dir.join("include"), dir]
+}
+
+#[cfg(feature = "rawspeed")]
+fn link_rawspeed() -> Result<()> {
+    println!(
+        "cargo:rustc-link-search=native={}",
+        rawspeed_dir().join("lib").display()
+    );
+    println!("cargo:rustc-link-lib=static=rawspeed");
+    // RawSpeed parses its cameras.xml with libxml2
+    pkg_config::probe_library("libxml-2.0")?;
+    Ok(())
+}
+
+/// Compile rawspeed.cpp which exposes LibRaw::set_rawspeed_camerafile to the c api
+#[cfg(feature = "rawspeed")]
+fn rawspeed_shim(libraw_dir: impl AsRef<Path>) {
+    println!("cargo:rerun-if-changed=src/rawspeed.cpp");
+    cc::Build::new()
+        .cpp(true)
+        .file(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("src")
+                .join("rawspeed.cpp"),
+        )
+        .include(libraw_dir.as_ref())
+        .warnings(false)
+        .compile("raw_sys_rawspeed");
+}
+
 fn build(out_dir: impl AsRef<Path>, libraw_dir: impl AsRef<Path>) -> Result<()> {
     std::env::set_current_dir(out_dir.as_ref()).expect("Unable to set current dir");
 
@@ -67,6 +263,18 @@ fn build(out_dir: impl AsRef<Path>, libraw_dir: impl AsRef<Path>) -> Result<()>
     if let Ok(path) = std::env::var("DEP_JPEG_INCLUDE") {
         libraw.includes(std::env::split_paths(&path));
     }
+
+    #[cfg(feature = "lcms")]
+    if let Ok(path) = std::env::var("DEP_LCMS2_INCLUDE") {
+        libraw.includes(std::env::split_paths(&path));
+    }
+
+    // Jasper has no sys crate, it has to be installed
+    #[cfg(feature = "jasper")]
+    libraw.includes(pkg_config::probe_library("jasper")?.include_paths);
+
+    #[cfg(feature = "rawspeed")]
+    libraw.includes(rawspeed_includes());
     // libraw.files(sources);
     // if Path::new("libraw/src/decoders/pana8.cpp").exists() {
     //     libraw.file("libraw/src/decoders/pana8.cpp");
@@ -233,8 +441,14 @@ fn build(out_dir: impl AsRef<Path>, libraw_dir: impl AsRef<Path>) -> Result<()>
     #[cfg(feature = "zlib")]
     libraw.flag("-DUSE_ZLIB");
 
-    // #[cfg(feature = "jasper")]
-    // libraw.flag("-DUSE_JASPER");
+    #[cfg(feature = "lcms")]
+    libraw.flag("-DUSE_LCMS2");
+
+    #[cfg(feature = "jasper")]
+    libraw.flag("-DUSE_JASPER");
+
+    #[cfg(feature = "rawspeed")]
+    libraw.flag("-DUSE_RAWSPEED");
 
     #[cfg(target_os = "linux")]
     libraw.cpp_link_stdlib("stdc++");
@@ -261,6 +475,8 @@ fn build(out_dir: impl AsRef<Path>, libraw_dir: impl AsRef<Path>) -> Result<()>
     }
     #[cfg(feature = "zlib")]
     println!("cargo:rustc-link-lib=static=z");
+    #[cfg(feature = "rawspeed")]
+    link_rawspeed()?;
 
     Ok(())
 }
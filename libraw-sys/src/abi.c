@@ -0,0 +1,19 @@
+/* Sizes of the structs shared with rust in the headers of the build (not the linked LibRaw),
+ * checked by verify_abi() */
+#include <stddef.h>
+#include "libraw/libraw.h"
+
+const size_t libraw_sys_struct_sizes[] = {
+    sizeof(libraw_data_t),
+    sizeof(libraw_iparams_t),
+    sizeof(libraw_image_sizes_t),
+    sizeof(libraw_colordata_t),
+    sizeof(libraw_imgother_t),
+    sizeof(libraw_thumbnail_list_t),
+    sizeof(libraw_output_params_t),
+    sizeof(libraw_raw_unpack_params_t),
+    sizeof(libraw_rawdata_t),
+    sizeof(libraw_lensinfo_t),
+    sizeof(libraw_makernotes_t),
+    sizeof(libraw_shootinginfo_t),
+};
@@ -0,0 +1,103 @@
+//! Check that the linked LibRaw matches the bindings
+//!
+//! The prebuilt bindings are generated for one LibRaw release, linking another one (a system
+//! LibRaw that got updated, a `LIBRAW_DIR` checkout) silently changes the struct layouts.
+//!
+//! LibRaw doesn't export its struct sizes, so only the version comes from the linked library. The
+//! sizes are those of the headers libraw-sys was built with (compiled into abi.c), which catches
+//! headers that don't match the prebuilt bindings but not a shared LibRaw swapped after the build.
+//! With the `bindgen` feature the bindings come from the same headers and the sizes always match.
+use crate::bindings::*;
+use core::fmt;
+use core::mem::size_of;
+
+/// Must be in the same order as `libraw_sys_struct_sizes` in abi.c
+const STRUCTS: [(&str, usize); 12] = [
+    ("libraw_data_t", size_of::<libraw_data_t>()),
+    ("libraw_iparams_t", size_of::<libraw_iparams_t>()),
+    ("libraw_image_sizes_t", size_of::<libraw_image_sizes_t>()),
+    ("libraw_colordata_t", size_of::<libraw_colordata_t>()),
+    ("libraw_imgother_t", size_of::<libraw_imgother_t>()),
+    (
+        "libraw_thumbnail_list_t",
+        size_of::<libraw_thumbnail_list_t>(),
+    ),
+    (
+        "libraw_output_params_t",
+        size_of::<libraw_output_params_t>(),
+    ),
+    (
+        "libraw_raw_unpack_params_t",
+        size_of::<libraw_raw_unpack_params_t>(),
+    ),
+    ("libraw_rawdata_t", size_of::<libraw_rawdata_t>()),
+    ("libraw_lensinfo_t", size_of::<libraw_lensinfo_t>()),
+    ("libraw_makernotes_t", size_of::<libraw_makernotes_t>()),
+    ("libraw_shootinginfo_t", size_of::<libraw_shootinginfo_t>()),
+];
+
+extern "C" {
+    static libraw_sys_struct_sizes: [usize; 12];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbiMismatch {
+    /// The linked LibRaw is another major / minor release than the bindings
+    Version {
+        bindings: (u32, u32),
+        linked: (u32, u32, u32),
+    },
+    /// A struct has a different size in the LibRaw headers of the build
+    Size {
+        name: &'static str,
+        bindings: usize,
+        headers: usize,
+    },
+}
+
+impl fmt::Display for AbiMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Version { bindings, linked } => write!(
+                f,
+                "linked LibRaw {}.{}.{} but the bindings are for {}.{}",
+                linked.0, linked.1, linked.2, bindings.0, bindings.1
+            ),
+            Self::Size {
+                name,
+                bindings,
+                headers,
+            } => write!(
+                f,
+                "{name} is {headers} bytes in the LibRaw headers but {bindings} in the bindings"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AbiMismatch {}
+
+/// Compare the version of the linked LibRaw and the struct sizes of the build's LibRaw headers
+/// with the bindings
+///
+/// Call this once on startup, every other function of this crate is undefined behaviour when it
+/// fails. A shared LibRaw of the same major / minor version is assumed to keep the layouts
+pub fn verify_abi() -> Result<(), AbiMismatch> {
+    let version = unsafe { libraw_versionNumber() } as u32;
+    let linked = (version >> 16, (version >> 8) & 0xff, version & 0xff);
+    let bindings = (LIBRAW_MAJOR_VERSION, LIBRAW_MINOR_VERSION);
+    if (linked.0, linked.1) != bindings {
+        return Err(AbiMismatch::Version { bindings, linked });
+    }
+    let sizes = unsafe { &libraw_sys_struct_sizes };
+    for (&(name, bindings), &headers) in STRUCTS.iter().zip(sizes) {
+        if bindings != headers {
+            return Err(AbiMismatch::Size {
+                name,
+                bindings,
+                headers,
+            });
+        }
+    }
+    Ok(())
+}
@@ -7,9 +7,23 @@
 #![allow(clippy::too_many_arguments)]
 #[cfg(feature = "openmp")]
 extern crate openmp_sys;
+#[cfg(feature = "lcms")]
+extern crate lcms2_sys;
 
 pub use self::bindings::*;
 
+mod abi;
+pub use abi::{verify_abi, AbiMismatch};
+
+#[cfg(feature = "rawspeed")]
+extern "C" {
+    /// `LibRaw::set_rawspeed_camerafile`, which the c api doesn't expose
+    pub fn libraw_sys_set_rawspeed_camerafile(
+        lr: *mut libraw_data_t,
+        path: *const libc::c_char,
+    ) -> libc::c_int;
+}
+
 #[cfg(all(windows, target_env = "msvc", not(feature = "bindgen")))]
 #[path = "windows.rs"]
 mod bindings;
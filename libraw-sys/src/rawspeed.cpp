@@ -0,0 +1,10 @@
+// The c api has no way to load RawSpeed's cameras.xml
+#include "libraw/libraw.h"
+
+extern "C" int libraw_sys_set_rawspeed_camerafile(libraw_data_t *lr, const char *path)
+{
+  if (!lr || !lr->parent_class)
+    return LIBRAW_OUT_OF_ORDER_CALL;
+  LibRaw *libraw = static_cast<LibRaw *>(lr->parent_class);
+  return libraw->set_rawspeed_camerafile(const_cast<char *>(path));
+}
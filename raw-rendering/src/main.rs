@@ -1,5 +1,18 @@
 fn main() {
-    let path = std::env::args().skip(1).next().unwrap();
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap();
+    if path == "cameras" {
+        // raw_rendering cameras [filter]
+        let filter = args.collect::<Vec<_>>().join(" ").to_lowercase();
+        println!("LibRaw {}", libraw_r::runtime_version());
+        for camera in libraw_r::cameras::supported_cameras() {
+            let name = camera.to_string();
+            if name.to_lowercase().contains(&filter) {
+                println!("{name}");
+            }
+        }
+        return;
+    }
     let buffer = &raw_rendering::unpack(path).unwrap();
     std::fs::write(
         "output.json",
@@ -0,0 +1,5 @@
+#[test]
+fn linked_libraw_matches_bindings() {
+    libraw_r::verify_abi().unwrap();
+    assert_eq!(libraw_r::version().minor, libraw_r::runtime_version().minor);
+}
@@ -0,0 +1,37 @@
+use crate::open_asset;
+use libraw_r::LibrawError;
+
+fn is_avif(data: &[u8]) -> bool {
+    data.len() > 12 && &data[4..8] == b"ftyp" && &data[8..12] == b"avif"
+}
+
+#[test]
+fn processed_avif() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    p.params().half_size = 1;
+    assert!(is_avif(&p.to_avif(60, 10).unwrap()));
+}
+
+#[test]
+fn processed_avif_16_bit() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    p.params().half_size = 1;
+    p.params().output_bps = 16;
+    assert!(is_avif(&p.to_avif(60, 10).unwrap()));
+}
+
+#[test]
+fn avif_rejects_non_srgb_output() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    p.params().output_color = 2;
+    assert!(matches!(
+        p.to_avif(60, 10),
+        Err(LibrawError::UnsupportedOutputColor(2))
+    ));
+}
+
+#[test]
+fn thumbnail_avif() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    assert!(is_avif(&p.get_avif(60, 10).unwrap()));
+}
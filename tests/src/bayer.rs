@@ -0,0 +1,203 @@
+#[test]
+fn open_bayer_16bit() {
+    use libraw_r::bayer::*;
+    let layout = BayerLayout {
+        bits: 12,
+        ..BayerLayout::new(
+            64,
+            48,
+            BayerPattern::Rggb,
+            BayerPacking::Unpacked16 { big_endian: false },
+        )
+    };
+    let data: Vec<u8> = (0..64u16 * 48)
+        .flat_map(|i| (i % 4096).to_le_bytes())
+        .collect();
+    assert_eq!(data.len(), layout.buffer_len());
+
+    let mut p = libraw_r::Processor::default();
+    p.open_bayer(data, &layout)
+        .expect("Failed to open bayer data");
+    p.unpack().expect("Failed to unpack");
+    assert_eq!(p.sizes().raw_width, 64);
+    assert_eq!(p.sizes().raw_height, 48);
+    p.dcraw_process().expect("Failed to process");
+
+    let short = vec![0u8; layout.buffer_len() - 1];
+    assert!(p.open_bayer(short, &layout).is_err());
+}
+
+#[test]
+fn open_after_bayer() {
+    use libraw_r::bayer::*;
+    let layout = BayerLayout::new(64, 48, BayerPattern::Rggb, BayerPacking::Unpacked8);
+    let mut p = libraw_r::Processor::default();
+    p.open_bayer(vec![128u8; layout.buffer_len()], &layout)
+        .expect("Failed to open bayer data");
+
+    let nef = std::fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/assets/RAW_NIKON_D3X.NEF"
+    ))
+    .expect("Failed to read file");
+    p.open_buffer(&nef).expect("Failed to open buffer");
+    p.unpack().expect("Failed to unpack");
+    assert_ne!(p.sizes().raw_width, 64);
+
+    p.open_bayer(vec![128u8; layout.buffer_len()], &layout)
+        .expect("Failed to open bayer data");
+    p.unpack().expect("Failed to unpack");
+    assert_eq!(p.sizes().raw_width, 64);
+}
+
+/// Test pattern for a `bits` deep sensor
+fn pattern(width: usize, height: usize, bits: u8) -> Vec<u16> {
+    (0..width * height)
+        .map(|i| ((i * 37 + i / width * 101) % (1 << bits)) as u16)
+        .collect()
+}
+
+/// Opens and unpacks `data`, returns the raw values row by row without the pitch padding
+fn open_and_unpack(data: Vec<u8>, layout: &libraw_r::bayer::BayerLayout) -> Vec<u16> {
+    assert_eq!(data.len(), layout.buffer_len());
+    let mut p = libraw_r::Processor::default();
+    p.open_bayer(data, layout)
+        .expect("Failed to open bayer data");
+    p.unpack().expect("Failed to unpack");
+    let sizes = p.sizes();
+    let width = usize::from(sizes.raw_width);
+    let pitch = sizes.raw_pitch as usize / 2;
+    p.raw_image()
+        .expect("No raw image")
+        .chunks(pitch)
+        .flat_map(|row| &row[..width])
+        .copied()
+        .collect()
+}
+
+#[test]
+fn open_bayer_loose10() {
+    use libraw_r::bayer::*;
+    let (width, height) = (60, 48);
+    let values = pattern(width, height, 10);
+    let data: Vec<u8> = values
+        .chunks(width)
+        .flat_map(|row| {
+            row.chunks(6).flat_map(|group| {
+                let word = group
+                    .iter()
+                    .enumerate()
+                    .fold(0u64, |word, (c, &v)| word | u64::from(v) << (10 * c));
+                word.to_le_bytes()
+            })
+        })
+        .collect();
+    let layout = BayerLayout::new(
+        width as u16,
+        height as u16,
+        BayerPattern::Rggb,
+        BayerPacking::Loose10,
+    );
+    assert_eq!(open_and_unpack(data, &layout), values);
+
+    let layout = BayerLayout {
+        raw_width: 64,
+        ..layout
+    };
+    let mut p = libraw_r::Processor::default();
+    assert!(matches!(
+        p.open_bayer(vec![0u8; layout.buffer_len()], &layout),
+        Err(libraw_r::LibrawError::UnsupportedImageFormat)
+    ));
+}
+
+#[test]
+fn open_bayer_mipi10() {
+    use libraw_r::bayer::*;
+    let (width, height) = (64, 48);
+    let values = pattern(width, height, 10);
+    let stride = BayerPacking::Mipi10.row_stride(width);
+    let data: Vec<u8> = values
+        .chunks(width)
+        .flat_map(|row| {
+            let mut bytes: Vec<u8> = row
+                .chunks(4)
+                .flat_map(|group| {
+                    let low = group
+                        .iter()
+                        .enumerate()
+                        .fold(0u8, |low, (c, &v)| low | ((v & 3) as u8) << (2 * c));
+                    group.iter().map(|&v| (v >> 2) as u8).chain([low])
+                })
+                .collect();
+            bytes.resize(stride, 0);
+            bytes
+        })
+        .collect();
+    let layout = BayerLayout::new(
+        width as u16,
+        height as u16,
+        BayerPattern::Rggb,
+        BayerPacking::Mipi10,
+    );
+    assert_eq!(open_and_unpack(data, &layout), values);
+}
+
+#[test]
+fn open_bayer_packed12() {
+    use libraw_r::bayer::*;
+    // 30 pixels are 45 bytes, padded to 46
+    let (width, height) = (30, 48);
+    let values = pattern(width, height, 12);
+    let stride = BayerPacking::Packed12.row_stride(width);
+    assert_eq!(stride, 46);
+    let data: Vec<u8> = values
+        .chunks(width)
+        .flat_map(|row| {
+            let mut bytes: Vec<u8> = row
+                .chunks(2)
+                .flat_map(|pair| {
+                    let (a, b) = (pair[0], pair[1]);
+                    [(a >> 4) as u8, ((a & 15) << 4 | b >> 8) as u8, b as u8]
+                })
+                .collect();
+            bytes.resize(stride, 0);
+            bytes
+        })
+        .collect();
+    let layout = BayerLayout::new(
+        width as u16,
+        height as u16,
+        BayerPattern::Rggb,
+        BayerPacking::Packed12,
+    );
+    assert_eq!(open_and_unpack(data, &layout), values);
+}
+
+#[test]
+fn row_strides() {
+    use libraw_r::bayer::BayerPacking;
+    assert_eq!(BayerPacking::Loose10.row_stride(60), 80);
+    assert_eq!(BayerPacking::Loose10.row_stride(64), 88);
+    assert_eq!(BayerPacking::Mipi10.row_stride(64), 80);
+    assert_eq!(BayerPacking::Mipi10.row_stride(60), 80);
+    assert_eq!(BayerPacking::Packed10.row_stride(64), 80);
+    assert_eq!(BayerPacking::Packed12.row_stride(64), 96);
+    assert_eq!(BayerPacking::Packed12.row_stride(30), 46);
+    assert_eq!(BayerPacking::Unpacked8.row_stride(64), 64);
+}
+
+#[test]
+fn supported_widths() {
+    use libraw_r::bayer::*;
+    // 88 bytes per row of 64 pixels are 11 bits per pixel
+    assert!(!BayerPacking::Loose10.supports(64, 48));
+    assert!(BayerPacking::Loose10.supports(60, 48));
+    // 80 bytes per row of 60 pixels look like loose RAW10
+    assert!(!BayerPacking::Mipi10.supports(60, 48));
+    assert!(BayerPacking::Mipi10.supports(64, 48));
+    assert!(BayerPacking::Packed10.supports(60, 48));
+    assert!(!BayerPacking::Packed10.supports(62, 48));
+    assert!(!BayerPacking::Packed12.supports(31, 48));
+    assert!(!BayerPacking::Unpacked8.supports(0, 48));
+}
@@ -0,0 +1,117 @@
+use libraw_r::calibration::{ColorCalibration, DngCalibration};
+use libraw_r::colorspace::D50;
+use libraw_r::matrix::Matrix3;
+
+/// The D65 ColorMatrix of the Canon EOS 5D Mark II from the DNG converter (and dcraw)
+const COLOR_MATRIX_D65: Matrix3 = Matrix3([
+    [0.4716, 0.0603, -0.0830],
+    [-0.7798, 1.5474, 0.2480],
+    [-0.1496, 0.1937, 0.6651],
+]);
+/// A made up standard light A ColorMatrix to interpolate with
+const COLOR_MATRIX_A: Matrix3 = Matrix3([
+    [0.5309, -0.0229, -0.0336],
+    [-0.6241, 1.3265, 0.3337],
+    [-0.0817, 0.1215, 0.6664],
+]);
+
+fn dng(illuminant: u16, temperature: f64, color_matrix: Matrix3) -> DngCalibration {
+    DngCalibration {
+        illuminant,
+        temperature,
+        color_matrix,
+        camera_calibration: Matrix3::IDENTITY,
+        forward_matrix: None,
+    }
+}
+
+fn calibration(dng: Vec<DngCalibration>) -> ColorCalibration {
+    ColorCalibration {
+        dng,
+        cam_xyz: Matrix3::IDENTITY,
+        rgb_cam: Matrix3::IDENTITY,
+        analog_balance: [1.0; 3],
+        as_shot_neutral: Some([0.47, 1.0, 0.68]),
+    }
+}
+
+fn assert_close(a: &Matrix3, b: &Matrix3, tolerance: f64) {
+    for r in 0..3 {
+        for c in 0..3 {
+            assert!((a[r][c] - b[r][c]).abs() < tolerance, "{a:?}\n{b:?}");
+        }
+    }
+}
+
+#[test]
+fn interpolation_weight() {
+    let calibration = calibration(vec![
+        dng(17, 2850.0, COLOR_MATRIX_A),
+        dng(21, 6500.0, COLOR_MATRIX_D65),
+    ]);
+    // At the calibrations
+    assert_eq!(calibration.interpolation_weight(2850.0), 1.0);
+    assert_eq!(calibration.interpolation_weight(6500.0), 0.0);
+    // Beyond them the nearest calibration is used as is
+    assert_eq!(calibration.interpolation_weight(2000.0), 1.0);
+    assert_eq!(calibration.interpolation_weight(10000.0), 0.0);
+    // Halfway in mired, not in kelvin
+    let mid = 2.0 / (1.0 / 2850.0 + 1.0 / 6500.0);
+    assert!((calibration.interpolation_weight(mid) - 0.5).abs() < 1e-12);
+    let w = calibration.interpolation_weight(4675.0);
+    assert!(w > 0.0 && w < 0.5, "{w}");
+
+    let halfway = calibration.color_matrix(mid);
+    for r in 0..3 {
+        for c in 0..3 {
+            let expected = (COLOR_MATRIX_A[r][c] + COLOR_MATRIX_D65[r][c]) / 2.0;
+            assert!((halfway[r][c] - expected).abs() < 1e-12);
+        }
+    }
+}
+
+#[test]
+fn single_calibration_weight() {
+    let calibration = calibration(vec![dng(21, 6500.0, COLOR_MATRIX_D65)]);
+    for temperature in [2000.0, 6500.0, 10000.0] {
+        assert_eq!(calibration.interpolation_weight(temperature), 1.0);
+        assert_eq!(calibration.color_matrix(temperature), COLOR_MATRIX_D65);
+    }
+}
+
+#[test]
+fn forward_matrix_agrees_with_color_matrix() {
+    let mut cm_only = calibration(vec![dng(21, 6500.0, COLOR_MATRIX_D65)]);
+    cm_only.analog_balance = [1.05, 1.0, 0.97];
+    cm_only.dng[0].camera_calibration =
+        Matrix3([[1.02, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 0.99]]);
+    let expected = cm_only.camera_to_xyz_d50(6500.0).unwrap();
+
+    // The camera neutral maps to D50 with Y = 1
+    let white = expected.mul_vec(cm_only.camera_neutral());
+    for c in 0..3 {
+        assert!((white[c] - D50[c]).abs() < 1e-4, "{white:?}");
+    }
+
+    // The ForwardMatrix a profile consistent with the ColorMatrix has, FM * D * inv(AB * CC)
+    // has to give the same camera to XYZ transform
+    let balance = Matrix3::diagonal(cm_only.analog_balance) * cm_only.dng[0].camera_calibration;
+    let reference = cm_only.reference_neutral(6500.0).unwrap();
+    let forward = expected * balance * Matrix3::diagonal(reference);
+    // Forward matrices map the reference white (1, 1, 1) to D50
+    let white = forward.mul_vec([1.0; 3]);
+    for c in 0..3 {
+        assert!((white[c] - D50[c]).abs() < 1e-4, "{white:?}");
+    }
+
+    let mut with_forward = cm_only.clone();
+    with_forward.dng[0].forward_matrix = Some(forward);
+    let actual = with_forward.camera_to_xyz_d50(6500.0).unwrap();
+    assert_close(&actual, &expected, 1e-9);
+
+    let balanced = with_forward.white_balanced_to_xyz_d50(6500.0).unwrap();
+    let white = balanced.mul_vec([1.0; 3]);
+    for c in 0..3 {
+        assert!((white[c] - D50[c]).abs() < 1e-4, "{white:?}");
+    }
+}
@@ -0,0 +1,76 @@
+use libraw_r::cameras::{is_supported, supported_cameras, CameraModel};
+
+#[test]
+fn parse_single_word_make() {
+    let camera = CameraModel::parse("Nikon D3X");
+    assert_eq!((camera.make, camera.model), ("Nikon", "D3X"));
+    let camera = CameraModel::parse("Canon EOS 5D Mark IV");
+    assert_eq!((camera.make, camera.model), ("Canon", "EOS 5D Mark IV"));
+}
+
+#[test]
+fn parse_multi_word_make() {
+    let camera = CameraModel::parse("Phase One IQ4 150MP");
+    assert_eq!((camera.make, camera.model), ("Phase One", "IQ4 150MP"));
+    let camera = CameraModel::parse("OM Digital Solutions OM-1");
+    assert_eq!(
+        (camera.make, camera.model),
+        ("OM Digital Solutions", "OM-1")
+    );
+    // Only a whole word matches a multi word make
+    let camera = CameraModel::parse("Photon X");
+    assert_eq!((camera.make, camera.model), ("Photon", "X"));
+}
+
+#[test]
+fn parse_keeps_the_note() {
+    let camera = CameraModel::parse("Sony ILCE-7RM4 (A7R IV)");
+    assert_eq!((camera.make, camera.model), ("Sony", "ILCE-7RM4 (A7R IV)"));
+    assert_eq!(camera.to_string(), "Sony ILCE-7RM4 (A7R IV)");
+}
+
+#[test]
+fn parse_make_only() {
+    let camera = CameraModel::parse("Sinar");
+    assert_eq!((camera.make, camera.model), ("Sinar", ""));
+}
+
+#[test]
+fn matches_exif_company_names() {
+    let nikon = CameraModel::parse("Nikon D3X");
+    assert!(nikon.matches("NIKON CORPORATION", "NIKON D3X"));
+    assert!(nikon.matches("Nikon", "D3X"));
+    assert!(!nikon.matches("NIKON CORPORATION", "NIKON D3S"));
+
+    let olympus = CameraModel::parse("Olympus E-M1 Mark II");
+    assert!(olympus.matches("OLYMPUS IMAGING CORP.", "E-M1MarkII"));
+    assert!(olympus.matches("OLYMPUS IMAGING CORP.", "E-M1 Mark II"));
+    assert!(!olympus.matches("OLYMPUS IMAGING CORP.", "E-M1MarkIII"));
+
+    let leica = CameraModel::parse("Leica M10");
+    assert!(leica.matches("LEICA CAMERA AG", "LEICA M10"));
+
+    let kodak = CameraModel::parse("Kodak DCS Pro 14N");
+    assert!(kodak.matches("EASTMAN KODAK COMPANY", "DCS Pro 14N"));
+}
+
+#[test]
+fn matches_ignores_the_note() {
+    let sony = CameraModel::parse("Sony ILCE-7RM4 (A7R IV)");
+    assert!(sony.matches("SONY", "ILCE-7RM4"));
+    assert!(!sony.matches("SONY", "A7R IV"));
+}
+
+#[test]
+fn matches_needs_the_same_make() {
+    let camera = CameraModel::parse("Canon EOS R5");
+    assert!(camera.matches("Canon", "Canon EOS R5"));
+    assert!(!camera.matches("Nikon", "EOS R5"));
+}
+
+#[test]
+fn supported_cameras_from_libraw() {
+    assert!(supported_cameras().count() > 100);
+    assert!(is_supported("NIKON CORPORATION", "NIKON D3X"));
+    assert!(!is_supported("NIKON CORPORATION", "NIKON D9999"));
+}
@@ -0,0 +1,40 @@
+use crate::open_asset;
+use libraw_r::capabilities::{Capabilities, DecoderFlags};
+use libraw_r::{LibrawError, Processor};
+
+#[test]
+fn require_current() {
+    let current = Capabilities::current();
+    assert_eq!(
+        Capabilities::require(Capabilities::empty()).unwrap(),
+        current
+    );
+    assert_eq!(Capabilities::require(current).unwrap(), current);
+}
+
+#[test]
+fn require_reports_only_the_missing() {
+    let current = Capabilities::current();
+    let missing = Capabilities::all() - current;
+    if missing.is_empty() {
+        return;
+    }
+    match Capabilities::require(current | missing) {
+        Err(LibrawError::MissingCapabilities(reported)) => assert_eq!(reported, missing),
+        other => panic!("expected missing capabilities, got {other:?}"),
+    }
+}
+
+#[test]
+fn decoder_info_needs_an_open_file() {
+    assert!(Processor::default().decoder_info().is_err());
+}
+
+#[test]
+fn decoder_info() {
+    let p = open_asset("RAW_NIKON_D3X.NEF");
+    let info = p.decoder_info().unwrap();
+    assert!(info.name.ends_with("_load_raw()"), "{info}");
+    assert_eq!(Some(info.name), p.unpack_function_name());
+    assert!(!info.flags.contains(DecoderFlags::UNSUPPORTED_FORMAT));
+}
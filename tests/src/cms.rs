@@ -0,0 +1,106 @@
+use crate::open_asset;
+use libraw_r::cms::{ColorProfile, ColorTarget, RenderingIntent};
+use libraw_r::colorspace::{GammaCurve, OutputColorSpace, D50, D65};
+use libraw_r::icc::matrix_trc_profile;
+use libraw_r::matrix::Matrix3;
+
+/// A linear camera profile with crosstalk between the channels, the rows of the mixing matrix
+/// sum to one so white balanced neutrals stay neutral
+fn camera() -> ColorProfile {
+    let mixing = Matrix3([[1.3, -0.2, -0.1], [-0.25, 1.4, -0.15], [0.05, -0.35, 1.3]]);
+    let to_xyz = OutputColorSpace::Srgb.to_xyz_d50().unwrap() * mixing.inverse().unwrap();
+    let icc = matrix_trc_profile(
+        "Test camera",
+        &to_xyz,
+        &Matrix3::bradford(D65, D50),
+        &GammaCurve::new(GammaCurve::LINEAR.0, GammaCurve::LINEAR.1),
+    );
+    ColorProfile::new_from_slice(&icc).unwrap()
+}
+
+/// Gray patches from deep shadow to white
+fn grays() -> Vec<f32> {
+    [0.02, 0.1, 0.18, 0.5, 0.9, 1.0]
+        .iter()
+        .flat_map(|v| [*v; 3])
+        .collect()
+}
+
+fn assert_neutral(target: &ColorTarget) {
+    let out = target.convert(&camera(), grays()).unwrap();
+    let mut last = 0.0;
+    for rgb in out.chunks_exact(3) {
+        let spread = rgb.iter().fold(0.0_f32, |m, v| m.max((v - rgb[1]).abs()));
+        assert!(spread < 2e-3, "{rgb:?}");
+        // Brighter in, brighter out
+        assert!(rgb[1] > last, "{out:?}");
+        last = rgb[1];
+    }
+    // Camera white maps to output white
+    assert!(
+        out[out.len() - 3..].iter().all(|v| (v - 1.0).abs() < 2e-3),
+        "{out:?}"
+    );
+}
+
+#[test]
+fn neutral_to_srgb() {
+    assert_neutral(&ColorTarget::srgb());
+    assert_neutral(&ColorTarget::srgb().with_intent(RenderingIntent::RelativeColorimetric));
+}
+
+#[test]
+fn neutral_to_display_p3() {
+    assert_neutral(&ColorTarget::display_p3());
+}
+
+#[test]
+fn colors_differ_between_targets() {
+    // A saturated camera red lands on different values in sRGB and Display P3
+    let red = vec![0.8, 0.1, 0.1];
+    let srgb = ColorTarget::srgb().convert(&camera(), red.clone()).unwrap();
+    let p3 = ColorTarget::display_p3().convert(&camera(), red).unwrap();
+    assert!((srgb[0] - p3[0]).abs() > 0.01, "{srgb:?} {p3:?}");
+}
+
+#[test]
+fn proofing_to_the_output_profile_is_an_identity() {
+    let mut patches = grays();
+    patches.extend([0.8, 0.1, 0.1, 0.1, 0.6, 0.2, 0.15, 0.2, 0.7, 0.4, 0.35, 0.3]);
+    for (target, proof) in [
+        (ColorTarget::srgb(), ColorProfile::new_srgb()),
+        (ColorTarget::display_p3(), ColorProfile::new_display_p3()),
+    ] {
+        let direct = target
+            .clone()
+            .with_intent(RenderingIntent::RelativeColorimetric)
+            .convert(&camera(), patches.clone())
+            .unwrap();
+        let proofed = target
+            .with_proof(proof, RenderingIntent::RelativeColorimetric)
+            .convert(&camera(), patches.clone())
+            .unwrap();
+        for (a, b) in direct.iter().zip(&proofed) {
+            assert!((a - b).abs() < 2e-3, "{direct:?}\n{proofed:?}");
+        }
+    }
+}
+
+#[test]
+fn color_managed_from_a_file() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    p.params().half_size = 1;
+    let output_bps = p.params().output_bps;
+    let target = ColorTarget::display_p3();
+    let image = p.to_color_managed(&target).unwrap();
+    assert_eq!(image.channels, 3);
+    assert!(image.width > 0 && image.height > 0);
+    assert_eq!(
+        image.pixels.len(),
+        image.width as usize * image.height as usize * 3
+    );
+    assert!(image.pixels.iter().any(|v| *v > 0));
+    assert_eq!(image.icc, target.profile.encode().unwrap());
+    // The overridden params are restored
+    assert_eq!(p.params().output_bps, output_bps);
+}
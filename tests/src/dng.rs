@@ -0,0 +1,65 @@
+use crate::open_asset;
+use libraw_r::dng::DngKind;
+use libraw_r::Processor;
+
+/// The visible area of the raw data
+fn visible(p: &Processor) -> Vec<u16> {
+    let sizes = p.sizes();
+    let raw = p.raw_image().unwrap();
+    let pitch = sizes.raw_pitch as usize / 2;
+    let (top, left) = (sizes.top_margin as usize, sizes.left_margin as usize);
+    (0..sizes.height as usize)
+        .flat_map(|row| &raw[(row + top) * pitch + left..][..sizes.width as usize])
+        .copied()
+        .collect()
+}
+
+#[test]
+fn cfa_dng_round_trip() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    p.unpack().expect("Failed to unpack");
+    let dng = p.to_dng(DngKind::Cfa).unwrap();
+
+    let mut q = Processor::default();
+    q.open_buffer(&dng).unwrap();
+    q.unpack().unwrap();
+    assert_eq!(q.idata().filters, p.idata().filters);
+    assert_eq!(q.idata().colors, p.idata().colors);
+    assert_eq!(
+        (q.sizes().width, q.sizes().height),
+        (p.sizes().width, p.sizes().height)
+    );
+    assert_eq!(
+        q.black_levels().per_cfa_position(),
+        p.black_levels().per_cfa_position()
+    );
+    assert_eq!(q.color().maximum, p.color().maximum);
+    assert!(visible(&q) == visible(&p));
+    q.dcraw_process().unwrap();
+}
+
+#[test]
+fn linear_dng_round_trip() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    p.unpack().expect("Failed to unpack");
+    let (pre_mul, cam_mul) = (p.color().pre_mul, p.color().cam_mul);
+    let dng = p.to_dng(DngKind::Linear).unwrap();
+    // Processing for the linear data doesn't leak into the processor
+    assert_eq!(p.color().pre_mul, pre_mul);
+
+    let mut q = Processor::default();
+    q.open_buffer(&dng).unwrap();
+    q.unpack().unwrap();
+    assert_eq!(q.idata().filters, 0);
+    assert_eq!(q.idata().colors, 3);
+    assert_eq!(
+        (q.sizes().width, q.sizes().height),
+        (p.sizes().iwidth, p.sizes().iheight)
+    );
+    // AsShotNeutral is read back as the camera multipliers
+    let ratio = |mul: [f32; 4], c: usize| mul[c] / mul[1];
+    for c in [0, 2] {
+        assert!((ratio(q.color().cam_mul, c) - ratio(cam_mul, c)).abs() < 0.01);
+    }
+    q.dcraw_process().unwrap();
+}
@@ -21,3 +21,27 @@ fn exiftest() {
     assert_eq!(1, exif.errors().unwrap().len());
     assert_eq!(92, exif.data().unwrap());
 }
+
+#[test]
+fn exif_tree() {
+    use libraw_r::exif::DataStreamType;
+    use libraw_r::exif_tree::{ExifIfd, Tag};
+    use libraw_r::*;
+    let mut p = Processor::default();
+    let collector = p.exif_collector(DataStreamType::File).unwrap();
+    p.open(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/assets/RAW_NIKON_D3X.NEF"
+    ))
+    .unwrap();
+    let exif = collector.data().unwrap();
+    assert_eq!(
+        Some("NIKON CORPORATION"),
+        exif.get(Tag::Make).and_then(|v| v.as_str())
+    );
+    assert!(exif.ifd(ExifIfd::Exif).is_some());
+    assert!(exif
+        .get(Tag::ExposureTime)
+        .and_then(|v| v.as_f64())
+        .is_some());
+}
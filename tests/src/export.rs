@@ -0,0 +1,190 @@
+use std::io::Cursor;
+
+use crate::open_asset;
+use libraw_r::export::{write, Compression, ExportFormat, ExportMetadata, ExportOptions};
+use libraw_r::metadata::CameraMetadata;
+use libraw_r::{LibrawError, ProcessedImage};
+use tiff::tags::Tag;
+
+/// Big enough for several tiff strips at 16 bits
+const WIDTH: u16 = 200;
+const HEIGHT: u16 = 130;
+
+fn samples(colors: u16) -> Vec<u16> {
+    (0..usize::from(WIDTH) * usize::from(HEIGHT) * usize::from(colors))
+        .map(|i| (i * 257 % 65536) as u16)
+        .collect()
+}
+
+fn image(colors: u16, bits: u16) -> ProcessedImage {
+    let samples = samples(colors);
+    match bits {
+        8 => {
+            let samples = samples.iter().map(|v| (v >> 8) as u8).collect::<Vec<_>>();
+            ProcessedImage::from_bitmap(WIDTH, HEIGHT, colors, &samples).unwrap()
+        }
+        _ => ProcessedImage::from_bitmap(WIDTH, HEIGHT, colors, &samples).unwrap(),
+    }
+}
+
+fn metadata() -> ExportMetadata {
+    ExportMetadata {
+        icc_profile: Some(b"not really an icc profile".to_vec()),
+        exif: Some(CameraMetadata {
+            make: "Pentax".into(),
+            model: "K-1".into(),
+            iso_speed: 100.0,
+            ..Default::default()
+        }),
+    }
+}
+
+fn export(image: &ProcessedImage, format: ExportFormat, bits: Option<u16>) -> Vec<u8> {
+    let options = ExportOptions {
+        format,
+        bits,
+        ..Default::default()
+    };
+    let mut out = Vec::new();
+    write(image, &metadata(), &options, &mut out).unwrap();
+    out
+}
+
+/// The decoded pixels widened to 16 bits
+fn decode(data: &[u8], format: image::ImageFormat) -> (u32, u32, u8, Vec<u16>) {
+    let decoded = image::load_from_memory_with_format(data, format).unwrap();
+    let channels = decoded.color().channel_count();
+    let (width, height) = (decoded.width(), decoded.height());
+    let bits = decoded.color().bits_per_pixel() / u16::from(channels);
+    let pixels = match bits {
+        8 => decoded
+            .as_bytes()
+            .iter()
+            .map(|v| u16::from(*v) << 8)
+            .collect(),
+        _ => match channels {
+            1 => decoded.into_luma16().into_raw(),
+            _ => decoded.into_rgb16().into_raw(),
+        },
+    };
+    (width, height, channels, pixels)
+}
+
+/// 8 bit samples are compared through their top byte
+fn expected(colors: u16, bits: u16) -> Vec<u16> {
+    match bits {
+        8 => samples(colors).iter().map(|v| v & 0xff00).collect(),
+        _ => samples(colors),
+    }
+}
+
+#[test]
+fn tiff_round_trip() {
+    for compression in [Compression::None, Compression::Lzw, Compression::Deflate] {
+        for colors in [1, 3] {
+            for bits in [8, 16] {
+                let data = export(&image(colors, bits), ExportFormat::Tiff(compression), None);
+                let (width, height, channels, pixels) = decode(&data, image::ImageFormat::Tiff);
+                let case = format!("{compression:?} {colors} colors {bits} bits");
+                assert_eq!((width, height), (WIDTH.into(), HEIGHT.into()), "{case}");
+                assert_eq!(u16::from(channels), colors, "{case}");
+                assert!(pixels == expected(colors, bits), "{case}");
+
+                let mut decoder = tiff::decoder::Decoder::new(Cursor::new(&data)).unwrap();
+                let strips = decoder.get_tag_u32_vec(Tag::StripOffsets).unwrap();
+                if colors == 3 && bits == 16 {
+                    assert!(strips.len() > 1, "{case}");
+                }
+                let predictor = decoder.find_tag(Tag::Predictor).unwrap();
+                match compression {
+                    Compression::None => assert!(predictor.is_none(), "{case}"),
+                    _ => assert_eq!(predictor.unwrap().into_u16().unwrap(), 2, "{case}"),
+                }
+                let icc = decoder.get_tag_u8_vec(Tag::Unknown(0x8773)).unwrap();
+                assert_eq!(icc, metadata().icc_profile.unwrap(), "{case}");
+                let make = decoder.get_tag_ascii_string(Tag::Make).unwrap();
+                assert_eq!(make, "Pentax", "{case}");
+            }
+        }
+    }
+}
+
+/// The data of the first chunk of a type
+fn png_chunk<'a>(png: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut rest = &png[8..];
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        if &rest[4..8] == kind {
+            return Some(&rest[8..8 + len]);
+        }
+        rest = &rest[12 + len..];
+    }
+    None
+}
+
+#[test]
+fn png_round_trip() {
+    for colors in [1, 3] {
+        for bits in [8, 16] {
+            let data = export(&image(colors, bits), ExportFormat::Png, None);
+            let (width, height, channels, pixels) = decode(&data, image::ImageFormat::Png);
+            let case = format!("{colors} colors {bits} bits");
+            assert_eq!((width, height), (WIDTH.into(), HEIGHT.into()), "{case}");
+            assert_eq!(u16::from(channels), colors, "{case}");
+            assert!(pixels == expected(colors, bits), "{case}");
+
+            let reader = png::Decoder::new(Cursor::new(&data)).read_info().unwrap();
+            let info = reader.info();
+            let icc = info.icc_profile.as_deref().unwrap();
+            assert_eq!(icc, metadata().icc_profile.unwrap(), "{case}");
+            // The png crate doesn't read eXIf, so the chunk is looked up by hand
+            let exif = png_chunk(&data, b"eXIf").unwrap();
+            assert_eq!(exif, metadata().exif.unwrap().to_exif(), "{case}");
+        }
+    }
+}
+
+#[test]
+fn bit_depth_conversion() {
+    let data = export(&image(3, 16), ExportFormat::Png, Some(8));
+    let (_, _, _, pixels) = decode(&data, image::ImageFormat::Png);
+    assert!(pixels == expected(3, 8));
+
+    let data = export(&image(3, 8), ExportFormat::Tiff(Compression::Lzw), Some(16));
+    let (_, _, _, pixels) = decode(&data, image::ImageFormat::Tiff);
+    let widened = expected(3, 8)
+        .iter()
+        .map(|v| (v >> 8) * 257)
+        .collect::<Vec<_>>();
+    assert!(pixels == widened);
+}
+
+#[test]
+fn unsupported_bit_depth_writes_nothing() {
+    let options = ExportOptions {
+        bits: Some(12),
+        ..Default::default()
+    };
+    let mut out = Vec::new();
+    let e = write(&image(3, 16), &metadata(), &options, &mut out).unwrap_err();
+    assert!(matches!(e, LibrawError::InvalidColor(12)), "{e}");
+    assert!(out.is_empty());
+
+    let path = std::env::temp_dir().join(format!("libraw-export-{}.tiff", std::process::id()));
+    std::fs::write(&path, b"keep").unwrap();
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    let e = p.export(&path, &options).unwrap_err();
+    assert!(matches!(e, LibrawError::InvalidColor(12)), "{e}");
+    // The existing file isn't truncated
+    assert_eq!(std::fs::read(&path).unwrap(), b"keep");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn from_bitmap_checks_the_length() {
+    assert!(ProcessedImage::from_bitmap::<u8>(2, 2, 3, &[0; 11]).is_err());
+    assert!(ProcessedImage::from_bitmap::<u8>(2, 2, 4, &[0; 16]).is_err());
+    let image = ProcessedImage::from_bitmap::<u16>(2, 2, 1, &[1, 2, 3, 4]).unwrap();
+    assert_eq!((image.width(), image.height(), image.bits()), (2, 2, 16));
+    assert_eq!(image.as_slice_u16(), [1, 2, 3, 4]);
+}
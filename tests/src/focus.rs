@@ -0,0 +1,66 @@
+use libraw_r::bayer::{BayerLayout, BayerPacking, BayerPattern};
+use libraw_r::focus::{FocusMetric, FocusOptions};
+use libraw_r::Processor;
+
+const SIZE: usize = 256;
+
+/// 12 bit checkerboard of 8 pixel squares
+fn checkerboard(row: usize, col: usize) -> f64 {
+    match (row / 8 + col / 8) % 2 {
+        0 => 400.0,
+        _ => 3600.0,
+    }
+}
+
+/// The checkerboard averaged over a `radius` box
+fn blurred(radius: usize) -> impl Fn(usize, usize) -> f64 {
+    move |row, col| {
+        let (mut sum, mut count) = (0.0, 0.0);
+        for r in row.saturating_sub(radius)..(row + radius + 1).min(SIZE) {
+            for c in col.saturating_sub(radius)..(col + radius + 1).min(SIZE) {
+                sum += checkerboard(r, c);
+                count += 1.0;
+            }
+        }
+        sum / count
+    }
+}
+
+fn score(image: impl Fn(usize, usize) -> f64, metric: FocusMetric) -> f64 {
+    let layout = BayerLayout {
+        bits: 12,
+        ..BayerLayout::new(
+            SIZE as u16,
+            SIZE as u16,
+            BayerPattern::Rggb,
+            BayerPacking::Unpacked16 { big_endian: false },
+        )
+    };
+    let data: Vec<u8> = (0..SIZE * SIZE)
+        .flat_map(|i| (image(i / SIZE, i % SIZE) as u16).to_le_bytes())
+        .collect();
+    let mut p = Processor::default();
+    p.open_bayer(data, &layout).unwrap();
+    p.unpack().unwrap();
+    let options = FocusOptions {
+        metric,
+        ..Default::default()
+    };
+    let score = p.focus_score(&options).unwrap();
+    assert_eq!((score.width, score.height), (SIZE / 2, SIZE / 2));
+    assert_eq!(score.tiles.len(), 64);
+    score.score
+}
+
+#[test]
+fn blur_lowers_focus_score() {
+    for metric in [FocusMetric::LaplacianVariance, FocusMetric::Tenengrad] {
+        let sharp = score(checkerboard, metric);
+        let soft = score(blurred(2), metric);
+        let softer = score(blurred(6), metric);
+        assert!(
+            sharp > soft && soft > softer,
+            "{metric:?} {sharp} {soft} {softer}"
+        );
+    }
+}
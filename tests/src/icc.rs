@@ -0,0 +1,80 @@
+use libraw_r::colorspace::{GammaCurve, OutputColorSpace};
+use libraw_r::icc::{IccSummary, ProfileClass};
+
+/// A v4.3 CMYK printer profile with only a multiLocalizedUnicodeType desc tag
+fn cmyk_v4_profile(description: &str) -> Vec<u8> {
+    let text: Vec<u8> = description
+        .encode_utf16()
+        .flat_map(|c| c.to_be_bytes())
+        .collect();
+    let mut desc = b"mluc\0\0\0\0".to_vec();
+    desc.extend(1_u32.to_be_bytes());
+    desc.extend(12_u32.to_be_bytes());
+    desc.extend(b"enUS");
+    desc.extend((text.len() as u32).to_be_bytes());
+    desc.extend(28_u32.to_be_bytes());
+    desc.extend(&text);
+
+    let mut icc = vec![0_u8; 128];
+    icc[8..12].copy_from_slice(&[4, 0x30, 0, 0]);
+    icc[12..16].copy_from_slice(b"prtr");
+    icc[16..20].copy_from_slice(b"CMYK");
+    icc[20..24].copy_from_slice(b"Lab ");
+    icc[36..40].copy_from_slice(b"acsp");
+    icc.extend(1_u32.to_be_bytes());
+    icc.extend(b"desc");
+    icc.extend(144_u32.to_be_bytes());
+    icc.extend((desc.len() as u32).to_be_bytes());
+    icc.extend(desc);
+    let size = icc.len() as u32;
+    icc[..4].copy_from_slice(&size.to_be_bytes());
+    icc
+}
+
+#[test]
+fn parse_v4_printer_profile() {
+    let summary = IccSummary::parse(&cmyk_v4_profile("Coated FOGRA39")).unwrap();
+    assert_eq!(summary.description.as_deref(), Some("Coated FOGRA39"));
+    assert_eq!(summary.color_space, "CMYK");
+    assert_eq!(summary.class, ProfileClass::Output);
+    assert!(summary.class.is_output());
+    assert_eq!(summary.version, (4, 3));
+}
+
+#[test]
+fn parse_generated_srgb_profile() {
+    let gamma = GammaCurve::new(GammaCurve::SRGB.0, GammaCurve::SRGB.1);
+    let icc = OutputColorSpace::Srgb.icc_profile(&gamma).unwrap();
+    let summary = IccSummary::parse(&icc).unwrap();
+    assert_eq!(summary.description.as_deref(), Some("sRGB"));
+    assert_eq!(summary.color_space, "RGB");
+    assert_eq!(summary.class, ProfileClass::Display);
+    assert_eq!(summary.version.0, 2);
+}
+
+#[test]
+fn parse_rejects_other_data() {
+    assert_eq!(IccSummary::parse(&[0; 200]), None);
+    assert_eq!(IccSummary::parse(&cmyk_v4_profile("x")[..100]), None);
+}
+
+#[test]
+fn parse_without_description() {
+    let mut icc = cmyk_v4_profile("Coated FOGRA39");
+    icc[128..132].copy_from_slice(&0_u32.to_be_bytes());
+    let summary = IccSummary::parse(&icc).unwrap();
+    assert_eq!(summary.description, None);
+}
+
+#[test]
+fn parse_huge_tag_count() {
+    let mut icc = cmyk_v4_profile("Coated FOGRA39");
+    icc[128..132].copy_from_slice(&u32::MAX.to_be_bytes());
+    // Only the entries that fit in the profile are read, the desc tag is still the first one
+    let summary = IccSummary::parse(&icc).unwrap();
+    assert_eq!(summary.description.as_deref(), Some("Coated FOGRA39"));
+
+    icc[132..136].copy_from_slice(b"wtpt");
+    let summary = IccSummary::parse(&icc).unwrap();
+    assert_eq!(summary.description, None);
+}
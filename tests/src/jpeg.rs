@@ -0,0 +1,293 @@
+use img_parts::jpeg::{markers, Jpeg, JpegSegment};
+use img_parts::{Bytes, ImageEXIF, ImageICC};
+use libraw_r::jpeg::{
+    copy_metadata, set_orientation, ImageJpeg, JpegEncoder, JpegOptions, TurboJpeg,
+};
+use libraw_r::metadata::CameraMetadata;
+use libraw_r::Orientation;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+const ICC: &[u8] = b"not really an icc profile";
+
+/// A gradient with some noise so the quality makes a difference in size
+fn pixels(channels: u32) -> Vec<u8> {
+    (0..WIDTH * HEIGHT * channels)
+        .map(|i| (i / channels % WIDTH * 4 + i % 7 * 9) as u8)
+        .collect()
+}
+
+fn parse(jpeg: &[u8]) -> Jpeg {
+    Jpeg::from_bytes(Bytes::copy_from_slice(jpeg)).unwrap()
+}
+
+/// Type, count and value (or offset) of a tag in IFD0 of a TIFF structure, SHORT values are
+/// returned as is
+fn ifd0_entry(tiff: &[u8], tag: u16) -> Option<(u16, u32, u32)> {
+    let little_endian = &tiff[..2] == b"II";
+    let u16_at = |offset: usize| {
+        let bytes = tiff[offset..offset + 2].try_into().unwrap();
+        match little_endian {
+            true => u16::from_le_bytes(bytes),
+            false => u16::from_be_bytes(bytes),
+        }
+    };
+    let u32_at = |offset: usize| {
+        let bytes = tiff[offset..offset + 4].try_into().unwrap();
+        match little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        }
+    };
+    let ifd0 = u32_at(4) as usize;
+    let entry = (0..usize::from(u16_at(ifd0)))
+        .map(|i| ifd0 + 2 + 12 * i)
+        .find(|entry| u16_at(*entry) == tag)?;
+    let kind = u16_at(entry + 2);
+    let value = match kind {
+        3 => u32::from(u16_at(entry + 8)),
+        _ => u32_at(entry + 8),
+    };
+    Some((kind, u32_at(entry + 4), value))
+}
+
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    ifd0_entry(tiff, 0x0112).map(|(_, _, value)| value as u16)
+}
+
+/// Encode with a backend and check the output decodes, follows the quality and keeps the
+/// metadata added around it
+fn check_encoder(encoder: &dyn JpegEncoder) {
+    for (color, channels) in [(image::ColorType::L8, 1), (image::ColorType::Rgb8, 3)] {
+        let pixels = pixels(channels);
+        let encode = |quality| {
+            let options = JpegOptions::default().with_quality(quality);
+            encoder
+                .encode(&pixels, WIDTH, HEIGHT, color, &options)
+                .unwrap()
+        };
+        let (low, high) = (encode(20), encode(95));
+        assert!(low.len() < high.len(), "{} < {}", low.len(), high.len());
+
+        let decoded = image::load_from_memory_with_format(&high, image::ImageFormat::Jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (WIDTH, HEIGHT));
+        assert_eq!(decoded.color(), color);
+
+        let mut source = parse(&low);
+        source.set_icc_profile(Some(Bytes::from_static(ICC)));
+        let mut buffer = Vec::new();
+        source.encoder().write_to(&mut buffer).unwrap();
+        let metadata = CameraMetadata {
+            make: "Pentax".into(),
+            ..Default::default()
+        };
+        let jpeg = copy_metadata(&buffer, high).unwrap();
+        let jpeg = set_orientation(jpeg, Orientation::CW90, Some(&metadata)).unwrap();
+        let jpeg = parse(&jpeg);
+        assert_eq!(jpeg.icc_profile().as_deref(), Some(ICC));
+        let exif = jpeg.exif().unwrap();
+        assert_eq!(tiff_orientation(&exif), Some(6));
+        let app = jpeg
+            .segments()
+            .iter()
+            .map(|segment| segment.marker())
+            .filter(|marker| (markers::APP0..=markers::APP15).contains(marker))
+            .collect::<Vec<_>>();
+        assert!(app.contains(&markers::APP1) && app.contains(&markers::APP2));
+    }
+}
+
+#[test]
+fn default_quality() {
+    assert_eq!(JpegOptions::default().quality, 75);
+    assert_eq!(JpegOptions::default().with_quality(30).quality, 30);
+}
+
+#[test]
+fn image_jpeg() {
+    check_encoder(&ImageJpeg);
+}
+
+#[test]
+fn turbojpeg() {
+    check_encoder(&TurboJpeg);
+}
+
+#[test]
+#[cfg(feature = "mozjpeg")]
+fn mozjpeg() {
+    check_encoder(&libraw_r::jpeg::MozJpeg);
+}
+
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// A TIFF structure with ImageWidth 8, Make "Pentax" stored after IFD0 and optionally an
+/// Orientation of 1
+fn tiff(little_endian: bool, orientation: bool) -> Vec<u8> {
+    let u16_bytes = |v: u16| match little_endian {
+        true => v.to_le_bytes(),
+        false => v.to_be_bytes(),
+    };
+    let u32_bytes = |v: u32| match little_endian {
+        true => v.to_le_bytes(),
+        false => v.to_be_bytes(),
+    };
+    let count = 2 + u16::from(orientation);
+    let make_offset = 8 + 2 + 12 * u32::from(count) + 4;
+    let mut tiff = match little_endian {
+        true => b"II".to_vec(),
+        false => b"MM".to_vec(),
+    };
+    tiff.extend_from_slice(&u16_bytes(42));
+    tiff.extend_from_slice(&u32_bytes(8));
+    tiff.extend_from_slice(&u16_bytes(count));
+    let mut entry = |tag: u16, kind: u16, count: u32, value: [u8; 4]| {
+        tiff.extend_from_slice(&u16_bytes(tag));
+        tiff.extend_from_slice(&u16_bytes(kind));
+        tiff.extend_from_slice(&u32_bytes(count));
+        tiff.extend_from_slice(&value);
+    };
+    let short = |v: u16| {
+        let [a, b] = u16_bytes(v);
+        [a, b, 0, 0]
+    };
+    entry(0x0100, 3, 1, short(8));
+    entry(0x010f, 2, 7, u32_bytes(make_offset));
+    if orientation {
+        entry(0x0112, 3, 1, short(1));
+    }
+    tiff.extend_from_slice(&u32_bytes(0));
+    tiff.extend_from_slice(b"Pentax\0");
+    tiff
+}
+
+/// A small encoded jpeg with the given APP1 contents
+fn jpeg_with_app1(segments: &[Vec<u8>]) -> Vec<u8> {
+    let pixels = pixels(3);
+    let options = JpegOptions::default();
+    let encoded = ImageJpeg
+        .encode(&pixels, WIDTH, HEIGHT, image::ColorType::Rgb8, &options)
+        .unwrap();
+    let mut jpeg = parse(&encoded);
+    for (i, contents) in segments.iter().enumerate() {
+        let segment = JpegSegment::new_with_contents(markers::APP1, contents.clone().into());
+        jpeg.segments_mut().insert(1 + i, segment);
+    }
+    let mut buffer = Vec::new();
+    jpeg.encoder().write_to(&mut buffer).unwrap();
+    buffer
+}
+
+fn exif_segment(tiff: &[u8]) -> Vec<u8> {
+    [b"Exif\0\0", tiff].concat()
+}
+
+/// The contents of every APP1 segment starting with `header`, without the header
+fn app1(jpeg: &[u8], header: &[u8]) -> Vec<Vec<u8>> {
+    parse(jpeg)
+        .segments()
+        .iter()
+        .filter(|segment| segment.marker() == markers::APP1)
+        .filter_map(|segment| segment.contents().strip_prefix(header).map(|c| c.to_vec()))
+        .collect()
+}
+
+/// Make still points at "Pentax" after the orientation was set
+fn check_make(tiff: &[u8]) {
+    let (kind, count, offset) = ifd0_entry(tiff, 0x010f).unwrap();
+    assert_eq!((kind, count), (2, 7));
+    assert_eq!(&tiff[offset as usize..offset as usize + 7], b"Pentax\0");
+}
+
+#[test]
+fn existing_orientation_is_patched_in_place() {
+    for little_endian in [true, false] {
+        let tiff = tiff(little_endian, true);
+        let jpeg = jpeg_with_app1(&[exif_segment(&tiff)]);
+        let jpeg = set_orientation(jpeg, Orientation::CW90, None).unwrap();
+        let exif = app1(&jpeg, b"Exif\0\0");
+        assert_eq!(exif.len(), 1);
+        let patched = &exif[0];
+        assert_eq!(patched.len(), tiff.len(), "little endian {little_endian}");
+        assert_eq!(tiff_orientation(patched), Some(6));
+        assert_eq!(ifd0_entry(patched, 0x0100), Some((3, 1, 8)));
+        // Only the value changed
+        let changed = tiff.iter().zip(patched).filter(|(a, b)| a != b).count();
+        assert_eq!(changed, 1);
+        check_make(patched);
+    }
+}
+
+#[test]
+fn missing_orientation_is_inserted() {
+    for little_endian in [true, false] {
+        let tiff = tiff(little_endian, false);
+        let jpeg = jpeg_with_app1(&[exif_segment(&tiff)]);
+        let jpeg = set_orientation(jpeg, Orientation::CW270, None).unwrap();
+        let patched = &app1(&jpeg, b"Exif\0\0")[0];
+        assert_eq!(
+            tiff_orientation(patched),
+            Some(8),
+            "little endian {little_endian}"
+        );
+        assert_eq!(ifd0_entry(patched, 0x0100), Some((3, 1, 8)));
+        check_make(patched);
+    }
+}
+
+#[test]
+fn missing_exif_is_created() {
+    let metadata = CameraMetadata {
+        make: "Pentax".into(),
+        ..Default::default()
+    };
+    // No EXIF, and one that can't be parsed
+    for segments in [vec![], vec![exif_segment(b"XX not a tiff")]] {
+        let jpeg = set_orientation(
+            jpeg_with_app1(&segments),
+            Orientation::CW180,
+            Some(&metadata),
+        );
+        let exif = app1(&jpeg.unwrap(), b"Exif\0\0");
+        assert_eq!(exif.len(), 1);
+        assert_eq!(tiff_orientation(&exif[0]), Some(3));
+        assert!(ifd0_entry(&exif[0], 0x010f).is_some());
+    }
+}
+
+#[test]
+fn xmp_orientation_is_rewritten() {
+    let xmp = concat!(
+        r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description "#,
+        r#"tiff:Make="Pentax" tiff:Orientation="1"/><rdf:Description>"#,
+        r#"<tiff:Orientation>1</tiff:Orientation></rdf:Description></rdf:RDF></x:xmpmeta>"#
+    );
+    let segments = [
+        exif_segment(&tiff(true, true)),
+        [XMP_HEADER, xmp.as_bytes()].concat(),
+    ];
+    let jpeg = set_orientation(jpeg_with_app1(&segments), Orientation::CW90, None).unwrap();
+    let patched = String::from_utf8(app1(&jpeg, XMP_HEADER).remove(0)).unwrap();
+    assert_eq!(
+        patched,
+        xmp.replace("\"1\"/>", "\"6\"/>").replace(">1<", ">6<")
+    );
+}
+
+#[test]
+fn copy_metadata_keeps_exif_xmp_and_icc() {
+    let xmp = [XMP_HEADER, b"<x:xmpmeta/>"].concat();
+    let source = jpeg_with_app1(&[exif_segment(&tiff(false, true)), xmp.clone()]);
+    let mut source = parse(&source);
+    source.set_icc_profile(Some(Bytes::from_static(ICC)));
+    let mut buffer = Vec::new();
+    source.encoder().write_to(&mut buffer).unwrap();
+
+    let target = jpeg_with_app1(&[exif_segment(&tiff(true, false))]);
+    let jpeg = copy_metadata(&buffer, target).unwrap();
+    assert_eq!(app1(&jpeg, b"Exif\0\0"), [tiff(false, true)]);
+    assert_eq!(app1(&jpeg, XMP_HEADER), [b"<x:xmpmeta/>".to_vec()]);
+    assert_eq!(parse(&jpeg).icc_profile().as_deref(), Some(ICC));
+    let decoded = image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (WIDTH, HEIGHT));
+}
@@ -0,0 +1,80 @@
+use crate::open_asset;
+use libraw_r::levels::BlackLevels;
+
+/// The xtrans layout of the X-Trans II / III sensors (0 red, 1 green, 2 blue)
+const XTRANS: [[u8; 6]; 6] = [
+    [1, 1, 0, 1, 1, 2],
+    [1, 1, 2, 1, 1, 0],
+    [2, 0, 1, 0, 2, 1],
+    [1, 1, 2, 1, 1, 0],
+    [1, 1, 0, 1, 1, 2],
+    [0, 2, 1, 2, 0, 1],
+];
+
+fn levels(filters: u32) -> BlackLevels {
+    BlackLevels {
+        black: 1000,
+        per_color: [10, 20, 30, 40],
+        pattern_size: (0, 0),
+        pattern: Vec::new(),
+        filters,
+        xtrans: XTRANS,
+    }
+}
+
+/// Every position of one period agrees with `at` and repeats after it
+fn check_period(black: &BlackLevels) {
+    let (cfa_rows, cfa_cols) = black.cfa_period();
+    let period = black.per_cfa_position();
+    let (rows, cols) = (period.len(), period[0].len());
+    assert_eq!((rows % cfa_rows, cols % cfa_cols), (0, 0));
+    for (row, line) in period.iter().enumerate() {
+        assert_eq!(line.len(), cols);
+        for (col, level) in line.iter().enumerate() {
+            assert_eq!(*level, black.at(row, col));
+            assert_eq!(black.at(row + rows, col + cols), black.at(row, col));
+        }
+    }
+}
+
+#[test]
+fn bayer_black_levels() {
+    // RGGB
+    let black = levels(0x94949494);
+    assert_eq!(black.cfa_period(), (2, 2));
+    assert_eq!(black.per_cfa_position(), [[1010, 1020], [1020, 1030]]);
+    check_period(&black);
+}
+
+#[test]
+fn xtrans_black_levels() {
+    let black = levels(9);
+    assert_eq!(black.cfa_period(), (6, 6));
+    for row in 0..12 {
+        for col in 0..12 {
+            let color = usize::from(XTRANS[row % 6][col % 6]);
+            assert_eq!(black.color(row, col), color);
+            assert_eq!(black.at(row, col), 1000 + black.per_color[color]);
+        }
+    }
+    check_period(&black);
+
+    // A 4x4 pattern on top of the 6x6 CFA repeats every 12 pixels
+    let black = BlackLevels {
+        pattern_size: (4, 4),
+        pattern: (0..16).collect(),
+        ..black
+    };
+    let period = black.per_cfa_position();
+    assert_eq!((period.len(), period[0].len()), (12, 12));
+    check_period(&black);
+}
+
+#[test]
+fn bayer_file_black_levels() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    p.unpack().expect("Failed to unpack");
+    let black = p.black_levels();
+    assert_eq!(black.cfa_period(), (2, 2));
+    check_period(&black);
+}
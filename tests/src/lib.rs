@@ -1,2 +1,41 @@
+#![cfg(test)]
+mod abi;
+#[cfg(feature = "avif")]
+mod avif;
+mod bayer;
+mod calibration;
+mod cameras;
+mod capabilities;
+#[cfg(feature = "cms")]
+mod cms;
+mod dng;
 mod exif;
+#[cfg(feature = "export")]
+mod export;
+mod focus;
+mod icc;
+#[cfg(feature = "jpeg")]
+mod jpeg;
+mod levels;
+mod metadata;
+mod orientation;
+mod pixelshift;
+#[cfg(feature = "jpeg")]
+mod preview;
+mod processed;
 mod progress;
+#[cfg(feature = "jpeg")]
+mod resize;
+mod stats;
+mod system;
+#[cfg(feature = "webp")]
+mod webp;
+mod white_balance;
+
+/// Open a file from the assets directory
+pub(crate) fn open_asset(name: &str) -> libraw_r::Processor {
+    let mut p = libraw_r::Processor::default();
+    p.open(format!("{}/assets/{name}", env!("CARGO_MANIFEST_DIR")))
+        .expect("Failed to open file");
+    p
+}
@@ -0,0 +1,34 @@
+/// libraw reads the date and time of the file with `mktime`, the EXIF written from it has to show
+/// the same wall clock in any time zone
+#[test]
+#[cfg(unix)]
+fn exif_datetime_is_camera_local() {
+    use libraw_r::metadata::exif_datetime;
+    // mktime reads the date as host local time like libraw does, whatever the host zone is
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = 2021 - 1900;
+    tm.tm_mon = 5;
+    tm.tm_mday = 15;
+    tm.tm_hour = 13;
+    tm.tm_min = 45;
+    tm.tm_sec = 30;
+    tm.tm_isdst = -1;
+    let timestamp = unsafe { libc::mktime(&mut tm) };
+    #[allow(clippy::useless_conversion)] // time_t is 32 bits on some targets
+    let timestamp = i64::from(timestamp);
+    assert_eq!(exif_datetime(timestamp), "2021:06:15 13:45:30");
+}
+
+#[test]
+fn offset_datetime() {
+    use libraw_r::metadata::offset_datetime;
+    let timestamp = 1623744930;
+    assert_eq!(offset_datetime(timestamp, 0), "2021:06:15 08:15:30");
+    assert_eq!(
+        offset_datetime(timestamp, 5 * 3600 + 1800),
+        "2021:06:15 13:45:30"
+    );
+    assert_eq!(offset_datetime(timestamp, -9 * 3600), "2021:06:14 23:15:30");
+    assert_eq!(offset_datetime(951782400, 0), "2000:02:29 00:00:00");
+    assert_eq!(offset_datetime(-1, 0), "1969:12:31 23:59:59");
+}
@@ -0,0 +1,152 @@
+use libraw_r::{Flip, Orientation};
+use proptest::prelude::*;
+
+type Matrix = [[i32; 2]; 2];
+
+/// Reference transform of every EXIF orientation on (x, y) with y pointing down
+fn matrix(orientation: u8) -> Matrix {
+    match orientation {
+        1 => [[1, 0], [0, 1]],
+        2 => [[-1, 0], [0, 1]],
+        3 => [[-1, 0], [0, -1]],
+        4 => [[1, 0], [0, -1]],
+        5 => [[0, 1], [1, 0]],
+        6 => [[0, -1], [1, 0]],
+        7 => [[0, -1], [-1, 0]],
+        8 => [[0, 1], [-1, 0]],
+        o => panic!("invalid orientation {o}"),
+    }
+}
+
+fn mul(a: Matrix, b: Matrix) -> Matrix {
+    let mut m = [[0; 2]; 2];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = a[i][0] * b[0][j] + a[i][1] * b[1][j];
+        }
+    }
+    m
+}
+
+fn from_matrix(m: Matrix) -> u8 {
+    (1..=8).find(|o| matrix(*o) == m).unwrap()
+}
+
+/// The source (row, col) of every pixel after applying the orientation with the reference
+/// matrices
+fn oriented(orientation: u8, width: usize, height: usize) -> Vec<Vec<(usize, usize)>> {
+    let m = matrix(orientation);
+    let (w, h) = match m[0][0] {
+        0 => (height, width),
+        _ => (width, height),
+    };
+    let mut out = vec![vec![(0, 0); w]; h];
+    for row in 0..height {
+        for col in 0..width {
+            // Centered coordinates doubled so they stay integers
+            let (x, y) = (
+                2 * col as i32 - (width as i32 - 1),
+                2 * row as i32 - (height as i32 - 1),
+            );
+            let (nx, ny) = (m[0][0] * x + m[0][1] * y, m[1][0] * x + m[1][1] * y);
+            let (ncol, nrow) = (
+                ((nx + w as i32 - 1) / 2) as usize,
+                ((ny + h as i32 - 1) / 2) as usize,
+            );
+            out[nrow][ncol] = (row, col);
+        }
+    }
+    out
+}
+
+/// The source (row, col) of every pixel after applying the flip like dcraw's flip_index
+fn flipped(flip: i32, width: usize, height: usize) -> Vec<Vec<(usize, usize)>> {
+    let (w, h) = match flip & 4 {
+        0 => (width, height),
+        _ => (height, width),
+    };
+    (0..h)
+        .map(|row| {
+            (0..w)
+                .map(|col| {
+                    let (mut row, mut col) = (row, col);
+                    if flip & 4 != 0 {
+                        std::mem::swap(&mut row, &mut col);
+                    }
+                    if flip & 2 != 0 {
+                        row = height - 1 - row;
+                    }
+                    if flip & 1 != 0 {
+                        col = width - 1 - col;
+                    }
+                    (row, col)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn composition(a in 1u8..=8, b in 1u8..=8) {
+        let composed = Orientation(a) + Orientation(b);
+        prop_assert_eq!(composed.0, from_matrix(mul(matrix(a), matrix(b))));
+    }
+
+    #[test]
+    fn associativity(a in 1u8..=8, b in 1u8..=8, c in 1u8..=8) {
+        let (a, b, c) = (Orientation(a), Orientation(b), Orientation(c));
+        prop_assert_eq!((a + b) + c, a + (b + c));
+    }
+
+    #[test]
+    fn inverse(o in 1u8..=8) {
+        let o = Orientation(o);
+        prop_assert_eq!(o + -o, Orientation::NONE);
+        prop_assert_eq!(-o + o, Orientation::NONE);
+        let m = matrix(o.0);
+        prop_assert_eq!((-o).0, from_matrix([[m[0][0], m[1][0]], [m[0][1], m[1][1]]]));
+    }
+
+    #[test]
+    fn flip_round_trip(flip in 0i32..8) {
+        let orientation = Orientation::from(Flip(flip));
+        prop_assert_eq!(Flip::from(orientation), Flip(flip));
+        prop_assert_eq!(Orientation::from_tflip(flip as u16), Some(orientation));
+        prop_assert_eq!(Orientation::try_from(u16::from(orientation)), Ok(orientation));
+    }
+
+    #[test]
+    fn flip_matches_pixels(flip in 0i32..8, width in 1usize..6, height in 1usize..6) {
+        let orientation = Orientation::from(Flip(flip));
+        prop_assert_eq!(
+            oriented(orientation.0, width, height),
+            flipped(flip, width, height)
+        );
+        prop_assert_eq!(orientation.swaps_dimensions(), flip & 4 != 0);
+    }
+
+    #[test]
+    fn pixels_match_reference(o in 1u8..=8, width in 1usize..6, height in 1usize..6) {
+        // Two channels per pixel holding the source row and column
+        let pixels = (0..height)
+            .flat_map(|row| (0..width).flat_map(move |col| [row, col]))
+            .collect::<Vec<_>>();
+        let (out, w, h) = Orientation(o).apply_to_pixels(&pixels, width, height, 2);
+        let expected = oriented(o, width, height);
+        prop_assert_eq!((w, h), (expected[0].len(), expected.len()));
+        let expected = expected
+            .into_iter()
+            .flatten()
+            .flat_map(|(row, col)| [row, col])
+            .collect::<Vec<_>>();
+        prop_assert_eq!(out, expected);
+    }
+}
+
+#[test]
+fn unknown_tflip() {
+    assert_eq!(Orientation::from_tflip(0xffff), None);
+    assert_eq!(Orientation::new(0), None);
+    assert!(Orientation::TRANSVERSE.is_mirrored());
+}
@@ -0,0 +1,181 @@
+use libraw_r::pixelshift::{
+    merge_file, merge_files, merge_frames, merge_rgbg, PixelShiftFrame, PixelShiftOptions,
+};
+use libraw_r::LibrawError;
+
+/// RGGB in libraw's `filters` format
+const RGGB: u32 = 0x94949494;
+const SIZE: usize = 4;
+
+/// Color of the bayer pattern at (row, col), 0 red, 1 green, 2 blue
+fn color(row: usize, col: usize) -> usize {
+    match (row % 2, col % 2) {
+        (0, 0) => 0,
+        (1, 1) => 2,
+        _ => 1,
+    }
+}
+
+/// The scene value of a channel at a pixel
+fn scene(channel: usize, idx: usize) -> u16 {
+    (channel as u16 + 1) * 1000 + idx as u16
+}
+
+/// A frame taken with the sensor shifted by (dr, dc), the sensor pixel at (row, col) sees the
+/// scene at (row - dr, col - dc) through its own color filter
+fn frame_data((dr, dc): (usize, usize)) -> Vec<u16> {
+    (0..SIZE * SIZE)
+        .map(|idx| {
+            let (row, col) = (idx / SIZE, idx % SIZE);
+            let scene_idx = (row + SIZE - dr) % SIZE * SIZE + (col + SIZE - dc) % SIZE;
+            scene(color(row, col), scene_idx)
+        })
+        .collect()
+}
+
+/// The 4 frames a sensor shifted by the default offsets records of the scene
+fn frames(options: &PixelShiftOptions) -> Vec<PixelShiftFrame> {
+    options
+        .offsets
+        .iter()
+        .map(|&offset| PixelShiftFrame::new(SIZE, SIZE, RGGB, frame_data(offset)).unwrap())
+        .collect()
+}
+
+/// Away from the far edges every pixel is the scene, at the edges the frames are read one bayer
+/// period back so the channels still come from the right colors
+fn check_scene(rgb: &[u16], row: usize, col: usize) {
+    if row < SIZE - 1 && col < SIZE - 1 {
+        assert_eq!(
+            rgb,
+            [0, 1, 2].map(|c| scene(c, row * SIZE + col)),
+            "{row} {col}"
+        );
+    }
+    let channels = rgb.iter().map(|v| v / 1000).collect::<Vec<_>>();
+    assert_eq!(channels, [1, 2, 3], "{row} {col}");
+}
+
+#[test]
+fn single_shot_is_rejected() {
+    let file = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/RAW_NIKON_D3X.NEF");
+    let e = merge_file(file, &PixelShiftOptions::default()).unwrap_err();
+    assert!(matches!(e, LibrawError::InvalidShotCount(1)), "{e}");
+}
+
+#[test]
+fn merge_separate_files() {
+    let file = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/RAW_NIKON_D3X.NEF");
+    let options = PixelShiftOptions::default();
+    let image = merge_files(&[file; 4], &options).unwrap();
+    let mut p = libraw_r::Processor::default();
+    p.open(file).unwrap();
+    let sizes = p.sizes();
+    assert_eq!(
+        (image.width(), image.height()),
+        (u32::from(sizes.width), u32::from(sizes.height))
+    );
+    assert!(matches!(
+        merge_files(&[file; 2], &options),
+        Err(LibrawError::InvalidShotCount(2))
+    ));
+}
+
+#[test]
+fn merge_rgbg_pixels() {
+    let pixels = [[100, 200, 300, 400], [10, 20, 30, 20], [0, 1000, 0, 0]];
+    let image = merge_rgbg(3, 1, &pixels, &PixelShiftOptions::default()).unwrap();
+    assert_eq!((image.width(), image.height()), (3, 1));
+    assert_eq!(image.as_slice_u16(), [100, 300, 300, 10, 20, 30, 0, 500, 0]);
+    assert!(image.motion_mask().is_none());
+
+    let options = PixelShiftOptions {
+        motion_threshold: Some(0.2),
+        ..Default::default()
+    };
+    let image = merge_rgbg(3, 1, &pixels, &options).unwrap();
+    assert_eq!(image.motion_mask().unwrap(), [true, false, true]);
+    // The frames are already combined, moving pixels keep their merged value
+    assert_eq!(image.as_slice_u16()[..3], [100, 300, 300]);
+
+    assert!(matches!(
+        merge_rgbg(2, 2, &pixels, &options),
+        Err(LibrawError::UnsupportedPixelShift)
+    ));
+}
+
+#[test]
+fn merge_four_frames() {
+    let options = PixelShiftOptions::default();
+    let image = merge_frames(&frames(&options), &options).unwrap();
+    assert_eq!((image.width(), image.height()), (SIZE as u32, SIZE as u32));
+    assert!(image.motion_mask().is_none());
+    for (idx, rgb) in image.as_slice_u16().chunks_exact(3).enumerate() {
+        check_scene(rgb, idx / SIZE, idx % SIZE);
+    }
+}
+
+#[test]
+fn motion_falls_back_to_the_first_frame() {
+    let options = PixelShiftOptions {
+        motion_threshold: Some(0.2),
+        ..Default::default()
+    };
+    let mut frames = frames(&options);
+    // The second frame sees (0, 0) with the green pixel at (0, 1), make it disagree with the
+    // fourth
+    let mut data = frame_data(options.offsets[1]);
+    data[1] = 0;
+    frames[1] = PixelShiftFrame::new(SIZE, SIZE, RGGB, data).unwrap();
+
+    let image = merge_frames(&frames, &options).unwrap();
+    let mask = image.motion_mask().unwrap();
+    assert!(mask[0]);
+    assert_eq!(mask.iter().filter(|moving| **moving).count(), 1);
+    // The 2x2 block of the first frame: red at (0, 0), greens at (0, 1) and (1, 0), blue at (1, 1)
+    let green = (scene(1, 1) + scene(1, SIZE)) / 2;
+    assert_eq!(
+        image.as_slice_u16()[..3],
+        [scene(0, 0), green, scene(2, SIZE + 1)]
+    );
+}
+
+#[test]
+fn sixteen_frames_double_the_resolution() {
+    let options = PixelShiftOptions::default();
+    let frames = frames(&options)
+        .iter()
+        .cycle()
+        .take(16)
+        .cloned()
+        .collect::<Vec<_>>();
+    let image = merge_frames(&frames, &options).unwrap();
+    assert_eq!(
+        (image.width(), image.height()),
+        (2 * SIZE as u32, 2 * SIZE as u32)
+    );
+    let pixels = image.as_slice_u16();
+    for &(dr, dc) in options.group_offsets.iter() {
+        for idx in 0..SIZE * SIZE {
+            let (row, col) = (idx / SIZE * 2 + dr, idx % SIZE * 2 + dc);
+            let at = (row * 2 * SIZE + col) * 3;
+            check_scene(&pixels[at..at + 3], idx / SIZE, idx % SIZE);
+        }
+    }
+}
+
+#[test]
+fn invalid_frames() {
+    let options = PixelShiftOptions::default();
+    let frames = frames(&options);
+    assert!(matches!(
+        merge_frames(&frames[..3], &options),
+        Err(LibrawError::InvalidShotCount(3))
+    ));
+    assert!(matches!(
+        PixelShiftFrame::new(SIZE, SIZE, 9, vec![0; SIZE * SIZE]),
+        Err(LibrawError::UnsupportedCfa)
+    ));
+    assert!(PixelShiftFrame::new(SIZE, SIZE, RGGB, vec![0; 3]).is_err());
+    assert!(PixelShiftFrame::new(1, SIZE, RGGB, vec![0; SIZE]).is_err());
+}
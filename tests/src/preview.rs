@@ -0,0 +1,80 @@
+use libraw_r::preview::{PreviewSource, PreviewStrategy};
+use libraw_r::resize::ResizeSpec;
+
+const WIDTH: u32 = 6000;
+const HEIGHT: u32 = 4000;
+/// A tiny thumbnail, a bitmap thumbnail and a 1600x1200 preview
+const EMBEDDED: [Option<(u32, u32)>; 3] = [Some((160, 120)), None, Some((1600, 1200))];
+
+fn plan(strategy: PreviewStrategy) -> Vec<PreviewSource> {
+    strategy.plan(WIDTH, HEIGHT, &EMBEDDED)
+}
+
+#[test]
+fn resize_smaller_than_the_embedded_preview() {
+    let strategy = PreviewStrategy::new(100_000).with_resize(ResizeSpec::fit(1000, 1000));
+    // Half size is 3000x2000, binned once to 1500x1000 which still fits 1000x666
+    assert_eq!(
+        plan(strategy),
+        [
+            PreviewSource::Embedded(2),
+            PreviewSource::HalfSize { binned: 1 },
+            PreviewSource::Full
+        ]
+    );
+}
+
+#[test]
+fn resize_larger_than_the_embedded_preview() {
+    let strategy = PreviewStrategy::new(100_000).with_resize(ResizeSpec::fit(2000, 2000));
+    assert_eq!(
+        plan(strategy),
+        [PreviewSource::HalfSize { binned: 0 }, PreviewSource::Full]
+    );
+
+    let strategy = PreviewStrategy::new(100_000).with_resize(ResizeSpec::fit(4000, 4000));
+    assert_eq!(plan(strategy), [PreviewSource::Full]);
+
+    // Without upscaling the embedded preview is good enough again
+    let resize = ResizeSpec::fit(4000, 4000).with_upscale(false);
+    let strategy = PreviewStrategy::new(100_000).with_resize(resize);
+    assert_eq!(plan(strategy)[0], PreviewSource::Embedded(2));
+}
+
+#[test]
+fn min_pixels() {
+    // The smallest big enough thumbnail
+    assert_eq!(
+        plan(PreviewStrategy::new(10_000))[0],
+        PreviewSource::Embedded(0)
+    );
+    assert_eq!(
+        plan(PreviewStrategy::new(20_000))[0],
+        PreviewSource::Embedded(2)
+    );
+    // 3000x2000 can't be binned below 2 MP, for 0.3 MP it is binned down to 750x500
+    assert_eq!(
+        plan(PreviewStrategy::default()),
+        [PreviewSource::HalfSize { binned: 0 }, PreviewSource::Full]
+    );
+    assert_eq!(
+        plan(PreviewStrategy::new(300_000))[1],
+        PreviewSource::HalfSize { binned: 2 }
+    );
+    assert_eq!(plan(PreviewStrategy::new(7_000_000)), [PreviewSource::Full]);
+}
+
+#[test]
+fn disabled_paths() {
+    let strategy = PreviewStrategy {
+        embedded: false,
+        ..PreviewStrategy::new(100_000)
+    };
+    assert_eq!(plan(strategy)[0], PreviewSource::HalfSize { binned: 2 });
+    let strategy = PreviewStrategy {
+        embedded: false,
+        half_size: false,
+        ..PreviewStrategy::new(100_000)
+    };
+    assert_eq!(plan(strategy), [PreviewSource::Full]);
+}
@@ -0,0 +1,82 @@
+use libraw_r::{LibrawError, ProcessedImage};
+
+fn bitmap_u8(width: u16, height: u16, colors: u16) -> (ProcessedImage, Vec<u8>) {
+    let len = usize::from(width) * usize::from(height) * usize::from(colors);
+    let data = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+    let image = ProcessedImage::from_bitmap(width, height, colors, &data).unwrap();
+    (image, data)
+}
+
+fn bitmap_u16(width: u16, height: u16, colors: u16) -> (ProcessedImage, Vec<u16>) {
+    let len = usize::from(width) * usize::from(height) * usize::from(colors);
+    let data = (0..len).map(|i| i as u16 * 1000).collect::<Vec<_>>();
+    let image = ProcessedImage::from_bitmap(width, height, colors, &data).unwrap();
+    (image, data)
+}
+
+#[test]
+fn into_owned_vec() {
+    let (image, data) = bitmap_u8(5, 3, 3);
+    assert_eq!(image.into_owned_vec::<u8>().unwrap(), data);
+    let (image, data) = bitmap_u16(5, 3, 1);
+    assert_eq!(image.into_owned_vec::<u16>().unwrap(), data);
+}
+
+#[test]
+fn into_owned_vec_checks_the_bits() {
+    let (image, _) = bitmap_u8(5, 3, 3);
+    assert!(matches!(
+        image.into_owned_vec::<u16>(),
+        Err(LibrawError::InvalidColor(8))
+    ));
+    let (image, _) = bitmap_u16(5, 3, 3);
+    assert!(matches!(
+        image.into_owned_vec::<u8>(),
+        Err(LibrawError::InvalidColor(16))
+    ));
+}
+
+#[test]
+#[cfg(feature = "jpeg")]
+fn dynamic_image() {
+    use image::{ColorType, DynamicImage};
+    for colors in [1, 3] {
+        let (image, data) = bitmap_u8(5, 3, colors);
+        let dynimg = DynamicImage::try_from(image).unwrap();
+        let color = [ColorType::L8, ColorType::Rgb8][usize::from(colors / 2)];
+        assert_eq!(dynimg.color(), color);
+        assert_eq!((dynimg.width(), dynimg.height()), (5, 3));
+        assert_eq!(dynimg.as_bytes(), data);
+
+        let (image, data) = bitmap_u16(5, 3, colors);
+        let dynimg = DynamicImage::try_from(image).unwrap();
+        let color = [ColorType::L16, ColorType::Rgb16][usize::from(colors / 2)];
+        assert_eq!(dynimg.color(), color);
+        assert_eq!((dynimg.width(), dynimg.height()), (5, 3));
+        let pixels = match colors {
+            1 => dynimg.into_luma16().into_raw(),
+            _ => dynimg.into_rgb16().into_raw(),
+        };
+        assert_eq!(pixels, data);
+    }
+}
+
+#[test]
+#[cfg(feature = "ndarray")]
+fn array_view() {
+    let (image, data) = bitmap_u16(5, 3, 3);
+    let view = image.as_array_view::<u16>().unwrap();
+    assert_eq!(view.shape(), [3, 5, 3]);
+    assert_eq!(view.strides(), [15, 3, 1]);
+    // Row 2, column 4, blue
+    assert_eq!(view[[2, 4, 2]], data[2 * 15 + 4 * 3 + 2]);
+    assert!(matches!(
+        image.as_array_view::<u8>(),
+        Err(LibrawError::InvalidColor(16))
+    ));
+
+    let (image, _) = bitmap_u8(4, 2, 1);
+    let view = image.as_array_view::<u8>().unwrap();
+    assert_eq!(view.shape(), [2, 4, 1]);
+    assert_eq!(view.strides(), [4, 1, 1]);
+}
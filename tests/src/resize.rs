@@ -0,0 +1,86 @@
+use image::ColorType;
+use libraw_r::resize::{bin_2x2, ResizeMode, ResizeSpec};
+use libraw_r::Resolution;
+
+#[test]
+fn fit_keeps_aspect_ratio() {
+    assert_eq!(ResizeSpec::fit(300, 300).dimensions(600, 400), (300, 200));
+    assert_eq!(ResizeSpec::fit(300, 100).dimensions(600, 400), (150, 100));
+}
+
+#[test]
+fn fill_and_exact_use_the_target() {
+    assert_eq!(ResizeSpec::fill(300, 300).dimensions(600, 400), (300, 300));
+    assert_eq!(ResizeSpec::exact(100, 50).dimensions(600, 400), (100, 50));
+}
+
+#[test]
+fn edges() {
+    assert_eq!(ResizeSpec::long_edge(300).dimensions(400, 600), (200, 300));
+    assert_eq!(ResizeSpec::short_edge(300).dimensions(400, 600), (300, 450));
+}
+
+#[test]
+fn upscale() {
+    assert_eq!(ResizeSpec::fit(800, 800).dimensions(400, 200), (800, 400));
+    let spec = ResizeSpec::fit(800, 800).with_upscale(false);
+    assert_eq!(spec.dimensions(400, 200), (400, 200));
+    let spec = ResizeSpec::long_edge(800).with_upscale(false);
+    assert_eq!(spec.dimensions(400, 200), (400, 200));
+    let spec = ResizeSpec::new(ResizeMode::Fill, 300, 300).with_upscale(false);
+    assert_eq!(spec.dimensions(400, 200), (300, 200));
+}
+
+#[test]
+fn resolution_never_upscales() {
+    let spec = ResizeSpec::from(Resolution::new(800, 800));
+    assert_eq!(spec.dimensions(400, 200), (400, 200));
+    assert_eq!(spec.dimensions(1600, 1200), (800, 600));
+}
+
+#[test]
+fn dimensions_are_at_least_one() {
+    assert_eq!(ResizeSpec::fit(10, 10).dimensions(10000, 10), (10, 1));
+    assert_eq!(ResizeSpec::exact(0, 0).dimensions(10, 10), (1, 1));
+}
+
+#[test]
+fn transposed() {
+    let spec = ResizeSpec::fit(300, 200).transposed();
+    assert_eq!((spec.width, spec.height), (200, 300));
+}
+
+#[test]
+fn bin_2x2_averages_blocks() {
+    #[rustfmt::skip]
+    let pixels = [
+        0, 4, 8, 1, 9,
+        4, 8, 8, 3, 9,
+        7, 7, 7, 7, 7,
+    ];
+    let (out, width, height) = bin_2x2(&pixels, 5, 3, ColorType::L8).unwrap();
+    assert_eq!((width, height), (2, 1));
+    assert_eq!(out, [4, 5]);
+}
+
+#[test]
+fn bin_2x2_16_bit_rgb() {
+    let pixels = [1000_u16, 0, 65535]
+        .iter()
+        .cycle()
+        .take(2 * 2 * 3)
+        .flat_map(|v| v.to_ne_bytes())
+        .collect::<Vec<_>>();
+    let (out, width, height) = bin_2x2(&pixels, 2, 2, ColorType::Rgb16).unwrap();
+    assert_eq!((width, height), (1, 1));
+    let out = out
+        .chunks_exact(2)
+        .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+        .collect::<Vec<_>>();
+    assert_eq!(out, [1000, 0, 65535]);
+}
+
+#[test]
+fn bin_2x2_rejects_wrong_length() {
+    assert!(bin_2x2(&[0; 5], 2, 2, ColorType::L8).is_err());
+}
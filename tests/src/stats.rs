@@ -0,0 +1,179 @@
+use crate::open_asset;
+use libraw_r::levels::BlackLevels;
+use libraw_r::stats::{RawStats, StatsOptions};
+use libraw_r::Processor;
+
+/// Pixels per channel relative to the whole visible area
+fn shares(p: &Processor) -> [f64; 4] {
+    let stats = p.raw_stats(&StatsOptions::default()).unwrap();
+    let total: u64 = stats.counts.iter().sum();
+    assert_eq!(
+        total,
+        u64::from(p.sizes().width) * u64::from(p.sizes().height)
+    );
+    stats.counts.map(|count| count as f64 / total as f64)
+}
+
+#[test]
+fn bayer_stats() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    p.unpack().expect("Failed to unpack");
+    let shares = shares(&p);
+    for share in shares {
+        assert!((share - 0.25).abs() < 0.01, "{shares:?}");
+    }
+}
+
+const GREEN: [bool; 4] = [false, true, false, true];
+
+/// RGGB with libraw's second green as channel 3
+fn rggb(row: usize, col: usize) -> usize {
+    [[0, 1], [3, 2]][row % 2][col % 2]
+}
+
+/// The xtrans layout of the X-Trans II / III sensors (0 red, 1 green, 2 blue)
+const XTRANS: [[u8; 6]; 6] = [
+    [1, 1, 0, 1, 1, 2],
+    [1, 1, 2, 1, 1, 0],
+    [2, 0, 1, 0, 2, 1],
+    [1, 1, 2, 1, 1, 0],
+    [1, 1, 0, 1, 1, 2],
+    [0, 2, 1, 2, 0, 1],
+];
+
+/// Deterministic values in -0.1..1.1 so both ends of the histogram get hit
+fn value(row: usize, col: usize) -> f64 {
+    let hash = (row * 7919 + col * 104729) % 1201;
+    hash as f64 / 1000.0 - 0.1
+}
+
+fn by_hand(histograms: [Vec<u64>; 4], log_average_luminance: f64) -> RawStats {
+    let counts = [0, 1, 2, 3].map(|c| histograms[c].iter().sum());
+    RawStats {
+        histograms,
+        counts,
+        clipped: [0; 4],
+        means: [0.0; 4],
+        green: GREEN,
+        log_average_luminance,
+    }
+}
+
+#[test]
+fn stats_of_a_mosaic() {
+    let options = StatsOptions {
+        bins: 5,
+        clip_level: 0.9,
+    };
+    // Red 0, greens 0.5, blue 1
+    let values = [0.0, 0.5, 1.0, 0.5];
+    let stats = RawStats::from_pixels(4, 2, GREEN, &options, |row, col| {
+        let c = rggb(row, col);
+        (c, values[c])
+    });
+    assert_eq!(stats.counts, [2; 4]);
+    assert_eq!(stats.clipped, [0, 0, 2, 0]);
+    assert_eq!(stats.means, values);
+    assert_eq!(stats.histograms[0], [2, 0, 0, 0, 0]);
+    assert_eq!(stats.histograms[1], [0, 0, 2, 0, 0]);
+    assert_eq!(stats.histograms[2], [0, 0, 0, 0, 2]);
+    assert!((stats.log_average_luminance - 0.5).abs() < 1e-12);
+    assert_eq!(stats.median_luminance(), 0.5);
+    assert_eq!(stats.clipped_fraction(2), 1.0);
+}
+
+/// The channels come from the same CFA lookup `raw_stats` uses for the black level
+#[test]
+fn stats_of_an_xtrans_mosaic() {
+    let black = BlackLevels {
+        black: 0,
+        per_color: [0; 4],
+        pattern_size: (0, 0),
+        pattern: Vec::new(),
+        filters: 9,
+        xtrans: XTRANS,
+    };
+    let options = StatsOptions {
+        bins: 5,
+        clip_level: 0.9,
+    };
+    let values = [1.0, 0.5, 0.0, 0.0];
+    let stats = RawStats::from_pixels(12, 18, [false, true, false, false], &options, |row, col| {
+        let c = black.color(row, col);
+        (c, values[c])
+    });
+    // 8 red, 20 green and 8 blue pixels in every 6x6 block
+    assert_eq!(stats.counts, [48, 120, 48, 0]);
+    assert_eq!(stats.clipped, [48, 0, 0, 0]);
+    assert_eq!(stats.histograms[1], [0, 0, 120, 0, 0]);
+    assert!((stats.log_average_luminance - 0.5).abs() < 1e-12);
+    assert_eq!(stats.median_luminance(), 0.5);
+}
+
+/// Run with and without `--features rayon`, both have to match the sequential reference
+#[test]
+fn stats_match_a_sequential_reference() {
+    let (width, height) = (97, 61);
+    let options = StatsOptions::default();
+    let stats = RawStats::from_pixels(width, height, GREEN, &options, |row, col| {
+        (rggb(row, col), value(row, col))
+    });
+
+    let mut histograms = [(); 4].map(|_| vec![0; options.bins]);
+    let (mut counts, mut clipped, mut sums) = ([0; 4], [0; 4], [0.0; 4]);
+    let (mut log_sum, mut log_count) = (0.0, 0);
+    for row in 0..height {
+        for col in 0..width {
+            let (c, v) = (rggb(row, col), value(row, col));
+            let bin = (v.clamp(0.0, 1.0) * (options.bins - 1) as f64).round() as usize;
+            histograms[c][bin] += 1;
+            counts[c] += 1;
+            clipped[c] += u64::from(v >= options.clip_level);
+            sums[c] += v;
+            if GREEN[c] {
+                log_sum += v.max(1.0 / 65536.0).ln();
+                log_count += 1;
+            }
+        }
+    }
+    assert_eq!(stats.histograms, histograms);
+    assert_eq!(stats.counts, counts);
+    assert_eq!(stats.clipped, clipped);
+    for c in 0..4 {
+        assert!((stats.means[c] - sums[c] / counts[c] as f64).abs() < 1e-12);
+    }
+    let log_average = (log_sum / log_count as f64).exp();
+    assert!((stats.log_average_luminance - log_average).abs() < 1e-12);
+}
+
+#[test]
+fn percentile() {
+    let stats = by_hand([vec![0, 1, 1, 1, 1], vec![], vec![], vec![]], 0.0);
+    assert_eq!(stats.percentile(0, 0.0), 0.25);
+    assert_eq!(stats.percentile(0, 0.5), 0.5);
+    assert_eq!(stats.percentile(0, 0.51), 0.75);
+    assert_eq!(stats.percentile(0, 1.0), 1.0);
+    // Empty channels have no percentiles
+    assert_eq!(stats.percentile(1, 0.5), 0.0);
+}
+
+#[test]
+fn headroom_ev() {
+    let half = || vec![0, 0, 10, 0, 0];
+    let stats = by_hand([half(), half(), half(), half()], 0.0);
+    assert!((stats.headroom_ev() - 1.0).abs() < 1e-12);
+
+    let clipped = by_hand([half(), vec![0, 0, 0, 0, 10], half(), half()], 0.0);
+    assert_eq!(clipped.headroom_ev(), 0.0);
+
+    let empty = by_hand([vec![0; 5], vec![], vec![], vec![]], 0.0);
+    assert_eq!(empty.headroom_ev(), f64::INFINITY);
+}
+
+#[test]
+fn exposure_ev() {
+    let ev = |average| by_hand([vec![], vec![], vec![], vec![]], average).exposure_ev();
+    assert!(ev(0.18).abs() < 1e-12);
+    assert!((ev(0.36) - 1.0).abs() < 1e-12);
+    assert!((ev(0.09) + 1.0).abs() < 1e-12);
+}
@@ -0,0 +1,37 @@
+//! Checks for a LibRaw linked through pkg-config, run with
+//! `LIBRAW_SYS_USE_PKG_CONFIG=1 cargo test -p tests system` or `--features system`
+use std::process::Command;
+
+fn uses_pkg_config() -> bool {
+    cfg!(feature = "system") || option_env!("LIBRAW_SYS_USE_PKG_CONFIG") == Some("1")
+}
+
+/// The version of the package libraw-sys links, libraw_r.pc is preferred like in the build
+fn pkg_config_version() -> Option<String> {
+    ["libraw_r", "libraw"].iter().find_map(|name| {
+        let output = Command::new("pkg-config")
+            .args(["--modversion", name])
+            .output()
+            .ok()?;
+        match output.status.success() {
+            true => Some(String::from_utf8_lossy(&output.stdout).trim().to_owned()),
+            false => None,
+        }
+    })
+}
+
+#[test]
+fn links_the_pkg_config_libraw() {
+    if !uses_pkg_config() {
+        return;
+    }
+    let version = pkg_config_version().expect("pkg-config doesn't find LibRaw");
+    let linked = libraw_r::runtime_version();
+    assert_eq!(
+        format!("{}.{}.{}", linked.major, linked.minor, linked.patch),
+        version,
+        "{}",
+        libraw_r::runtime_version_string()
+    );
+    libraw_r::verify_abi().unwrap();
+}
@@ -0,0 +1,33 @@
+use crate::open_asset;
+use img_parts::webp::{WebP, CHUNK_EXIF, CHUNK_ICCP, CHUNK_VP8X};
+use img_parts::Bytes;
+
+const ICC_FLAG: u8 = 0x20;
+const EXIF_FLAG: u8 = 0x08;
+
+fn vp8x_flags(webp: &WebP) -> u8 {
+    let chunk = webp.chunk_by_id(CHUNK_VP8X).expect("Missing VP8X chunk");
+    chunk.content().data().expect("VP8X isn't a data chunk")[0]
+}
+
+#[test]
+fn processed_webp_flags_icc_and_exif() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    let webp = WebP::from_bytes(Bytes::from(p.to_webp(80).unwrap())).unwrap();
+    assert!(webp.has_chunk(CHUNK_ICCP));
+    assert!(webp.has_chunk(CHUNK_EXIF));
+    assert_eq!(
+        vp8x_flags(&webp) & (ICC_FLAG | EXIF_FLAG),
+        ICC_FLAG | EXIF_FLAG
+    );
+}
+
+#[test]
+fn thumbnail_webp_flags_match_chunks() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    let webp = WebP::from_bytes(Bytes::from(p.get_webp(80).unwrap())).unwrap();
+    let flags = vp8x_flags(&webp);
+    assert!(webp.has_chunk(CHUNK_EXIF));
+    assert_ne!(flags & EXIF_FLAG, 0);
+    assert_eq!(flags & ICC_FLAG != 0, webp.has_chunk(CHUNK_ICCP));
+}
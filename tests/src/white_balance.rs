@@ -0,0 +1,103 @@
+use crate::open_asset;
+use libraw_r::white_balance::{
+    temperature_tint_to_xy, xy_to_temperature_tint, WhiteBalance, WhiteBalancePreset,
+};
+
+#[test]
+fn d65_temperature() {
+    let (temperature, tint) = xy_to_temperature_tint((0.3127, 0.3290));
+    assert!((temperature - 6504.0).abs() < 50.0, "{temperature}");
+    // D65 is slightly green of the Planckian locus
+    assert!(tint < 0.0 && tint > -15.0, "{tint}");
+}
+
+#[test]
+fn temperature_tint_round_trip() {
+    for temperature in [2500.0, 4000.0, 5500.0, 7500.0, 12000.0] {
+        for tint in [-50.0, 0.0, 50.0] {
+            let (t, n) = xy_to_temperature_tint(temperature_tint_to_xy(temperature, tint));
+            assert!(
+                (t - temperature).abs() < temperature * 0.005,
+                "{temperature} {t}"
+            );
+            assert!((n - tint).abs() < 1.0, "{tint} {n}");
+        }
+    }
+}
+
+#[test]
+fn kelvin_multipliers() {
+    let p = open_asset("RAW_NIKON_D3X.NEF");
+    let warm = p.kelvin_multipliers(3000.0, 0.0).unwrap();
+    let cool = p.kelvin_multipliers(7500.0, 0.0).unwrap();
+    assert_eq!(warm[1], 1.0);
+    assert_eq!(warm[3], warm[1]);
+    // Tungsten light needs less red and more blue gain than shade
+    assert!(warm[0] < cool[0]);
+    assert!(warm[2] > cool[2]);
+
+    let neutral = warm[..3]
+        .iter()
+        .map(|v| 1.0 / *v as f64)
+        .collect::<Vec<_>>();
+    let (temperature, _) = p
+        .color_calibration()
+        .temperature_tint([neutral[0], neutral[1], neutral[2]])
+        .unwrap();
+    assert!((temperature - 3000.0).abs() < 50.0, "{temperature}");
+}
+
+#[test]
+fn kelvin_white_balance_sets_user_mul() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    let expected = p.kelvin_multipliers(5000.0, 10.0).unwrap();
+    p.set_white_balance(WhiteBalance::Kelvin {
+        temp: 5000.0,
+        tint: 10.0,
+    })
+    .unwrap();
+    assert_eq!(p.params().user_mul, expected);
+    assert_eq!(p.params().use_camera_wb, 0);
+    let applied = p.applied_white_balance().unwrap();
+    for c in 0..3 {
+        assert!((applied[c] - expected[c] as f64).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn presets_are_normalized_to_green() {
+    let p = open_asset("RAW_NIKON_D3X.NEF");
+    let presets = p.white_balance_presets();
+    assert!(!presets.is_empty());
+    for WhiteBalancePreset { multipliers, .. } in presets {
+        assert_eq!(multipliers[1], 1.0);
+        assert!(multipliers.iter().all(|v| *v > 0.0));
+    }
+}
+
+#[test]
+fn daylight_uses_the_camera_preset() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    p.set_white_balance(WhiteBalance::Daylight).unwrap();
+    let preset = p
+        .white_balance_presets()
+        .into_iter()
+        .find(|preset| preset.name == "Daylight");
+    match preset {
+        Some(preset) => assert_eq!(p.params().user_mul, preset.multipliers),
+        None => assert_eq!(p.params().user_mul, [0.0; 4]),
+    }
+}
+
+#[test]
+fn as_shot_and_user_multipliers() {
+    let mut p = open_asset("RAW_NIKON_D3X.NEF");
+    p.set_white_balance(WhiteBalance::AsShot).unwrap();
+    assert_eq!(p.params().use_camera_wb, 1);
+    assert_eq!(p.params().user_mul, [0.0; 4]);
+
+    p.set_white_balance(WhiteBalance::Multipliers([2.0, 1.0, 1.5, 1.0]))
+        .unwrap();
+    assert_eq!(p.params().use_camera_wb, 0);
+    assert_eq!(p.applied_white_balance(), Some([2.0, 1.0, 1.5]));
+}